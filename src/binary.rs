@@ -0,0 +1,239 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::{IksError, IksNode, IksType, Result};
+
+/// Bytes identifying the binary format
+const MAGIC: &[u8; 4] = b"IKSB";
+/// Version of the encoding written by `encode`; version 1, which lacks
+/// valueless attributes, is still read
+const VERSION: u8 = 2;
+
+/// Flag set when the node has a name
+const HAS_NAME: u8 = 0x01;
+/// Flag set when the node has content
+const HAS_CONTENT: u8 = 0x02;
+/// Flag set when a text node is written as a CDATA section
+const CDATA_SECTION: u8 = 0x04;
+/// Flag set when the names of attributes written without a value follow
+/// the attributes, since version 2
+const VALUELESS: u8 = 0x08;
+
+/// Encodes a node and its subtree.
+///
+/// # Arguments
+///
+/// * `node` - The root of the subtree to encode
+///
+/// # Returns
+///
+/// The binary representation of the tree
+pub(crate) fn encode(node: &IksNode) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    encode_node(node, &mut out);
+
+    let mut stack: Vec<_> = node.children.iter().rev().cloned().collect();
+    while let Some(child) = stack.pop() {
+        let child = child.borrow();
+        encode_node(&child, &mut out);
+        stack.extend(child.children.iter().rev().cloned());
+    }
+    out
+}
+
+/// Writes a single node, without its children.
+fn encode_node(node: &IksNode, out: &mut Vec<u8>) {
+    let mut flags = 0;
+    if node.name.is_some() {
+        flags |= HAS_NAME;
+    }
+    if node.content.is_some() {
+        flags |= HAS_CONTENT;
+    }
+    if node.cdata_section {
+        flags |= CDATA_SECTION;
+    }
+    if !node.valueless_attributes.is_empty() {
+        flags |= VALUELESS;
+    }
+    out.push(type_to_byte(node.node_type));
+    out.push(flags);
+    if let Some(name) = &node.name {
+        write_str(out, name);
+    }
+    if let Some(content) = &node.content {
+        write_str(out, content);
+    }
+    write_u32(out, node.attributes.len());
+    for (name, value) in &node.attributes {
+        write_str(out, name);
+        write_str(out, value);
+    }
+    if !node.valueless_attributes.is_empty() {
+        write_u32(out, node.valueless_attributes.len());
+        for name in &node.valueless_attributes {
+            write_str(out, name);
+        }
+    }
+    write_u32(out, node.children.len());
+}
+
+/// Decodes a tree written by `encode`.
+///
+/// # Arguments
+///
+/// * `data` - The binary representation of the tree
+///
+/// # Returns
+///
+/// A `Result` containing the root of the decoded tree, or
+/// `IksError::BadBinary` if the data is truncated or malformed
+pub(crate) fn decode(data: &[u8]) -> Result<Rc<RefCell<IksNode>>> {
+    let mut reader = Reader { data };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(IksError::BadBinary);
+    }
+    let version = reader.take(1)?[0];
+    if !(1..=VERSION).contains(&version) {
+        return Err(IksError::BadBinary);
+    }
+
+    let (root, children) = decode_node(&mut reader, version)?;
+    let root = root.into_rc();
+    // Each entry holds an element still waiting for children and their count
+    let mut stack = vec![(root.clone(), children)];
+    while let Some((parent, remaining)) = stack.last_mut() {
+        if *remaining == 0 {
+            stack.pop();
+            continue;
+        }
+        *remaining -= 1;
+        let parent = parent.clone();
+        let (node, children) = decode_node(&mut reader, version)?;
+        let node = IksNode::append_child(&parent, node.into_rc());
+        stack.push((node, children));
+    }
+
+    if !reader.data.is_empty() {
+        return Err(IksError::BadBinary);
+    }
+    Ok(root)
+}
+
+/// Reads a single node and the number of children that follow it.
+fn decode_node(reader: &mut Reader<'_>, version: u8) -> Result<(IksNode, usize)> {
+    let node_type = byte_to_type(reader.take(1)?[0])?;
+    let flags = reader.take(1)?[0];
+    let known = match version {
+        1 => HAS_NAME | HAS_CONTENT | CDATA_SECTION,
+        _ => HAS_NAME | HAS_CONTENT | CDATA_SECTION | VALUELESS,
+    };
+    if flags & !known != 0 {
+        return Err(IksError::BadBinary);
+    }
+
+    let mut node = IksNode::new(node_type);
+    if flags & HAS_NAME != 0 {
+        node.name = Some(reader.read_str()?);
+    }
+    if flags & HAS_CONTENT != 0 {
+        node.content = Some(reader.read_str()?);
+    }
+    node.cdata_section = flags & CDATA_SECTION != 0;
+
+    let attributes = reader.read_u32()?;
+    for _ in 0..attributes {
+        let name = reader.read_str()?;
+        let value = reader.read_str()?;
+        node.attributes.push((name, value));
+    }
+    if flags & VALUELESS != 0 {
+        let valueless = reader.read_u32()?;
+        for _ in 0..valueless {
+            node.valueless_attributes.push(reader.read_str()?);
+        }
+    }
+    let children = reader.read_u32()?;
+    Ok((node, children))
+}
+
+/// Cursor over the encoded bytes.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Takes the next `len` bytes.
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(IksError::BadBinary);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    /// Reads a little-endian `u32` count.
+    fn read_u32(&mut self) -> Result<usize> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    /// Reads a length-prefixed UTF-8 string.
+    fn read_str(&mut self) -> Result<String> {
+        let len = self.read_u32()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| IksError::BadBinary)
+    }
+}
+
+/// Writes a count as a little-endian `u32`.
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    let value = u32::try_from(value).expect("count does not fit the binary format");
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Writes a length-prefixed UTF-8 string.
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Gets the byte identifying a node type.
+fn type_to_byte(node_type: IksType) -> u8 {
+    match node_type {
+        IksType::None => 0,
+        IksType::Tag => 1,
+        IksType::Attribute => 2,
+        IksType::CData => 3,
+        IksType::Comment => 4,
+        IksType::Pi => 5,
+    }
+}
+
+/// Gets the node type identified by a byte.
+fn byte_to_type(byte: u8) -> Result<IksType> {
+    match byte {
+        0 => Ok(IksType::None),
+        1 => Ok(IksType::Tag),
+        2 => Ok(IksType::Attribute),
+        3 => Ok(IksType::CData),
+        4 => Ok(IksType::Comment),
+        5 => Ok(IksType::Pi),
+        _ => Err(IksError::BadBinary),
+    }
+}
@@ -0,0 +1,599 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::collections::{HashMap, HashSet};
+use crate::{Parser, SaxHandler};
+
+/// What `Parser::parse_bytes` does with bytes that are not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidUtf8 {
+    /// Fail with `IksError::BadUtf8`
+    #[default]
+    Error,
+    /// Decode each invalid sequence as U+FFFD, like `String::from_utf8_lossy`
+    Replace,
+}
+
+/// Configuration for the SAX parser.
+///
+/// The default configuration is strict and places no limits on the input,
+/// matching the behavior of `Parser::new`.
+#[derive(Debug, Clone)]
+pub struct ParserConfig {
+    /// Accept common malformations instead of failing.
+    ///
+    /// In lenient mode unknown entity references are kept verbatim in text
+    /// and attribute values instead of being rejected, as is an `&` in an
+    /// attribute value that starts no reference. Attributes without a value,
+    /// as in `<input disabled>`, are accepted with an empty value, the XML
+    /// declaration may follow leading whitespace, and further elements and
+    /// text may follow the root element. Strict mode only allows comments,
+    /// processing instructions and whitespace there.
+    pub lenient: bool,
+    /// Maximum nesting depth of elements, or `None` for no limit.
+    ///
+    /// See `xml::MAX_NESTING_DEPTH` for a sensible value for untrusted input.
+    pub max_depth: Option<usize>,
+    /// Maximum number of elements in the input, or `None` for no limit.
+    ///
+    /// Unlike `max_depth` this also bounds flat documents with many
+    /// siblings. The start tag that crosses the limit fails with
+    /// `IksError::TooManyElements`.
+    pub max_elements: Option<usize>,
+    /// Maximum length in bytes of a raw attribute value, or `None` for no limit.
+    ///
+    /// A longer value fails with `IksError::AttributeTooLong` as soon as the
+    /// limit is crossed, before the rest of it is buffered. See
+    /// `xml::MAX_ATTR_VALUE_LENGTH` for a sensible value for untrusted input.
+    pub max_attr_value_length: Option<usize>,
+    /// Additional named entities, mapping the entity name to its replacement text.
+    pub entities: HashMap<String, String>,
+    /// Names of the elements the input may contain, or `None` to allow any.
+    ///
+    /// The first element whose name is not in the set fails the parse with
+    /// `IksError::BadXml` before the handler sees it, which makes this a
+    /// cheap guard for input such as XMPP stanzas from untrusted peers.
+    pub allowed_elements: Option<HashSet<String>>,
+    /// Handling of invalid UTF-8 passed to `Parser::parse_bytes`.
+    pub on_invalid_utf8: InvalidUtf8,
+    /// Width of a tab stop used when counting columns.
+    ///
+    /// A tab advances `Parser::column` to the next multiple of this width,
+    /// so positions match what an editor shows for tab-indented files. The
+    /// default of 1 counts a tab like any other character; 0 is treated
+    /// like 1.
+    pub tab_width: usize,
+    /// Reject a byte order mark that is not at the start of the input.
+    ///
+    /// A leading U+FEFF is always skipped. Anywhere else it is a zero-width
+    /// no-break space, which is kept as text by default but usually means
+    /// that differently encoded pieces were glued together. When set, such
+    /// a BOM fails the parse with `IksError::MisplacedBom`.
+    pub reject_inner_bom: bool,
+    /// Count lines and columns while parsing.
+    ///
+    /// Turning this off saves some work per character for bulk processing
+    /// where positions are not needed. Byte offsets are still counted, but
+    /// `Parser::line`, `Parser::column` and the positions in errors and
+    /// spans then report line 0, column 0.
+    pub track_positions: bool,
+    /// Add the default attribute values declared by `<!ATTLIST ...>` in the
+    /// internal subset to elements that omit them.
+    ///
+    /// The defaults are added after the attributes written in the tag, as
+    /// if they had been written there. Handlers wrapped in `Streaming` get
+    /// them through `on_attribute` before `on_start_tag_end`.
+    pub apply_dtd_defaults: bool,
+    /// Convert element and attribute names to lowercase as they are read.
+    ///
+    /// This suits HTML and other dialects whose names are case-insensitive:
+    /// `<DIV CLASS="x">` is reported as `div` with a `class` attribute, and
+    /// `</Div>` closes it. XML names are case-sensitive, so this is off by
+    /// default.
+    pub lowercase_names: bool,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig {
+            lenient: false,
+            max_depth: None,
+            max_elements: None,
+            max_attr_value_length: None,
+            entities: HashMap::new(),
+            allowed_elements: None,
+            on_invalid_utf8: InvalidUtf8::default(),
+            tab_width: 1,
+            reject_inner_bom: false,
+            track_positions: true,
+            apply_dtd_defaults: false,
+            lowercase_names: false,
+        }
+    }
+}
+
+impl ParserConfig {
+    /// Looks up the replacement text of a named entity.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The entity name, without `&` and `;`
+    ///
+    /// # Returns
+    ///
+    /// The replacement text, or `None` if the entity is not declared
+    pub(crate) fn resolve_entity(&self, name: &str) -> Option<&str> {
+        match name {
+            "amp" => Some("&"),
+            "lt" => Some("<"),
+            "gt" => Some(">"),
+            "apos" => Some("'"),
+            "quot" => Some("\""),
+            name => self.entities.get(name).map(String::as_str),
+        }
+    }
+}
+
+/// Builder tying together a handler and the parser configuration.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::{DomParser, ParserBuilder};
+///
+/// let mut parser = ParserBuilder::new()
+///     .lenient()
+///     .max_depth(500)
+///     .entity("nbsp", "\u{a0}")
+///     .build(DomParser::new().unwrap());
+/// parser.parse("<p>a&nbsp;b</p>").unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ParserBuilder {
+    config: ParserConfig,
+}
+
+impl ParserBuilder {
+    /// Creates a builder with the default configuration.
+    ///
+    /// # Returns
+    ///
+    /// A new `ParserBuilder` instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts from an existing configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration to extend
+    ///
+    /// # Returns
+    ///
+    /// A new `ParserBuilder` instance
+    pub fn from_config(config: ParserConfig) -> Self {
+        ParserBuilder { config }
+    }
+
+    /// Enables lenient parsing.
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn lenient(mut self) -> Self {
+        self.config.lenient = true;
+        self
+    }
+
+    /// Limits the nesting depth of elements.
+    ///
+    /// # Arguments
+    ///
+    /// * `depth` - The maximum number of nested open elements
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.config.max_depth = Some(depth);
+        self
+    }
+
+    /// Limits the total number of elements.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - The maximum number of start tags, including empty elements
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn max_elements(mut self, count: usize) -> Self {
+        self.config.max_elements = Some(count);
+        self
+    }
+
+    /// Limits the length of attribute values.
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - The maximum number of bytes in a raw attribute value
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn max_attr_value_length(mut self, len: usize) -> Self {
+        self.config.max_attr_value_length = Some(len);
+        self
+    }
+
+    /// Chooses how invalid UTF-8 in byte input is handled.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Whether to fail or to substitute U+FFFD
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn on_invalid_utf8(mut self, policy: InvalidUtf8) -> Self {
+        self.config.on_invalid_utf8 = policy;
+        self
+    }
+
+    /// Sets the tab stop width used for column numbers.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The number of columns between tab stops, e.g. 4 or 8
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn tab_width(mut self, width: usize) -> Self {
+        self.config.tab_width = width;
+        self
+    }
+
+    /// Sets whether a byte order mark after the start of the input is an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `reject` - Whether to fail on a misplaced BOM instead of keeping it
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn reject_inner_bom(mut self, reject: bool) -> Self {
+        self.config.reject_inner_bom = reject;
+        self
+    }
+
+    /// Sets whether lines and columns are counted.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to track line and column numbers
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn track_positions(mut self, enabled: bool) -> Self {
+        self.config.track_positions = enabled;
+        self
+    }
+
+    /// Sets whether attribute defaults from the DTD are applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to add declared defaults to elements omitting them
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn apply_dtd_defaults(mut self, enabled: bool) -> Self {
+        self.config.apply_dtd_defaults = enabled;
+        self
+    }
+
+    /// Sets whether element and attribute names are lowercased.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to convert names to lowercase as they are read
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn lowercase_names(mut self, enabled: bool) -> Self {
+        self.config.lowercase_names = enabled;
+        self
+    }
+
+    /// Declares an additional named entity.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The entity name, without `&` and `;`
+    /// * `value` - The replacement text
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn entity<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.config.entities.insert(name.into(), value.into());
+        self
+    }
+
+    /// Restricts the input to the given element names.
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The names of the allowed elements
+    ///
+    /// # Returns
+    ///
+    /// The updated builder
+    pub fn allow_elements<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.allowed_elements = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Gets the configuration built so far.
+    ///
+    /// # Returns
+    ///
+    /// A reference to the configuration
+    pub fn config(&self) -> &ParserConfig {
+        &self.config
+    }
+
+    /// Creates a parser for the given handler with the built configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler to receive parsing events
+    ///
+    /// # Returns
+    ///
+    /// A new `Parser` instance
+    pub fn build<H: SaxHandler>(self, handler: H) -> Parser<H> {
+        Parser::with_config(handler, self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DomParser, IksError};
+
+    #[test]
+    fn test_builder_options() {
+        let builder = ParserBuilder::new()
+            .lenient()
+            .max_depth(2)
+            .entity("nbsp", "\u{a0}");
+        assert!(builder.config().lenient);
+        assert_eq!(builder.config().max_depth, Some(2));
+
+        let mut parser = builder.clone().build(DomParser::new().unwrap());
+        parser.parse("<p>a&nbsp;b &unknown; c<b/></p>").unwrap();
+        let root = parser.handler().document().unwrap();
+        assert_eq!(root.borrow().to_string(), "<p>a\u{a0}b &amp;unknown; c<b/></p>");
+
+        let mut parser = builder.clone().build(DomParser::new().unwrap());
+        parser.parse("<a t=\"x & y &amp; &unknown; &nbsp;z&\"/>").unwrap();
+        let root = parser.handler().document().unwrap();
+        assert_eq!(root.borrow().find_attrib("t"), Some("x & y & &unknown; \u{a0}z&"));
+
+        let mut parser = builder.build(DomParser::new().unwrap());
+        let result = parser.parse("<a><b><c/></b></a>");
+        assert!(matches!(result, Err(IksError::TooDeep)));
+
+        let mut strict = ParserBuilder::new().build(DomParser::new().unwrap());
+        assert!(matches!(strict.parse("<p>&nbsp;</p>"), Err(IksError::BadXml)));
+        let mut strict = ParserBuilder::new().build(DomParser::new().unwrap());
+        assert!(matches!(strict.parse("<a t=\"x & y\"/>"), Err(IksError::BadAttribute { .. })));
+    }
+
+    #[test]
+    fn test_valueless_attributes() {
+        let mut parser = ParserBuilder::new().lenient().build(DomParser::new().unwrap());
+        parser.parse("<form><input disabled><input checked name = \"a\" readonly /></input></form>").unwrap();
+        let root = parser.handler().document().unwrap();
+        assert_eq!(
+            root.borrow().to_string(),
+            "<form><input disabled=\"\"><input checked=\"\" name=\"a\" readonly=\"\"/></input></form>"
+        );
+
+        for xml in ["<input disabled>", "<input disabled/>", "<input disabled name=\"a\"/>"] {
+            let mut strict = ParserBuilder::new().build(DomParser::new().unwrap());
+            assert!(matches!(strict.parse(xml), Err(IksError::BadAttribute { .. })));
+        }
+    }
+
+    #[test]
+    fn test_without_positions() {
+        let xml = "<doc a=\"1\">\n\t<item>one &amp; two</item>\n\t<item/>\n</doc>";
+        let expected = DomParser::parse_str(xml).unwrap();
+
+        let mut parser = ParserBuilder::new().track_positions(false).build(DomParser::new().unwrap());
+        parser.parse(xml).unwrap();
+        parser.finish().unwrap();
+        let root = parser.handler().document().unwrap();
+        assert!(root.borrow().tree_eq(&expected.borrow()));
+        assert_eq!((parser.line(), parser.column()), (0, 0));
+        assert_eq!(parser.offset(), xml.len());
+
+        let mut parser = ParserBuilder::new().track_positions(false).build(DomParser::new().unwrap());
+        match parser.parse("<a>\n<b x=y>") {
+            Err(IksError::BadAttribute { position, .. }) => {
+                assert_eq!((position.offset, position.line, position.column), (7, 0, 0));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let xml = "<a>\n\t<b>\n\t\t<c x=\"1\" y>";
+        let mut parser = ParserBuilder::new().build(DomParser::new().unwrap());
+        parser.parse("<a>\n\t\t<b").unwrap();
+        assert_eq!(parser.column(), 4);
+
+        let mut parser = ParserBuilder::new().tab_width(4).build(DomParser::new().unwrap());
+        parser.parse("<a>\n\t\t<b").unwrap();
+        assert_eq!(parser.column(), 10);
+        parser.parse(">\n  \tx").unwrap();
+        assert_eq!(parser.column(), 5);
+
+        let mut parser = ParserBuilder::new().tab_width(8).build(DomParser::new().unwrap());
+        match parser.parse(xml) {
+            Err(IksError::BadAttribute { position, .. }) => assert_eq!((position.line, position.column), (3, 26)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_declaration_position() {
+        let parse = |builder: ParserBuilder, xml: &str| {
+            let mut parser = builder.build(DomParser::new().unwrap());
+            parser.parse(xml).map(|_| parser.handler().document().unwrap().borrow().to_string())
+        };
+        let decl = "<?xml version=\"1.0\"?>";
+        assert_eq!(parse(ParserBuilder::new(), &format!("\u{feff}{decl}<a/>")).unwrap(), "<a/>");
+
+        let leading = format!("\n  {decl}<a/>");
+        match parse(ParserBuilder::new(), &leading) {
+            Err(IksError::MisplacedDeclaration { position }) => assert_eq!((position.offset, position.line), (3, 2)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(parse(ParserBuilder::new().lenient(), &leading).unwrap(), "<a/>");
+
+        for misplaced in [format!("<a/>{decl}"), format!("<!-- c -->{decl}<a/>"), format!("text{decl}<a/>")] {
+            for builder in [ParserBuilder::new(), ParserBuilder::new().lenient()] {
+                assert!(matches!(parse(builder, &misplaced), Err(IksError::MisplacedDeclaration { .. })), "{}", misplaced);
+            }
+        }
+        assert!(parse(ParserBuilder::new(), "<?xml-stylesheet href='a.css'?><a/>").is_ok());
+        assert!(parse(ParserBuilder::new(), "<a><?php echo; ?></a>").is_ok());
+    }
+
+    #[test]
+    fn test_dtd_defaults() {
+        let xml = "<!DOCTYPE list [<!ATTLIST item type CDATA 'book' lang CDATA #IMPLIED>]>\
+                   <list><item/><item type='film'>x</item><other/></list>";
+        let parse = |builder: ParserBuilder| {
+            let mut parser = builder.build(DomParser::new().unwrap());
+            parser.parse(xml).unwrap();
+            parser.handler().document().unwrap().borrow().to_string()
+        };
+        assert_eq!(parse(ParserBuilder::new()), "<list><item/><item type=\"film\">x</item><other/></list>");
+        assert_eq!(
+            parse(ParserBuilder::new().apply_dtd_defaults(true)),
+            "<list><item type=\"book\"/><item type=\"film\">x</item><other/></list>"
+        );
+    }
+
+    #[test]
+    fn test_inner_bom() {
+        let xml = "\u{feff}<a>one\u{feff}two</a>";
+        let mut parser = ParserBuilder::new().build(DomParser::new().unwrap());
+        parser.parse(xml).unwrap();
+        let root = parser.handler().document().unwrap();
+        assert_eq!(root.borrow().text_content(), "one\u{feff}two");
+        assert_eq!(parser.column(), 14);
+
+        let mut parser = ParserBuilder::new().reject_inner_bom(true).build(DomParser::new().unwrap());
+        match parser.parse(xml) {
+            Err(IksError::MisplacedBom { position }) => assert_eq!((position.offset, position.column), (9, 7)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut parser = ParserBuilder::new().reject_inner_bom(true).build(DomParser::new().unwrap());
+        parser.parse("\u{feff}<a>text</a>").unwrap();
+        assert_eq!(parser.handler().document().unwrap().borrow().text_content(), "text");
+    }
+
+    #[test]
+    fn test_max_elements() {
+        let builder = ParserBuilder::new().max_elements(5);
+        let mut parser = builder.clone().build(DomParser::new().unwrap());
+        parser.parse("<list><i/><i/><i>x</i><i/></list>").unwrap();
+
+        let mut parser = builder.build(DomParser::new().unwrap());
+        let result = parser.parse("<list><i/><i/><i/><i/><i/><i/></list>");
+        assert!(matches!(result, Err(IksError::TooManyElements)));
+    }
+
+    #[test]
+    fn test_attribute_value_length() {
+        let value = "x".repeat(2048);
+        let xml = format!("<root><item data=\"{}\"/></root>", value);
+        let mut parser = ParserBuilder::new().max_attr_value_length(1024).build(DomParser::new().unwrap());
+        match parser.parse(&xml) {
+            Err(IksError::AttributeTooLong { name, position, limit }) => {
+                assert_eq!(name, "data");
+                assert_eq!((position.line, position.column), (1, 13));
+                assert_eq!(limit, 1024);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut parser = ParserBuilder::new().max_attr_value_length(2048).build(DomParser::new().unwrap());
+        parser.parse(&xml).unwrap();
+        let mut parser = ParserBuilder::new().build(DomParser::new().unwrap());
+        parser.parse(&xml).unwrap();
+    }
+
+    #[test]
+    fn test_lowercase_names() {
+        let builder = ParserBuilder::new().lowercase_names(true);
+        let mut parser = builder.clone().build(DomParser::new().unwrap());
+        parser.parse_complete("<DIV CLASS=\"x\"><Span Data-Id='a'>Text</SPAN></Div>").unwrap();
+        let root = parser.handler().document().unwrap();
+        assert_eq!(root.borrow().name(), Some("div"));
+        assert_eq!(root.borrow().find_attrib("class"), Some("x"));
+        assert_eq!(root.borrow().to_string(), "<div class=\"x\"><span data-id=\"a\">Text</span></div>");
+
+        let mut parser = builder.lenient().allow_elements(["input"]).build(DomParser::new().unwrap());
+        parser.parse_complete("<INPUT DISABLED/>").unwrap();
+        let root = parser.handler().document().unwrap();
+        assert!(root.borrow().is_valueless_attribute("disabled"));
+
+        let mut parser = ParserBuilder::new().build(DomParser::new().unwrap());
+        parser.parse_complete("<DIV CLASS=\"x\"/>").unwrap();
+        assert_eq!(parser.handler().document().unwrap().borrow().to_string(), "<DIV CLASS=\"x\"/>");
+    }
+
+    #[test]
+    fn test_allowed_elements() {
+        let builder = ParserBuilder::new().allow_elements(["iq", "message", "presence"]);
+
+        let mut parser = builder.clone().build(DomParser::new().unwrap());
+        parser.parse("<message><iq/><presence>hi</presence></message>").unwrap();
+        assert!(parser.handler().document().is_some());
+
+        let mut parser = builder.build(DomParser::new().unwrap());
+        let result = parser.parse("<message><script>alert(1)</script></message>");
+        assert!(matches!(result, Err(IksError::BadXml)));
+        let root = parser.handler().document();
+        assert!(root.is_none_or(|root| root.borrow().find("script").is_none()));
+    }
+}
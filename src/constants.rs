@@ -145,4 +145,16 @@ pub mod xml {
     /// This constant defines the maximum length allowed for XML comments.
     /// It helps prevent excessive memory usage and potential DoS attacks.
     pub const MAX_COMMENT_LENGTH: usize = 4096;
-} 
\ No newline at end of file
+
+    /// Default XML declaration for serialized documents.
+    /// 
+    /// This constant is the prolog written by the serializer when a
+    /// declaration is requested without specifying one.
+    pub const DEFAULT_PROLOG: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+
+    /// Namespace URI bound to the reserved `xml` prefix.
+    /// 
+    /// This prefix never needs to be declared; it is used by attributes
+    /// such as `xml:lang` and `xml:space`.
+    pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+}
@@ -0,0 +1,191 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use crate::{Parser, Result, SaxHandler};
+
+/// Trait for hashers that can be fed with the raw bytes seen by a parser.
+///
+/// Any hasher implementing the `sha1::Digest` family of traits gets this
+/// for free, so `Sha1` (and friends) can be used directly.
+pub trait ByteDigest {
+    /// Feeds a chunk of bytes into the hasher.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The bytes to hash
+    fn update_bytes(&mut self, data: &[u8]);
+}
+
+impl<D: sha1::Digest> ByteDigest for D {
+    fn update_bytes(&mut self, data: &[u8]) {
+        self.update(data);
+    }
+}
+
+/// Parser wrapper that hashes the input while parsing it.
+///
+/// Every chunk passed to `parse` is fed into the hasher before being handed
+/// to the underlying `Parser`, so a single pass over the data yields both
+/// the parse result and a digest of the raw content.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::{DigestingParser, DomParser};
+/// use sha1::{Digest, Sha1};
+///
+/// let mut parser = DigestingParser::new(DomParser::new().unwrap(), Sha1::new());
+/// parser.parse("<root>Hello World</root>").unwrap();
+///
+/// let (_, hasher) = parser.into_parts();
+/// assert_eq!(hasher.finalize(), Sha1::digest(b"<root>Hello World</root>"));
+/// ```
+pub struct DigestingParser<H: SaxHandler, D: ByteDigest> {
+    parser: Parser<H>,
+    digest: D,
+}
+
+impl<H: SaxHandler, D: ByteDigest> DigestingParser<H, D> {
+    /// Creates a new digesting parser.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler to receive parsing events
+    /// * `digest` - The hasher to feed the consumed bytes into
+    ///
+    /// # Returns
+    ///
+    /// A new `DigestingParser` instance
+    pub fn new(handler: H, digest: D) -> Self {
+        DigestingParser {
+            parser: Parser::new(handler),
+            digest,
+        }
+    }
+
+    /// Hashes and parses a chunk of XML data.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The XML data to parse
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure of the parse
+    pub fn parse(&mut self, data: &str) -> Result<(), H::Error> {
+        self.digest.update_bytes(data.as_bytes());
+        self.parser.parse(data)
+    }
+
+    /// Hashes and parses a chunk of XML data given as UTF-8 bytes.
+    ///
+    /// The bytes are hashed as given, even where a chunk splits a
+    /// multi-byte character.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The UTF-8 encoded XML data to parse
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure of the parse
+    pub fn parse_bytes(&mut self, data: &[u8]) -> Result<(), H::Error> {
+        self.digest.update_bytes(data);
+        self.parser.parse_bytes(data)
+    }
+
+    /// Signals the end of the input, see `Parser::finish`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that fails if the document was truncated
+    pub fn finish(&mut self) -> Result<(), H::Error> {
+        self.parser.finish()
+    }
+
+    /// Gets a reference to the underlying parser.
+    ///
+    /// # Returns
+    ///
+    /// A reference to the parser
+    pub fn parser(&self) -> &Parser<H> {
+        &self.parser
+    }
+
+    /// Gets a mutable reference to the underlying parser.
+    ///
+    /// # Returns
+    ///
+    /// A mutable reference to the parser
+    pub fn parser_mut(&mut self) -> &mut Parser<H> {
+        &mut self.parser
+    }
+
+    /// Gets a reference to the hasher.
+    ///
+    /// # Returns
+    ///
+    /// A reference to the hasher, fed with all data parsed so far
+    pub fn digest(&self) -> &D {
+        &self.digest
+    }
+
+    /// Splits the wrapper into the parser and the hasher.
+    ///
+    /// # Returns
+    ///
+    /// A tuple containing (parser, hasher)
+    pub fn into_parts(self) -> (Parser<H>, D) {
+        (self.parser, self.digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DomParser;
+    use sha1::{Digest, Sha1};
+
+    #[test]
+    fn test_streamed_hash_matches() {
+        let xml = "<root version=\"1.0\"><child id=\"1\">Text \u{e7}</child><child id=\"2\"/></root>";
+
+        let mut parser = DigestingParser::new(DomParser::new().unwrap(), Sha1::new());
+        let (head, tail) = xml.split_at(17);
+        parser.parse(head).unwrap();
+        parser.parse(tail).unwrap();
+
+        let (parser, hasher) = parser.into_parts();
+        let root = parser.handler().document().unwrap();
+        assert_eq!(root.borrow().name(), Some("root"));
+        assert_eq!(hasher.finalize(), Sha1::digest(xml.as_bytes()));
+    }
+
+    #[test]
+    fn test_byte_chunks() {
+        let xml = "<root>Text \u{e7}\u{1f980}</root>".as_bytes();
+
+        let mut parser = DigestingParser::new(DomParser::new().unwrap(), Sha1::new());
+        for chunk in xml.chunks(3) {
+            parser.parse_bytes(chunk).unwrap();
+        }
+        parser.finish().unwrap();
+        let (parser, hasher) = parser.into_parts();
+        assert_eq!(parser.handler().document().unwrap().borrow().text_content(), "Text \u{e7}\u{1f980}");
+        assert_eq!(hasher.finalize(), Sha1::digest(xml));
+
+        let mut parser = DigestingParser::new(DomParser::new().unwrap(), Sha1::new());
+        parser.parse_bytes(&xml[..10]).unwrap();
+        assert!(matches!(parser.finish(), Err(crate::IksError::UnexpectedEof)));
+    }
+}
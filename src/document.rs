@@ -0,0 +1,445 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use crate::{DomParser, IksError, IksNode, Parser, Result, SerializeOptions};
+use crate::serialize;
+use crate::utility::unescape;
+
+/// A default attribute value declared by `<!ATTLIST ...>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeDefault {
+    /// Name of the element the attribute belongs to
+    pub element: String,
+    /// Name of the attribute
+    pub attribute: String,
+    /// The default value, with references decoded
+    pub value: String,
+    /// Whether the declaration is `#FIXED`, so no other value is allowed
+    pub fixed: bool,
+}
+
+/// A document type declaration.
+///
+/// The internal subset is kept as raw text; its declarations are not
+/// interpreted, but they are written back unchanged when the declaration
+/// is serialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Doctype {
+    /// Name of the root element
+    pub name: String,
+    /// Public identifier of the external subset
+    pub public_id: Option<String>,
+    /// System identifier (URI) of the external subset
+    pub system_id: Option<String>,
+    /// Raw text between `[` and `]`, without the brackets
+    pub internal_subset: Option<String>,
+}
+
+impl Doctype {
+    /// Creates a declaration without external or internal subset.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the root element
+    ///
+    /// # Returns
+    ///
+    /// A new `Doctype` instance
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Doctype {
+            name: name.into(),
+            public_id: None,
+            system_id: None,
+            internal_subset: None,
+        }
+    }
+
+    /// Parses the body of a `<!DOCTYPE ...>` declaration.
+    ///
+    /// # Arguments
+    ///
+    /// * `decl` - The text following the `DOCTYPE` keyword, up to the final `>`
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the parsed declaration
+    pub(crate) fn parse(decl: &str) -> Result<Self> {
+        let rest = decl.trim_start();
+        let name_len = rest.find(|c: char| c.is_whitespace() || c == '[').unwrap_or(rest.len());
+        if name_len == 0 {
+            return Err(IksError::BadXml);
+        }
+        let mut doctype = Doctype::new(&rest[..name_len]);
+        let mut rest = rest[name_len..].trim_start();
+
+        if let Some(ids) = rest.strip_prefix("PUBLIC") {
+            let (public_id, ids) = take_literal(ids)?;
+            let (system_id, ids) = take_literal(ids)?;
+            doctype.public_id = Some(public_id.to_string());
+            doctype.system_id = Some(system_id.to_string());
+            rest = ids;
+        } else if let Some(ids) = rest.strip_prefix("SYSTEM") {
+            let (system_id, ids) = take_literal(ids)?;
+            doctype.system_id = Some(system_id.to_string());
+            rest = ids;
+        }
+
+        let rest = rest.trim_start();
+        if let Some(subset) = rest.strip_prefix('[') {
+            let end = subset.rfind(']').ok_or(IksError::BadXml)?;
+            if !subset[end + 1..].trim().is_empty() {
+                return Err(IksError::BadXml);
+            }
+            doctype.internal_subset = Some(subset[..end].to_string());
+        } else if !rest.is_empty() {
+            return Err(IksError::BadXml);
+        }
+        Ok(doctype)
+    }
+
+    /// Gets the default attribute values declared in the internal subset.
+    ///
+    /// Every `<!ATTLIST ...>` declaration is read; attributes declared
+    /// `#REQUIRED` or `#IMPLIED` have no default and are left out. As the
+    /// XML specification demands, only the first declaration of an attribute
+    /// counts. Declarations in the external subset are not read.
+    ///
+    /// # Returns
+    ///
+    /// The declared defaults, in declaration order
+    pub fn attribute_defaults(&self) -> Vec<AttributeDefault> {
+        let mut defaults = Vec::new();
+        let mut rest = self.internal_subset.as_deref().unwrap_or_default();
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("<!--") {
+                rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            } else if let Some(after) = rest.strip_prefix("<?") {
+                rest = after.find("?>").map_or("", |end| &after[end + 2..]);
+            } else {
+                let (decl, after) = split_declaration(&rest[1..]);
+                if let Some(body) = decl.strip_prefix("!ATTLIST") {
+                    parse_attlist(body, &mut defaults);
+                }
+                rest = after;
+            }
+        }
+        defaults
+    }
+}
+
+/// Splits a declaration off at the first `>` outside a quoted literal.
+///
+/// # Returns
+///
+/// A tuple containing (text before the `>`, text after it)
+fn split_declaration(s: &str) -> (&str, &str) {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return (&s[..i], &s[i + 1..]),
+            None => {}
+        }
+    }
+    (s, "")
+}
+
+/// Splits the body of a declaration into names, keywords, quoted literals
+/// and parenthesized groups.
+fn declaration_tokens(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(first) = rest.chars().next() {
+        let len = match first {
+            '"' | '\'' => rest[1..].find(first).map_or(rest.len(), |end| end + 2),
+            '(' => rest.find(')').map_or(rest.len(), |end| end + 1),
+            _ => rest.find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '(')).unwrap_or(rest.len()),
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+/// Collects the defaults of one `<!ATTLIST element (name type default)*>`.
+fn parse_attlist(body: &str, defaults: &mut Vec<AttributeDefault>) {
+    let tokens = declaration_tokens(body);
+    let Some((element, mut rest)) = tokens.split_first() else {
+        return;
+    };
+    while let [attribute, kind, tail @ ..] = rest {
+        rest = tail;
+        if *kind == "NOTATION" {
+            rest = rest.get(1..).unwrap_or_default();
+        }
+        let fixed = rest.first() == Some(&"#FIXED");
+        if fixed {
+            rest = &rest[1..];
+        }
+        let Some((default, tail)) = rest.split_first() else {
+            return;
+        };
+        rest = tail;
+        match default.chars().next() {
+            Some('#') if !fixed => continue,
+            Some(quote @ ('"' | '\'')) if default.len() >= 2 && default.ends_with(quote) => {
+                let known = defaults.iter().any(|d| d.element == *element && d.attribute == *attribute);
+                if !known {
+                    defaults.push(AttributeDefault {
+                        element: element.to_string(),
+                        attribute: attribute.to_string(),
+                        value: unescape(&default[1..default.len() - 1]),
+                        fixed,
+                    });
+                }
+            }
+            _ => return,
+        }
+    }
+}
+
+/// Splits a quoted literal off the start of a string.
+///
+/// # Returns
+///
+/// A tuple containing (literal without quotes, remaining text)
+fn take_literal(s: &str) -> Result<(&str, &str)> {
+    let s = s.trim_start();
+    let quote = s.chars().next().filter(|&c| c == '"' || c == '\'').ok_or(IksError::BadXml)?;
+    let end = s[1..].find(quote).ok_or(IksError::BadXml)? + 1;
+    Ok((&s[1..end], &s[end + 1..]))
+}
+
+/// Writes a literal, picking a quote character that does not occur in it.
+fn write_literal(f: &mut fmt::Formatter<'_>, literal: &str) -> fmt::Result {
+    if literal.contains('"') {
+        write!(f, " '{}'", literal)
+    } else {
+        write!(f, " \"{}\"", literal)
+    }
+}
+
+impl fmt::Display for Doctype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<!DOCTYPE {}", self.name)?;
+        match (&self.public_id, &self.system_id) {
+            (Some(public_id), system_id) => {
+                f.write_str(" PUBLIC")?;
+                write_literal(f, public_id)?;
+                write_literal(f, system_id.as_deref().unwrap_or_default())?;
+            }
+            (None, Some(system_id)) => {
+                f.write_str(" SYSTEM")?;
+                write_literal(f, system_id)?;
+            }
+            (None, None) => {}
+        }
+        if let Some(subset) = &self.internal_subset {
+            write!(f, " [{}]", subset)?;
+        }
+        f.write_str(">")
+    }
+}
+
+/// A parsed document: the root element plus its document type declaration.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::Document;
+///
+/// let xml = "<!DOCTYPE note SYSTEM \"note.dtd\"><note>Hi</note>";
+/// let doc = Document::parse_str(xml).unwrap();
+/// assert_eq!(doc.doctype.as_ref().unwrap().name, "note");
+/// assert_eq!(doc.to_string(), xml);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Document {
+    /// The document type declaration, if the document had one
+    pub doctype: Option<Doctype>,
+    /// The root element
+    pub root: Rc<RefCell<IksNode>>,
+}
+
+impl Document {
+    /// Creates a document without a type declaration.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The root element
+    ///
+    /// # Returns
+    ///
+    /// A new `Document` instance
+    pub fn new(root: Rc<RefCell<IksNode>>) -> Self {
+        Document { doctype: None, root }
+    }
+
+    /// Parses an XML string into a document.
+    ///
+    /// # Arguments
+    ///
+    /// * `xml` - The XML string to parse
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the parsed document
+    pub fn parse_str(xml: &str) -> Result<Self> {
+        let mut parser = Parser::new(DomParser::new()?);
+        parser.parse(xml)?;
+        Self::from_handler(parser.handler())
+    }
+
+    /// Parses UTF-8 encoded XML into a document.
+    ///
+    /// The bytes go straight to `Parser::parse_bytes`, so data read from a
+    /// socket or file needs no conversion to `str` first. The slice must
+    /// hold the whole document: a truncated one or a multi-byte character
+    /// cut off at the end is an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `xml` - The XML data to parse
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the parsed document
+    pub fn parse_bytes(xml: &[u8]) -> Result<Self> {
+        let mut parser = Parser::new(DomParser::new()?);
+        parser.parse_bytes(xml)?;
+        parser.finish()?;
+        Self::from_handler(parser.handler())
+    }
+
+    /// Takes the document built by a parser's handler.
+    fn from_handler(handler: &DomParser) -> Result<Self> {
+        let root = handler.document().ok_or(IksError::BadXml)?;
+        Ok(Document {
+            doctype: handler.doctype().cloned(),
+            root,
+        })
+    }
+
+    /// Serializes the document using the given serialization options.
+    ///
+    /// The prolog, if enabled, is followed by the document type declaration
+    /// and the root element.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - How to write the document
+    ///
+    /// # Returns
+    ///
+    /// The XML representation of the document
+    pub fn to_string_with(&self, options: &SerializeOptions) -> String {
+        let mut out = String::new();
+        serialize::serialize_document(&self.root.borrow(), self.doctype.as_ref(), &mut out, options)
+            .expect("writing to a String never fails");
+        out
+    }
+}
+
+impl TryFrom<&[u8]> for Document {
+    type Error = IksError;
+
+    fn try_from(xml: &[u8]) -> Result<Self> {
+        Document::parse_bytes(xml)
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        serialize::serialize_document(&self.root.borrow(), self.doctype.as_ref(), f, &SerializeOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from_bytes() {
+        let xml = "<!DOCTYPE p><p lang='tr'>güneş</p>";
+        let doc = Document::try_from(xml.as_bytes()).unwrap();
+        assert_eq!(doc.doctype.as_ref().map(|d| d.name.as_str()), Some("p"));
+        assert_eq!(doc.root.borrow().find_attrib("lang"), Some("tr"));
+        assert_eq!(doc.root.borrow().text_content(), "güneş");
+
+        let cut = xml.len() - "ş</p>".len() + 1;
+        assert!(Document::try_from(&xml.as_bytes()[..cut]).is_err());
+        assert!(Document::try_from(&b"<p>\xff</p>"[..]).is_err());
+    }
+
+    #[test]
+    fn test_attribute_defaults() {
+        let doctype = Doctype::parse(" doc [\n  <!-- <!ATTLIST doc ignored CDATA 'x'> -->\n  \
+            <!ATTLIST item\n    id ID #REQUIRED\n    kind (a|b) \"a\"\n    note CDATA #IMPLIED\n    \
+            title CDATA 'x &amp; y > z'\n    version CDATA #FIXED \"1.0\">\n  \
+            <!ELEMENT item (#PCDATA)>\n  <!ATTLIST item kind CDATA 'b' lang NMTOKEN 'en'>\n]").unwrap();
+        let defaults: Vec<_> = doctype.attribute_defaults().into_iter()
+            .map(|d| (d.element, d.attribute, d.value, d.fixed))
+            .collect();
+        let expected = [
+            ("item", "kind", "a", false),
+            ("item", "title", "x & y > z", false),
+            ("item", "version", "1.0", true),
+            ("item", "lang", "en", false),
+        ].map(|(e, a, v, f)| (e.to_string(), a.to_string(), v.to_string(), f));
+        assert_eq!(defaults, expected);
+        assert!(Doctype::new("doc").attribute_defaults().is_empty());
+    }
+
+    #[test]
+    fn test_doctype_round_trip() {
+        let xml = "<!DOCTYPE note [\n  <!ENTITY writer \"Donald Duck.\">\n  <!-- a > b -->\n]><note>Hi</note>";
+        let doc = Document::parse_str(xml).unwrap();
+        let doctype = doc.doctype.as_ref().unwrap();
+        assert_eq!(doctype.name, "note");
+        assert_eq!(doctype.system_id, None);
+        assert_eq!(
+            doctype.internal_subset.as_deref(),
+            Some("\n  <!ENTITY writer \"Donald Duck.\">\n  <!-- a > b -->\n")
+        );
+        assert_eq!(doc.to_string(), xml);
+
+        let reparsed = Document::parse_str(&doc.to_string()).unwrap();
+        assert_eq!(reparsed.doctype, doc.doctype);
+        assert!(reparsed.root.borrow().tree_eq(&doc.root.borrow()));
+    }
+
+    #[test]
+    fn test_doctype_external_ids() {
+        let xml = "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" 'http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd'><html/>";
+        let doc = Document::parse_str(xml).unwrap();
+        let doctype = doc.doctype.as_ref().unwrap();
+        assert_eq!(doctype.public_id.as_deref(), Some("-//W3C//DTD XHTML 1.0 Strict//EN"));
+        assert_eq!(doctype.system_id.as_deref(), Some("http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd"));
+        assert_eq!(
+            doc.to_string_with(&SerializeOptions::new().with_prolog()),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \
+             \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\"><html/>"
+        );
+
+        assert!(Document::parse_str("<!DOCTYPE><a/>").is_err());
+        assert!(Document::parse_str("<!DOCTYPE a SYSTEM><a/>").is_err());
+        assert!(Document::parse_str("<a><!DOCTYPE a></a>").is_err());
+        assert!(Document::parse_str("<a/>").unwrap().doctype.is_none());
+    }
+}
@@ -13,9 +13,32 @@
 
 use std::rc::Rc;
 use std::cell::RefCell;
-use crate::{IksError, IksNode, Result, TagType, SaxHandler};
+use crate::{Doctype, Event, IksError, IksNode, Result, TagType, SaxHandler, Span};
 use crate::constants::memory;
 
+/// Callback run on each completed element, see `DomParser::set_on_node_built`.
+type NodeHook = Box<dyn FnMut(&mut IksNode)>;
+/// Callback reporting parse progress, see `DomParser::set_progress`.
+type ProgressHook = Box<dyn FnMut(Progress)>;
+
+/// How often `DomParser::set_progress` reports progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressInterval {
+    /// Report each time the input consumed passes a multiple of this many bytes
+    Bytes(usize),
+    /// Report after every this many elements
+    Elements(usize),
+}
+
+/// The progress of a DOM parse, as passed to the progress callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Progress {
+    /// Bytes of input consumed, up to the end of the latest tag
+    pub bytes: usize,
+    /// Elements started so far
+    pub elements: usize,
+}
+
 /// DOM parser that builds a tree structure from SAX events.
 /// 
 /// This parser implements the `SaxHandler` trait to build a complete DOM tree
@@ -41,6 +64,26 @@ pub struct DomParser {
     root: Option<Rc<RefCell<IksNode>>>,
     node_stack: Vec<Rc<RefCell<IksNode>>>,
     chunk_size: usize,
+    preserve_whitespace: bool,
+    preserve_references: bool,
+    /// Source forms of the attributes of the tag being read, as (name, raw)
+    attribute_sources: Vec<(String, String)>,
+    /// Names of the valueless attributes of the tag being read
+    valueless_attributes: Vec<String>,
+    lossless: bool,
+    track_spans: bool,
+    tag_span: Span,
+    max_bytes: Option<usize>,
+    used_bytes: usize,
+    doctype: Option<Doctype>,
+    on_node_built: Option<NodeHook>,
+    /// Whitespace at the start of the current text run, kept until the run
+    /// turns out not to be blank
+    pending_blank: String,
+    on_progress: Option<(ProgressInterval, ProgressHook)>,
+    progress: Progress,
+    /// Byte count at which the next report is due in `ProgressInterval::Bytes` mode
+    next_progress: usize,
 }
 
 impl DomParser {
@@ -54,6 +97,21 @@ impl DomParser {
             root: None,
             node_stack: Vec::new(),
             chunk_size: memory::DEFAULT_IKS_CHUNK_SIZE,
+            preserve_whitespace: false,
+            preserve_references: false,
+            attribute_sources: Vec::new(),
+            valueless_attributes: Vec::new(),
+            lossless: false,
+            track_spans: false,
+            tag_span: Span::default(),
+            max_bytes: None,
+            used_bytes: 0,
+            doctype: None,
+            on_node_built: None,
+            pending_blank: String::new(),
+            on_progress: None,
+            progress: Progress::default(),
+            next_progress: 0,
         })
     }
 
@@ -70,6 +128,218 @@ impl DomParser {
         self.chunk_size = cs.max(memory::DEFAULT_IKS_CHUNK_SIZE);
     }
 
+    /// Sets whether whitespace-only character data is kept in the tree.
+    /// 
+    /// By default text consisting only of whitespace (usually indentation)
+    /// is dropped.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `enabled` - Whether to keep whitespace-only text nodes
+    pub fn set_preserve_whitespace(&mut self, enabled: bool) {
+        self.preserve_whitespace = enabled;
+    }
+
+    /// Sets whether attribute values keep the references they were written with.
+    /// 
+    /// Values are always decoded, so `a &#38; b` and `a &amp; b` both read
+    /// as `a & b`, and are normally written back in the canonical form
+    /// `a &amp; b`. With this enabled the source form of each value that
+    /// contains a reference is kept next to it and written back instead,
+    /// as long as the attribute keeps its parsed value. This matters to
+    /// tools that sign or diff documents and need them byte for byte.
+    /// Values written in single quotes that contain a `"` are still escaped,
+    /// since the serializer quotes with `"`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `enabled` - Whether to keep the source forms of attribute values
+    pub fn set_preserve_references(&mut self, enabled: bool) {
+        self.preserve_references = enabled;
+    }
+
+    /// Sets whether the tree keeps everything needed to reproduce the input.
+    /// 
+    /// In lossless mode comments, processing instructions, CDATA sections
+    /// and whitespace-only text inside the root element become nodes of
+    /// the tree, so serializing it gives back the original markup for
+    /// documents written in the serializer's style (double-quoted
+    /// attributes, no character references beyond the predefined ones;
+    /// `set_preserve_references` lifts the latter for attribute values).
+    /// 
+    /// # Arguments
+    /// 
+    /// * `enabled` - Whether to keep comments, PIs and CDATA sections
+    pub fn set_lossless(&mut self, enabled: bool) {
+        self.lossless = enabled;
+    }
+
+    /// Checks whether the element being built ends with a plain text node
+    /// that new character data would be merged into.
+    fn follows_text(&self) -> bool {
+        self.node_stack.last().is_some_and(|parent| {
+            parent.borrow().children.last().is_some_and(|last| {
+                let last = last.borrow();
+                last.node_type == crate::IksType::CData && !last.cdata_section
+            })
+        })
+    }
+
+    /// Adds a non-element node to the element being built.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `node` - The node to append
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` that fails if the memory limit is exceeded
+    fn append_leaf(&mut self, node: IksNode) -> Result<()> {
+        let Some(parent) = self.node_stack.last().cloned() else {
+            return Ok(());
+        };
+        let bytes = node.name.as_ref().map_or(0, String::len) + node.content.as_ref().map_or(0, String::len);
+        self.charge(bytes)?;
+        parent.borrow_mut().add_child(node);
+        Ok(())
+    }
+
+    /// Sets a callback invoked on every element once it is complete.
+    /// 
+    /// The callback runs when the closing tag has been read, so the element
+    /// already holds all of its attributes and children, and it may rewrite
+    /// the element in place, e.g. to rename it or drop attributes. This
+    /// allows transforming a document in the same pass that builds it.
+    /// Every element is passed to the callback before it is added to its
+    /// parent: while a hook is set, open elements are only linked into the
+    /// tree once they are complete, so `document` does not show them while
+    /// they are being parsed.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `hook` - The callback receiving each completed element
+    pub fn set_on_node_built<F: FnMut(&mut IksNode) + 'static>(&mut self, hook: F) {
+        self.on_node_built = Some(Box::new(hook));
+    }
+
+    /// Sets a callback reporting how far parsing has got.
+    /// 
+    /// Progress is checked whenever a tag has been read, so a progress bar
+    /// can follow a large parse. With `ProgressInterval::Bytes(n)` the
+    /// callback runs once each time the input consumed passes a multiple of
+    /// `n` bytes; with `ProgressInterval::Elements(n)` it runs after every
+    /// `n`th element. Without a callback no progress is tracked.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `interval` - How often to report; an interval of 0 is treated as 1
+    /// * `callback` - The callback receiving the progress
+    pub fn set_progress<F: FnMut(Progress) + 'static>(&mut self, interval: ProgressInterval, callback: F) {
+        let interval = match interval {
+            ProgressInterval::Bytes(n) => ProgressInterval::Bytes(n.max(1)),
+            ProgressInterval::Elements(n) => ProgressInterval::Elements(n.max(1)),
+        };
+        if let ProgressInterval::Bytes(n) = interval {
+            self.next_progress = (self.progress.bytes / n + 1) * n;
+        }
+        self.on_progress = Some((interval, Box::new(callback)));
+    }
+
+    /// Updates the progress after a tag and runs the callback when due.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `started` - Whether the tag started an element
+    fn report_progress(&mut self, started: bool) {
+        let Some((interval, callback)) = self.on_progress.as_mut() else {
+            return;
+        };
+        self.progress.bytes = self.tag_span.end.offset;
+        if started {
+            self.progress.elements += 1;
+        }
+        let due = match *interval {
+            ProgressInterval::Bytes(n) => {
+                let due = self.progress.bytes >= self.next_progress;
+                if due {
+                    self.next_progress = (self.progress.bytes / n + 1) * n;
+                }
+                due
+            }
+            ProgressInterval::Elements(n) => started && self.progress.elements.is_multiple_of(n),
+        };
+        if due {
+            callback(self.progress);
+        }
+    }
+
+    /// Enables or disables recording of element source locations.
+    /// 
+    /// When enabled, the span of every element built from then on is stored
+    /// on the node and can be queried with `IksNode::span` or `span`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `enabled` - Whether to record spans
+    pub fn set_track_spans(&mut self, enabled: bool) {
+        self.track_spans = enabled;
+    }
+
+    /// Limits the memory the tree may use.
+    /// 
+    /// Every node is charged for its own size plus the bytes of its name,
+    /// attributes and text. Once a node would push the total over the limit,
+    /// parsing fails with `IksError::NoMem` instead of growing the tree.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `max_bytes` - The maximum number of bytes, or `None` for no limit
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+    }
+
+    /// Gets the number of bytes charged against the memory limit so far.
+    /// 
+    /// # Returns
+    /// 
+    /// The approximate memory used by the tree in bytes
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    /// Charges a new node against the memory limit.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `bytes` - The size of the node's strings in bytes
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` that fails with `IksError::NoMem` if the limit is exceeded
+    fn charge(&mut self, bytes: usize) -> Result<()> {
+        let used = self.used_bytes + std::mem::size_of::<RefCell<IksNode>>() + bytes;
+        if self.max_bytes.is_some_and(|max| used > max) {
+            return Err(IksError::NoMem);
+        }
+        self.used_bytes = used;
+        Ok(())
+    }
+
+    /// Gets the source location of an element.
+    /// 
+    /// This is the same as `IksNode::span`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `node` - An element built by this parser
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the span if it was recorded
+    pub fn span(&self, node: &Rc<RefCell<IksNode>>) -> Option<Span> {
+        node.borrow().span()
+    }
+
     /// Gets the parsed document root node.
     /// 
     /// # Returns
@@ -79,6 +349,15 @@ impl DomParser {
         self.root.clone()
     }
 
+    /// Gets the document type declaration of the parsed document.
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the declaration if the document had one
+    pub fn doctype(&self) -> Option<&Doctype> {
+        self.doctype.as_ref()
+    }
+
     /// Parses an XML string into a DOM tree.
     /// 
     /// This is a convenience method that creates a new parser, parses the
@@ -92,7 +371,7 @@ impl DomParser {
     /// 
     /// A `Result` containing the root node of the DOM tree
     pub fn parse_str(xml: &str) -> Result<Rc<RefCell<IksNode>>> {
-        let mut parser = DomParser::new()?;
+        let parser = DomParser::new()?;
         let mut sax_parser = crate::Parser::new(parser);
         sax_parser.parse(xml)?;
         
@@ -100,10 +379,36 @@ impl DomParser {
         sax_parser.handler().document().ok_or(IksError::BadXml)
     }
 
+    /// Parses an XML string, keeping the tree built before an error.
+    /// 
+    /// Instead of discarding everything when the input turns out to be
+    /// broken or truncated, this returns the elements parsed up to the
+    /// error, which is useful for previews and recovery. Elements that were
+    /// open at the error are part of the tree with the children read so far.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `xml` - The XML string to parse
+    /// 
+    /// # Returns
+    /// 
+    /// A tuple of the root node, if any element was started, and the error
+    /// that stopped parsing, if any
+    pub fn parse_str_lenient(xml: &str) -> (Option<Rc<RefCell<IksNode>>>, Option<IksError>) {
+        let parser = match DomParser::new() {
+            Ok(parser) => parser,
+            Err(e) => return (None, Some(e)),
+        };
+        let mut sax_parser = crate::Parser::new(parser);
+        let error = sax_parser.parse_complete(xml).err();
+        (sax_parser.handler().document(), error)
+    }
+
     /// Loads and parses an XML file into a DOM tree.
     /// 
     /// This is a convenience method that reads a file and parses its contents
-    /// into a DOM tree.
+    /// into a DOM tree. The file is streamed through `Parser::parse_reader`
+    /// instead of being read into memory first.
     /// 
     /// # Arguments
     /// 
@@ -113,8 +418,48 @@ impl DomParser {
     /// 
     /// A `Result` containing the root node of the DOM tree
     pub fn load_file(path: &str) -> Result<Rc<RefCell<IksNode>>> {
-        let xml = std::fs::read_to_string(path)?;
-        Self::parse_str(&xml)
+        let file = std::fs::File::open(path)?;
+        let mut sax_parser = crate::Parser::new(DomParser::new()?);
+        sax_parser.parse_reader(file)?;
+        sax_parser.handler().document().ok_or(IksError::BadXml)
+    }
+
+    /// Loads an XML file and reads one value from it.
+    /// 
+    /// This packages `load_file` and `IksNode::find_path` for the common
+    /// case of reading a setting from a configuration file. The path is
+    /// relative to the root element. It selects an element, whose text is
+    /// returned, and may end in `@name` to get an attribute instead, as in
+    /// `server/@port`; a lone `@name` reads an attribute of the root.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `path` - Path to the XML file to parse
+    /// * `path_expr` - The path of the value in the document
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` containing the value, or `None` if the document has no
+    /// such element or attribute
+    pub fn read_config_value(path: &str, path_expr: &str) -> Result<Option<String>> {
+        let root = Self::load_file(path)?;
+        let (element_path, attribute) = match path_expr.rsplit_once('/') {
+            Some((element_path, last)) if last.starts_with('@') => (element_path, Some(&last[1..])),
+            None if path_expr.starts_with('@') => ("", Some(&path_expr[1..])),
+            _ => (path_expr, None),
+        };
+        let element = match element_path {
+            "" => Some(root),
+            element_path => root.borrow().find_path(element_path),
+        };
+        let Some(element) = element else {
+            return Ok(None);
+        };
+        let element = element.borrow();
+        Ok(match attribute {
+            Some(name) => element.find_attrib(name).map(str::to_string),
+            None => Some(element.text_content()),
+        })
     }
 
     /// Saves a DOM tree to an XML file.
@@ -137,6 +482,8 @@ impl DomParser {
 }
 
 impl SaxHandler for DomParser {
+    type Error = IksError;
+
     /// Handles tag events during parsing.
     /// 
     /// This method creates new nodes for tags and maintains the parent-child
@@ -152,8 +499,11 @@ impl SaxHandler for DomParser {
     /// 
     /// A `Result` indicating success or failure
     fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+        self.pending_blank.clear();
         match tag_type {
             TagType::Open | TagType::Single => {
+                let strings = attributes.iter().map(|(n, v)| n.len() + v.len()).sum::<usize>();
+                self.charge(name.len() + strings)?;
                 let mut node = IksNode::new_tag(name);
                 
                 // Pre-allocate attributes vector with capacity
@@ -163,26 +513,52 @@ impl SaxHandler for DomParser {
                 for (attr, value) in attributes {
                     node.add_attribute(attr, value);
                 }
+                for (attr, raw) in self.attribute_sources.drain(..) {
+                    if let Some(value) = node.find_attrib(&attr) {
+                        let value = value.to_string();
+                        node.attribute_sources.push(crate::AttributeSource { name: attr, value, raw });
+                    }
+                }
+                node.valueless_attributes.append(&mut self.valueless_attributes);
+                if self.track_spans {
+                    node.span = Some(self.tag_span);
+                }
+                if tag_type == TagType::Single {
+                    if let Some(hook) = self.on_node_built.as_mut() {
+                        hook(&mut node);
+                    }
+                }
                 
-                let node_rc = Rc::new(RefCell::new(node));
+                let node_rc = node.into_rc();
 
                 if let Some(parent_rc) = self.node_stack.last() {
-                    node_rc.borrow_mut().parent = Some(Rc::downgrade(parent_rc));
-                    parent_rc.borrow_mut().children.push(node_rc.clone());
-                    if tag_type == TagType::Open {
-                        self.node_stack.push(node_rc);
+                    // With a hook, open elements are linked once the hook has run
+                    if tag_type == TagType::Single || self.on_node_built.is_none() {
+                        IksNode::append_child(parent_rc, node_rc.clone());
                     }
                 } else {
                     self.root = Some(node_rc.clone());
-                    if tag_type == TagType::Open {
-                        self.node_stack.push(node_rc);
-                    }
+                }
+                if tag_type == TagType::Open {
+                    self.node_stack.push(node_rc);
                 }
             },
             TagType::Close => {
                 if let Some(current) = self.node_stack.last() {
-                    if current.borrow().name.as_ref().map_or(false, |n| n == name) {
-                        self.node_stack.pop();
+                    if current.borrow().name.as_deref() == Some(name) {
+                        if let Some(span) = current.borrow_mut().span.as_mut() {
+                            span.end = self.tag_span.end;
+                        }
+                        if let Some(hook) = self.on_node_built.as_mut() {
+                            hook(&mut current.borrow_mut());
+                            let node_rc = current.clone();
+                            self.node_stack.pop();
+                            if let Some(parent_rc) = self.node_stack.last() {
+                                IksNode::append_child(parent_rc, node_rc);
+                            }
+                        } else {
+                            self.node_stack.pop();
+                        }
                     } else {
                         // Only return error if we're not at the root level
                         if !self.node_stack.is_empty() {
@@ -192,13 +568,17 @@ impl SaxHandler for DomParser {
                 }
             },
         }
+        self.report_progress(tag_type != TagType::Close);
         Ok(())
     }
     
     /// Handles character data events during parsing.
     /// 
     /// This method creates text nodes for character data and adds them to
-    /// the current parent node.
+    /// the current parent node. Text following another text node, e.g.
+    /// after a chunk boundary, is merged into it. Whitespace at the start of
+    /// a run is held back until the run turns out to contain other text,
+    /// so a chunk boundary cannot make part of the text look blank.
     /// 
     /// # Arguments
     /// 
@@ -208,21 +588,138 @@ impl SaxHandler for DomParser {
     /// 
     /// A `Result` indicating success or failure
     fn on_cdata(&mut self, data: &str) -> Result<()> {
+        let keep_blank = self.preserve_whitespace || self.lossless;
+        if self.node_stack.is_empty() {
+            return Ok(());
+        }
+        let pending;
+        let data = if !keep_blank && crate::utility::is_blank(data) {
+            if !self.follows_text() {
+                self.pending_blank.push_str(data);
+                return Ok(());
+            }
+            data
+        } else if self.pending_blank.is_empty() {
+            data
+        } else {
+            pending = std::mem::take(&mut self.pending_blank) + data;
+            pending.as_str()
+        };
+        self.charge(data.len())?;
         if let Some(parent) = self.node_stack.last() {
-            if !data.trim().is_empty() {
-                let mut cdata = IksNode::new(crate::IksType::CData);
-                cdata.set_content(data);
-                parent.borrow_mut().add_child(cdata);
+            let mut parent = parent.borrow_mut();
+            if let Some(last) = parent.children.last() {
+                let mut last = last.borrow_mut();
+                if last.node_type == crate::IksType::CData && !last.cdata_section {
+                    last.content.get_or_insert_with(String::new).push_str(data);
+                    return Ok(());
+                }
             }
+            parent.add_child(IksNode::text(data));
+        }
+        Ok(())
+    }
+
+    fn on_cdata_section(&mut self, data: &str) -> Result<()> {
+        if self.lossless {
+            self.append_leaf(IksNode::new_cdata_section(data))
+        } else {
+            self.on_cdata(data)
+        }
+    }
+
+    fn on_comment(&mut self, text: &str) -> Result<()> {
+        if self.lossless {
+            self.append_leaf(IksNode::new_comment(text))?;
+        }
+        Ok(())
+    }
+
+    fn on_pi(&mut self, target: &str, data: &str) -> Result<()> {
+        if self.lossless {
+            self.append_leaf(IksNode::new_pi(target, data))?;
         }
         Ok(())
     }
+
+    fn on_doctype(&mut self, doctype: &Doctype) -> Result<()> {
+        self.doctype = Some(doctype.clone());
+        Ok(())
+    }
+
+    fn on_tag_span(&mut self, span: Span) {
+        self.tag_span = span;
+    }
+
+    fn on_attribute_source(&mut self, name: &str, raw: &str) {
+        if self.preserve_references && !raw.contains('"') {
+            self.attribute_sources.push((name.to_string(), raw.to_string()));
+        }
+    }
+
+    fn on_valueless_attribute(&mut self, name: &str) {
+        self.valueless_attributes.push(name.to_string());
+    }
+}
+
+impl crate::Parser<DomParser> {
+    /// Finishes a chunked parse and returns the built tree.
+    /// 
+    /// This is `finish` followed by `DomParser::document`, for callers that
+    /// feed a `DomParser` through a SAX parser themselves and then hold on
+    /// to the tree.
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` containing the root node, the error of `finish`, or
+    /// `IksError::BadXml` if the input held no element
+    pub fn finish_document(&mut self) -> Result<Rc<RefCell<IksNode>>> {
+        self.finish()?;
+        self.handler().document().ok_or(IksError::BadXml)
+    }
+}
+
+/// Builds a DOM tree from a stream of SAX events.
+/// 
+/// The events are fed to a lossless `DomParser`, so comments, processing
+/// instructions and whitespace-only text in the stream all become nodes.
+/// This lets events from another tokenizer, or from `IksNode::to_events`,
+/// be turned into a tree without going through XML text.
+/// 
+/// # Arguments
+/// 
+/// * `events` - The events of one document, in order
+/// 
+/// # Returns
+/// 
+/// A `Result` containing the root node, `IksError::BadXml` for end tags
+/// that do not match or a stream without an element, and
+/// `IksError::UnexpectedEof` if elements are left open
+pub fn build_dom<I: IntoIterator<Item = Event>>(events: I) -> Result<Rc<RefCell<IksNode>>> {
+    let mut dom = DomParser::new()?;
+    dom.set_lossless(true);
+    for event in events {
+        if matches!(event, Event::EndTag(_)) && dom.node_stack.is_empty() {
+            return Err(IksError::BadXml);
+        }
+        event.dispatch(&mut dom)?;
+    }
+    if !dom.node_stack.is_empty() {
+        return Err(IksError::UnexpectedEof);
+    }
+    dom.document().ok_or(IksError::BadXml)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Gets a path in the temporary directory unique to a test and process,
+    /// so tests running in parallel or concurrent test runs do not clash.
+    fn temp_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("iksemel-{}-{}.xml", name, std::process::id()))
+    }
+
     #[test]
     fn test_dom_child(){
         let xml = r#"
@@ -277,6 +774,200 @@ mod tests {
         assert!(child3.children.is_empty());
     }
     
+    #[test]
+    fn test_element_spans() {
+        let xml = "<root>\n  <a><b x='1'>text</b></a>\n</root>";
+        let mut dom = DomParser::new().unwrap();
+        dom.set_track_spans(true);
+        let mut parser = crate::Parser::new(dom);
+        parser.parse(xml).unwrap();
+
+        let dom = parser.handler();
+        let root = dom.document().unwrap();
+        let a = root.borrow().find("a").unwrap();
+        let b = a.borrow().find("b").unwrap();
+
+        let span = dom.span(&b).unwrap();
+        assert_eq!(&xml[span.start.offset..span.end.offset], "<b x='1'>text</b>");
+        assert_eq!((span.start.line, span.start.column), (2, 6));
+        assert_eq!((span.end.line, span.end.column), (2, 22));
+
+        let span = dom.span(&root).unwrap();
+        assert_eq!((span.start.offset, span.end.offset), (0, xml.len()));
+        assert_eq!(root.borrow().span(), Some(span));
+
+        let copy = b.borrow().deep_clone();
+        assert_eq!(copy.span(), dom.span(&b));
+        drop(b);
+        a.borrow_mut().clear_children();
+        let fresh = IksNode::new_tag("b").into_rc();
+        assert_eq!(dom.span(&fresh), None);
+    }
+
+    #[test]
+    fn test_preserve_references() {
+        let xml = "<doc title=\"a &#38; b\" alt='say \"hi\" &amp; go'><item k=\"&lt;&#x3E;\" plain=\"x\"/></doc>";
+        let mut dom = DomParser::new().unwrap();
+        dom.set_preserve_references(true);
+        let mut parser = crate::Parser::new(dom);
+        parser.parse_complete(xml).unwrap();
+        let root = parser.handler().document().unwrap();
+        assert_eq!(root.borrow().find_attrib("title"), Some("a & b"));
+        assert_eq!(root.borrow().attribute_source("title"), Some("a &#38; b"));
+        assert_eq!(
+            root.borrow().to_string(),
+            "<doc title=\"a &#38; b\" alt=\"say &quot;hi&quot; &amp; go\"><item k=\"&lt;&#x3E;\" plain=\"x\"/></doc>"
+        );
+
+        let item = root.borrow().find("item").unwrap();
+        item.borrow_mut().drain_attributes().for_each(drop);
+        item.borrow_mut().add_attribute("k", "new");
+        assert_eq!(item.borrow().attribute_source("k"), None);
+        assert_eq!(item.borrow().to_string(), "<item k=\"new\"/>");
+
+        let canonical = DomParser::parse_str(xml).unwrap();
+        assert_eq!(canonical.borrow().attribute_source("title"), None);
+        assert!(canonical.borrow().to_string().starts_with("<doc title=\"a &amp; b\""));
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let xml = "<config version=\"2\">\n  <!-- connection settings -->\n  <?reload on-change?>\n  \
+                   <host>example.com</host>\n  <script><![CDATA[if (a < b && c) { go(); }]]></script>\n  \
+                   <note>a &amp; b<!--inline--></note>\n</config>";
+
+        let mut dom = DomParser::new().unwrap();
+        dom.set_lossless(true);
+        let mut parser = crate::Parser::new(dom);
+        parser.parse_complete(xml).unwrap();
+        let root = parser.handler().document().unwrap();
+        assert_eq!(root.borrow().to_string(), xml);
+
+        let plain = DomParser::parse_str(xml).unwrap();
+        assert_eq!(
+            plain.borrow().to_string(),
+            "<config version=\"2\"><host>example.com</host><script>if (a &lt; b &amp;&amp; c) { go(); }</script>\
+             <note>a &amp; b</note></config>"
+        );
+        assert_eq!(plain.borrow().find_cdata("script").as_deref(), Some("if (a < b && c) { go(); }"));
+    }
+
+    #[test]
+    fn test_text_runs() {
+        let xml = "<a>x &amp; y<![CDATA[<raw>]]><![CDATA[ & more]]> tail<b/>after</a>";
+
+        let root = DomParser::parse_str(xml).unwrap();
+        let root = root.borrow();
+        assert_eq!(root.children.len(), 3);
+        assert_eq!(root.children[0].borrow().content(), Some("x & y<raw> & more tail"));
+        assert_eq!(root.to_string(), "<a>x &amp; y&lt;raw&gt; &amp; more tail<b/>after</a>");
+
+        let mut dom = DomParser::new().unwrap();
+        dom.set_lossless(true);
+        let mut parser = crate::Parser::new(dom);
+        parser.parse_complete(xml).unwrap();
+        let lossless = parser.handler().document().unwrap();
+        let lossless = lossless.borrow();
+        let kinds: Vec<_> = lossless.children()
+            .map(|child| (child.borrow().is_cdata_section(), child.borrow().content().map(String::from)))
+            .collect();
+        assert_eq!(kinds, [
+            (false, Some("x & y".to_string())),
+            (true, Some("<raw>".to_string())),
+            (true, Some(" & more".to_string())),
+            (false, Some(" tail".to_string())),
+            (false, None),
+            (false, Some("after".to_string())),
+        ]);
+        assert_eq!(lossless.to_string(), xml);
+    }
+
+    #[test]
+    fn test_node_built_hook() {
+        let mut dom = DomParser::new().unwrap();
+        dom.set_on_node_built(|node| {
+            let name = node.name().map(str::to_uppercase);
+            node.name = name;
+        });
+        let mut parser = crate::Parser::new(dom);
+        parser.parse_complete("<root><item id=\"1\">one</item><item id=\"2\"/><group><sub/></group></root>").unwrap();
+        let root = parser.handler().document().unwrap();
+        assert_eq!(
+            root.borrow().to_string(),
+            "<ROOT><ITEM id=\"1\">one</ITEM><ITEM id=\"2\"/><GROUP><SUB/></GROUP></ROOT>"
+        );
+
+        let plain = DomParser::parse_str("<root><item/></root>").unwrap();
+        assert_eq!(plain.borrow().to_string(), "<root><item/></root>");
+
+        let unlinked = Rc::new(RefCell::new(Vec::new()));
+        let seen = unlinked.clone();
+        let mut dom = DomParser::new().unwrap();
+        dom.set_on_node_built(move |node| {
+            seen.borrow_mut().push((node.name().unwrap().to_string(), node.parent().is_none()));
+        });
+        let mut parser = crate::Parser::new(dom);
+        parser.parse_complete("<root><group><sub/>text</group><empty/></root>").unwrap();
+        let expected = [("sub", true), ("group", true), ("empty", true), ("root", true)];
+        assert_eq!(*unlinked.borrow(), expected.map(|(name, orphan)| (name.to_string(), orphan)));
+        let root = parser.handler().document().unwrap();
+        assert_eq!(root.borrow().to_string(), "<root><group><sub/>text</group><empty/></root>");
+        let group = root.borrow().find("group").unwrap();
+        assert!(Rc::ptr_eq(&group.borrow().parent().unwrap(), &root));
+        assert_eq!(group.borrow().next().unwrap().borrow().name(), Some("empty"));
+    }
+
+    #[test]
+    fn test_progress() {
+        use std::cell::Cell;
+
+        // 8 bytes per item plus 15 for the root tags
+        let xml = format!("<items>{}</items>", "<item/> ".repeat(20));
+
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let mut dom = DomParser::new().unwrap();
+        let seen = reports.clone();
+        dom.set_progress(ProgressInterval::Elements(5), move |progress| seen.borrow_mut().push(progress.elements));
+        let mut parser = crate::Parser::new(dom);
+        parser.parse(&xml).unwrap();
+        assert_eq!(*reports.borrow(), [5, 10, 15, 20]);
+
+        let count = Rc::new(Cell::new(0));
+        let mut dom = DomParser::new().unwrap();
+        let fired = count.clone();
+        dom.set_progress(ProgressInterval::Bytes(40), move |progress| {
+            assert!(progress.bytes >= 40 * (fired.get() + 1));
+            fired.set(fired.get() + 1);
+        });
+        let mut parser = crate::Parser::new(dom);
+        for chunk in xml.as_bytes().chunks(16) {
+            parser.parse(std::str::from_utf8(chunk).unwrap()).unwrap();
+        }
+        assert_eq!(xml.len(), 175);
+        assert_eq!(count.get(), 4);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let xml = format!("<root>{}</root>", "<item key=\"value\">some text</item>".repeat(100));
+
+        let mut dom = DomParser::new().unwrap();
+        dom.set_max_bytes(Some(1024));
+        let mut parser = crate::Parser::new(dom);
+        assert!(matches!(parser.parse(&xml), Err(IksError::NoMem)));
+        assert!(parser.handler().used_bytes() <= 1024);
+
+        let mut parser = crate::Parser::new(DomParser::new().unwrap());
+        parser.parse(&xml).unwrap();
+        let used = parser.handler().used_bytes();
+        assert!(used > 1024);
+
+        let mut dom = DomParser::new().unwrap();
+        dom.set_max_bytes(Some(used));
+        let mut parser = crate::Parser::new(dom);
+        parser.parse(&xml).unwrap();
+    }
+
     #[test]
     fn test_file_operations() -> Result<()> {
         let root = Rc::new(RefCell::new(IksNode::new_tag("root")));
@@ -289,7 +980,7 @@ mod tests {
         root.borrow_mut().add_child(child);
         
         // Save to file
-        let temp_path = std::env::temp_dir().join("test.xml");
+        let temp_path = temp_file("save_load");
         DomParser::save_file(&root, temp_path.to_str().unwrap())?;
         
         // Load from file
@@ -305,4 +996,146 @@ mod tests {
         
         Ok(())
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_finish_document() -> Result<()> {
+        let mut parser = crate::Parser::new(DomParser::new()?);
+        parser.parse_chunks(["<doc><item>a</it", "em><item/></doc>"].iter())?;
+        let root = parser.finish_document()?;
+        assert_eq!(root.borrow().to_string(), "<doc><item>a</item><item/></doc>");
+        assert!(Rc::ptr_eq(&root, &parser.handler().document().unwrap()));
+
+        let mut parser = crate::Parser::new(DomParser::new()?);
+        parser.parse("<doc><item>")?;
+        assert!(matches!(parser.finish_document(), Err(IksError::UnexpectedEof)));
+
+        let mut parser = crate::Parser::new(DomParser::new()?);
+        parser.parse("<!-- nothing -->")?;
+        assert!(matches!(parser.finish_document(), Err(IksError::BadXml)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_value() -> Result<()> {
+        let xml = "<config version=\"2\">\n  <server name=\"main\" port=\"8080\">\n    <host>example.com</host>\n  </server>\n\
+                   <server name=\"backup\" port=\"9090\"><host>b.example.com</host></server>\n</config>";
+        let temp_path = temp_file("read_config");
+        std::fs::write(&temp_path, xml)?;
+        let path = temp_path.to_str().unwrap();
+        let read = |expr: &str| DomParser::read_config_value(path, expr);
+
+        let values = (
+            read("server/host"),
+            read("server[@name='backup']/host"),
+            read("server[@name='backup']/@port"),
+            read("@version"),
+            read("server/@missing"),
+            read("client/host"),
+        );
+        std::fs::remove_file(&temp_path)?;
+
+        assert_eq!(values.0?.as_deref(), Some("example.com"));
+        assert_eq!(values.1?.as_deref(), Some("b.example.com"));
+        assert_eq!(values.2?.as_deref(), Some("9090"));
+        assert_eq!(values.3?.as_deref(), Some("2"));
+        assert_eq!(values.4?, None);
+        assert_eq!(values.5?, None);
+        assert!(DomParser::read_config_value(path, "server/host").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_large_file() -> Result<()> {
+        let item = "<item id=\"ü\">text &amp; more <b>çok</b></item>\n";
+        let xml = format!("<root>\n{}</root>", item.repeat(2000));
+        assert!(xml.len() > 10 * memory::FILE_BUFFER_SIZE);
+
+        let temp_path = temp_file("load_large");
+        std::fs::write(&temp_path, &xml)?;
+        let loaded = DomParser::load_file(temp_path.to_str().unwrap());
+        std::fs::remove_file(&temp_path)?;
+
+        let loaded = loaded?;
+        let parsed = DomParser::parse_str(&xml)?;
+        assert_eq!(loaded.borrow().child_count(), 2000);
+        assert!(loaded.borrow().tree_eq(&parsed.borrow()));
+        assert_eq!(loaded.borrow().to_string(), parsed.borrow().to_string());
+
+        let truncated = temp_file("load_truncated");
+        std::fs::write(&truncated, &xml[..xml.len() - 3])?;
+        let result = DomParser::load_file(truncated.to_str().unwrap());
+        std::fs::remove_file(&truncated)?;
+        assert!(matches!(result, Err(IksError::UnexpectedEof)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_str_lenient() {
+        let (root, error) = DomParser::parse_str_lenient("<list><item>one</item><item>two</item><item a=b>three</item></list>");
+        assert!(matches!(error, Some(IksError::BadAttribute { .. })));
+        let root = root.unwrap();
+        assert_eq!(root.borrow().to_string(), "<list><item>one</item><item>two</item></list>");
+
+        let (root, error) = DomParser::parse_str_lenient("<list><item>one</item><item>tw");
+        assert!(matches!(error, Some(IksError::UnexpectedEof)));
+        assert_eq!(root.unwrap().borrow().to_string(), "<list><item>one</item><item>tw</item></list>");
+
+        let (root, error) = DomParser::parse_str_lenient("<list><item/></list>");
+        assert!(error.is_none());
+        assert_eq!(root.unwrap().borrow().to_string(), "<list><item/></list>");
+
+        let (root, error) = DomParser::parse_str_lenient("<<");
+        assert!(root.is_none() && error.is_some());
+    }
+
+    #[test]
+    fn test_build_dom_from_events() {
+        let start = |name: &str, attributes: &[(&str, &str)]| Event::StartTag {
+            name: name.to_string(),
+            attributes: attributes.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect(),
+        };
+        let events = vec![
+            start("message", &[("to", "juliet@example.com")]),
+            start("body", &[]),
+            Event::Text("Art thou not Romeo?".to_string()),
+            Event::EndTag("body".to_string()),
+            Event::Comment(" sent by hand ".to_string()),
+            start("thread", &[]),
+            Event::EndTag("thread".to_string()),
+            Event::EndTag("message".to_string()),
+        ];
+        let root = build_dom(events.clone()).unwrap();
+        assert_eq!(
+            root.borrow().to_string(),
+            "<message to=\"juliet@example.com\"><body>Art thou not Romeo?</body><!-- sent by hand --><thread/></message>"
+        );
+        assert_eq!(root.borrow().to_events(), events);
+
+        assert!(matches!(build_dom(events[..3].to_vec()), Err(IksError::UnexpectedEof)));
+        assert!(matches!(build_dom(vec![start("a", &[]), Event::EndTag("b".to_string())]), Err(IksError::BadXml)));
+        assert!(matches!(build_dom(vec![Event::EndTag("a".to_string())]), Err(IksError::BadXml)));
+        assert!(matches!(build_dom(vec![Event::Text("x".to_string())]), Err(IksError::BadXml)));
+    }
+
+    #[test]
+    fn test_text_split_at_whitespace() {
+        let xml = "<e>günlüğü №1 🦀<b/> </e>";
+        let bytes = xml.as_bytes();
+        for a in 0..bytes.len() {
+            for b in a..bytes.len() {
+                let mut parser = crate::Parser::new(DomParser::new().unwrap());
+                parser.parse_bytes(&bytes[..a]).unwrap();
+                parser.parse_bytes(&bytes[a..b]).unwrap();
+                parser.parse_bytes(&bytes[b..]).unwrap();
+                let root = parser.handler().document().unwrap();
+                assert_eq!(root.borrow().to_string(), "<e>günlüğü №1 🦀<b/></e>", "split at {} and {}", a, b);
+            }
+        }
+
+        let mut parser = crate::Parser::new(DomParser::new().unwrap());
+        for chunk in ["<p>", "  ", "\n", "x", " ", "</p>"] {
+            parser.parse(chunk).unwrap();
+        }
+        assert_eq!(parser.handler().document().unwrap().borrow().to_string(), "<p>  \nx </p>");
+    }
+}
@@ -0,0 +1,248 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::{IksNode, IksType, Result, SaxHandler, TagType};
+
+/// A single SAX event, owning its data.
+///
+/// A sequence of events describes a document the same way the callbacks of
+/// a `SaxHandler` do, so it can be stored, compared or replayed later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// An element was opened
+    StartTag {
+        /// Name of the element
+        name: String,
+        /// Attributes as (name, value) pairs, in source order
+        attributes: Vec<(String, String)>,
+    },
+    /// An element was closed, given by its name
+    EndTag(String),
+    /// Character data
+    Text(String),
+    /// The text inside a `<![CDATA[ ... ]]>` section
+    CData(String),
+    /// A comment, with the text between `<!--` and `-->`
+    Comment(String),
+    /// A processing instruction
+    Pi {
+        /// The name following `<?`
+        target: String,
+        /// The rest of the instruction
+        data: String,
+    },
+}
+
+impl Event {
+    /// Delivers this event to a handler.
+    ///
+    /// Start and end tags are reported as `TagType::Open` and
+    /// `TagType::Close`; an empty element is an opening tag directly
+    /// followed by its closing tag.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler to call
+    ///
+    /// # Returns
+    ///
+    /// The result of the handler callback
+    pub fn dispatch<H: SaxHandler>(&self, handler: &mut H) -> Result<(), H::Error> {
+        match self {
+            Event::StartTag { name, attributes } => handler.on_tag(name, attributes, TagType::Open),
+            Event::EndTag(name) => handler.on_tag(name, &[], TagType::Close),
+            Event::Text(text) => handler.on_cdata(text),
+            Event::CData(text) => handler.on_cdata_section(text),
+            Event::Comment(text) => handler.on_comment(text),
+            Event::Pi { target, data } => handler.on_pi(target, data),
+        }
+    }
+}
+
+/// A pending step of the event collector's work stack.
+enum Step {
+    /// Emit the events of a node and queue its children
+    Start(Rc<RefCell<IksNode>>),
+    /// Emit the end tag of an element
+    End(String),
+}
+
+/// Lists the events that describe a node and its subtree.
+///
+/// # Arguments
+///
+/// * `node` - The root of the subtree
+///
+/// # Returns
+///
+/// The events in document order
+pub(crate) fn collect(node: &IksNode) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut stack = Vec::new();
+    push_node(node, &mut events, &mut stack);
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Start(child) => push_node(&child.borrow(), &mut events, &mut stack),
+            Step::End(name) => events.push(Event::EndTag(name)),
+        }
+    }
+    events
+}
+
+/// Emits the events of a single node and queues the work for its children.
+fn push_node(node: &IksNode, events: &mut Vec<Event>, stack: &mut Vec<Step>) {
+    let content = || node.content.clone().unwrap_or_default();
+    match node.node_type {
+        IksType::Tag => {
+            let name = node.name.clone().unwrap_or_default();
+            events.push(Event::StartTag {
+                name: name.clone(),
+                attributes: node.attributes.clone(),
+            });
+            if let Some(content) = &node.content {
+                events.push(Event::Text(content.clone()));
+            }
+            stack.push(Step::End(name));
+            stack.extend(node.children.iter().rev().cloned().map(Step::Start));
+        }
+        IksType::CData if node.cdata_section => events.push(Event::CData(content())),
+        IksType::CData if node.content.is_some() => events.push(Event::Text(content())),
+        IksType::Comment => events.push(Event::Comment(content())),
+        IksType::Pi => events.push(Event::Pi {
+            target: node.name.clone().unwrap_or_default(),
+            data: content(),
+        }),
+        IksType::None => stack.extend(node.children.iter().rev().cloned().map(Step::Start)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DomParser, IksError, Parser};
+
+    #[derive(Default)]
+    struct CollectingHandler {
+        events: Vec<Event>,
+    }
+
+    impl SaxHandler for CollectingHandler {
+        type Error = IksError;
+
+        fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+            let start = Event::StartTag {
+                name: name.to_string(),
+                attributes: attributes.to_vec(),
+            };
+            match tag_type {
+                TagType::Open => self.events.push(start),
+                TagType::Close => self.events.push(Event::EndTag(name.to_string())),
+                TagType::Single => {
+                    self.events.push(start);
+                    self.events.push(Event::EndTag(name.to_string()));
+                }
+            }
+            Ok(())
+        }
+
+        fn on_cdata(&mut self, data: &str) -> Result<()> {
+            self.events.push(Event::Text(data.to_string()));
+            Ok(())
+        }
+
+        fn on_cdata_section(&mut self, data: &str) -> Result<()> {
+            self.events.push(Event::CData(data.to_string()));
+            Ok(())
+        }
+
+        fn on_comment(&mut self, text: &str) -> Result<()> {
+            self.events.push(Event::Comment(text.to_string()));
+            Ok(())
+        }
+
+        fn on_pi(&mut self, target: &str, data: &str) -> Result<()> {
+            self.events.push(Event::Pi {
+                target: target.to_string(),
+                data: data.to_string(),
+            });
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_events_round_trip() {
+        let xml = "<a x=\"1\">hi &amp; bye<b/><!-- note --><?app run?><c y=\"2\">text</c></a>";
+        let mut dom = DomParser::new().unwrap();
+        dom.set_lossless(true);
+        let mut parser = Parser::new(dom);
+        parser.parse(xml).unwrap();
+        let root = parser.handler().document().unwrap();
+
+        let events = root.borrow().to_events();
+        let start = |name: &str, attributes: &[(&str, &str)]| Event::StartTag {
+            name: name.to_string(),
+            attributes: attributes.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect(),
+        };
+        assert_eq!(events, [
+            start("a", &[("x", "1")]),
+            Event::Text("hi & bye".to_string()),
+            start("b", &[]),
+            Event::EndTag("b".to_string()),
+            Event::Comment(" note ".to_string()),
+            Event::Pi { target: "app".to_string(), data: "run".to_string() },
+            start("c", &[("y", "2")]),
+            Event::Text("text".to_string()),
+            Event::EndTag("c".to_string()),
+            Event::EndTag("a".to_string()),
+        ]);
+
+        let mut collector = CollectingHandler::default();
+        root.borrow().drive(&mut collector).unwrap();
+        assert_eq!(collector.events, events);
+
+        let mut parsed = Parser::new(CollectingHandler::default());
+        parsed.parse(xml).unwrap();
+        assert_eq!(parsed.handler().events, events);
+
+        let mut rebuilt = DomParser::new().unwrap();
+        rebuilt.set_lossless(true);
+        root.borrow().drive(&mut rebuilt).unwrap();
+        assert_eq!(*rebuilt.document().unwrap().borrow(), *root.borrow());
+    }
+
+    #[test]
+    fn test_cdata_events() {
+        let xml = "<a>x <![CDATA[<raw> & ]]> y</a>";
+        let mut parsed = Parser::new(CollectingHandler::default());
+        parsed.parse(xml).unwrap();
+        let events = &parsed.handler().events;
+        assert_eq!(events[1..4], [
+            Event::Text("x ".to_string()),
+            Event::CData("<raw> & ".to_string()),
+            Event::Text(" y".to_string()),
+        ]);
+
+        let mut dom = DomParser::new().unwrap();
+        dom.set_lossless(true);
+        for event in events {
+            event.dispatch(&mut dom).unwrap();
+        }
+        let root = dom.document().unwrap();
+        assert!(root.borrow().child_at(1).unwrap().borrow().is_cdata_section());
+        assert_eq!(root.borrow().to_string(), xml);
+        assert_eq!(root.borrow().to_events(), *events);
+    }
+}
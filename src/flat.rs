@@ -0,0 +1,491 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::ops::Range;
+use crate::{IksError, IksType, Parser, Result, SaxHandler, TagType};
+use crate::utility::is_blank;
+
+/// A node of a `FlatDocument`.
+///
+/// Nodes are stored in document order, so the descendants of a node are
+/// the nodes right after it, up to the index returned by `end`. Names,
+/// text and attributes live in buffers shared by the whole document and
+/// are reached through it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatNode {
+    node_type: IksType,
+    parent: Option<usize>,
+    /// Index just past the last descendant
+    end: usize,
+    /// The name of an element or the content of a text node, in `strings`
+    value: Range<usize>,
+    /// The attributes of an element, in `attributes`
+    attributes: Range<usize>,
+}
+
+impl FlatNode {
+    /// Gets the type of this node.
+    ///
+    /// # Returns
+    ///
+    /// `IksType::Tag` for elements, `IksType::CData` for text
+    pub fn node_type(&self) -> IksType {
+        self.node_type
+    }
+
+    /// Gets the index of the parent of this node.
+    ///
+    /// # Returns
+    ///
+    /// The parent index, or `None` for a top-level element
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    /// Gets the index just past the subtree of this node.
+    ///
+    /// # Returns
+    ///
+    /// The index of the next node that is not a descendant
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
+
+/// A read-only document stored as a flat arena of nodes.
+///
+/// Unlike the `IksNode` tree, which links `Rc<RefCell<IksNode>>` cells,
+/// the nodes sit next to each other in one vector and refer to each other
+/// by index. Walking a subtree is a scan over a contiguous range, which
+/// suits documents that are parsed once and queried many times. Elements
+/// and text are kept; comments, processing instructions and
+/// whitespace-only text are dropped, as `DomParser` does by default.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::FlatParser;
+///
+/// let doc = FlatParser::parse_str("<config><server port='80'>main</server><server port='81'/></config>").unwrap();
+/// let root = doc.root().unwrap();
+/// let ports: Vec<_> = doc.children(root).filter_map(|server| doc.attr(server, "port")).collect();
+/// assert_eq!(ports, ["80", "81"]);
+/// assert_eq!(doc.find(root, "server").map(|server| doc.text_content(server)).as_deref(), Some("main"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlatDocument {
+    nodes: Vec<FlatNode>,
+    strings: String,
+    attributes: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl FlatDocument {
+    /// Gets all nodes in document order.
+    ///
+    /// # Returns
+    ///
+    /// A slice of the nodes
+    pub fn nodes(&self) -> &[FlatNode] {
+        &self.nodes
+    }
+
+    /// Gets the number of nodes.
+    ///
+    /// # Returns
+    ///
+    /// The number of elements and text nodes
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Checks whether the document has no nodes.
+    ///
+    /// # Returns
+    ///
+    /// `true` if no element was parsed
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Gets the index of the root element.
+    ///
+    /// # Returns
+    ///
+    /// The index of the first top-level element, or `None` if the document
+    /// is empty
+    pub fn root(&self) -> Option<usize> {
+        (!self.nodes.is_empty()).then_some(0)
+    }
+
+    /// Gets a node by index.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the node
+    ///
+    /// # Returns
+    ///
+    /// The node, or `None` if the index is out of range
+    pub fn node(&self, idx: usize) -> Option<&FlatNode> {
+        self.nodes.get(idx)
+    }
+
+    /// Gets the name of an element.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the node
+    ///
+    /// # Returns
+    ///
+    /// The element name, or `None` for text nodes and indices out of range
+    pub fn name(&self, idx: usize) -> Option<&str> {
+        let node = self.nodes.get(idx)?;
+        (node.node_type == IksType::Tag).then(|| &self.strings[node.value.clone()])
+    }
+
+    /// Gets the content of a text node.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the node
+    ///
+    /// # Returns
+    ///
+    /// The text, or `None` for elements and indices out of range
+    pub fn text(&self, idx: usize) -> Option<&str> {
+        let node = self.nodes.get(idx)?;
+        (node.node_type == IksType::CData).then(|| &self.strings[node.value.clone()])
+    }
+
+    /// Gets the value of an attribute of an element.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the element
+    /// * `name` - The attribute name
+    ///
+    /// # Returns
+    ///
+    /// The value, or `None` if the node has no such attribute
+    pub fn attr(&self, idx: usize, name: &str) -> Option<&str> {
+        self.attributes(idx).find(|&(n, _)| n == name).map(|(_, value)| value)
+    }
+
+    /// Iterates over the attributes of an element.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the element
+    ///
+    /// # Returns
+    ///
+    /// An iterator of (name, value) pairs in source order, empty for an
+    /// index out of range
+    pub fn attributes(&self, idx: usize) -> impl Iterator<Item = (&str, &str)> {
+        let range = self.nodes.get(idx).map_or(0..0, |node| node.attributes.clone());
+        self.attributes[range].iter()
+            .map(|(name, value)| (&self.strings[name.clone()], &self.strings[value.clone()]))
+    }
+
+    /// Gets the index of the parent of a node.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the node
+    ///
+    /// # Returns
+    ///
+    /// The parent index, or `None` for a top-level element and an index
+    /// out of range
+    pub fn parent(&self, idx: usize) -> Option<usize> {
+        self.nodes.get(idx)?.parent
+    }
+
+    /// Iterates over the direct children of a node.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the node
+    ///
+    /// # Returns
+    ///
+    /// An iterator of child indices in document order, empty for an index
+    /// out of range
+    pub fn children(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        let end = self.nodes.get(idx).map_or(idx, |node| node.end);
+        let first = (idx + 1 < end).then_some(idx + 1);
+        std::iter::successors(first, move |&child| Some(self.nodes[child].end).filter(|&next| next < end))
+    }
+
+    /// Gets the indices of all descendants of a node.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the node
+    ///
+    /// # Returns
+    ///
+    /// The range of descendant indices, in document order, empty for an
+    /// index out of range
+    pub fn descendants(&self, idx: usize) -> Range<usize> {
+        let end = self.nodes.get(idx).map_or(0, |node| node.end);
+        idx.saturating_add(1)..end
+    }
+
+    /// Finds the first child element with the given name.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the parent
+    /// * `name` - The element name
+    ///
+    /// # Returns
+    ///
+    /// The index of the child, or `None` if there is none
+    pub fn find(&self, idx: usize, name: &str) -> Option<usize> {
+        self.children(idx).find(|&child| self.name(child) == Some(name))
+    }
+
+    /// Finds the first descendant element with the given name.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the node to search below
+    /// * `name` - The element name
+    ///
+    /// # Returns
+    ///
+    /// The index of the element, or `None` if there is none
+    pub fn find_deep(&self, idx: usize, name: &str) -> Option<usize> {
+        self.descendants(idx).find(|&node| self.name(node) == Some(name))
+    }
+
+    /// Concatenates the text below a node.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - The index of the node
+    ///
+    /// # Returns
+    ///
+    /// The text of the node and all its descendants, in document order,
+    /// or an empty string for an index out of range
+    pub fn text_content(&self, idx: usize) -> String {
+        let end = self.nodes.get(idx).map_or(idx, |node| node.end);
+        (idx..end).filter_map(|node| self.text(node)).collect()
+    }
+}
+
+/// Parser building a `FlatDocument` from SAX events.
+///
+/// Like `DomParser`, this is a `SaxHandler` to be driven by a `Parser`.
+#[derive(Debug, Default)]
+pub struct FlatParser {
+    document: FlatDocument,
+    /// Indices of the open elements
+    stack: Vec<usize>,
+    /// Text since the last tag, kept until it turns out not to be blank
+    pending_text: String,
+}
+
+impl FlatParser {
+    /// Creates a new flat parser.
+    ///
+    /// # Returns
+    ///
+    /// A new `FlatParser` instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses an XML string into a flat document.
+    ///
+    /// # Arguments
+    ///
+    /// * `xml` - The XML string to parse
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the document, or the parse error
+    pub fn parse_str(xml: &str) -> Result<FlatDocument> {
+        let mut parser = Parser::new(FlatParser::new());
+        parser.parse_complete(xml)?;
+        Ok(std::mem::take(parser.handler_mut()).into_document())
+    }
+
+    /// Gets the document built so far.
+    ///
+    /// # Returns
+    ///
+    /// A reference to the document
+    pub fn document(&self) -> &FlatDocument {
+        &self.document
+    }
+
+    /// Takes the built document out of the parser.
+    ///
+    /// # Returns
+    ///
+    /// The document
+    pub fn into_document(self) -> FlatDocument {
+        self.document
+    }
+
+    /// Appends a string to the document buffer.
+    fn push_str(&mut self, s: &str) -> Range<usize> {
+        push_str(&mut self.document.strings, s)
+    }
+
+    /// Adds the pending text as a node, unless it is blank.
+    fn flush_text(&mut self) {
+        if let Some(&parent) = self.stack.last() {
+            if !is_blank(&self.pending_text) {
+                let value = push_str(&mut self.document.strings, &self.pending_text);
+                let idx = self.document.nodes.len();
+                self.document.nodes.push(FlatNode {
+                    node_type: IksType::CData,
+                    parent: Some(parent),
+                    end: idx + 1,
+                    value,
+                    attributes: 0..0,
+                });
+            }
+        }
+        self.pending_text.clear();
+    }
+}
+
+/// Appends a string to a buffer, returning where it went.
+fn push_str(buffer: &mut String, s: &str) -> Range<usize> {
+    let start = buffer.len();
+    buffer.push_str(s);
+    start..buffer.len()
+}
+
+impl SaxHandler for FlatParser {
+    type Error = IksError;
+
+    fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+        self.flush_text();
+        match tag_type {
+            TagType::Open | TagType::Single => {
+                let value = self.push_str(name);
+                let first = self.document.attributes.len();
+                for (attr, attr_value) in attributes {
+                    let attr = self.push_str(attr);
+                    let attr_value = self.push_str(attr_value);
+                    self.document.attributes.push((attr, attr_value));
+                }
+                let idx = self.document.nodes.len();
+                self.document.nodes.push(FlatNode {
+                    node_type: IksType::Tag,
+                    parent: self.stack.last().copied(),
+                    end: idx + 1,
+                    value,
+                    attributes: first..self.document.attributes.len(),
+                });
+                if tag_type == TagType::Open {
+                    self.stack.push(idx);
+                }
+            }
+            TagType::Close => {
+                let idx = self.stack.pop().ok_or(IksError::BadXml)?;
+                if self.document.name(idx) != Some(name) {
+                    return Err(IksError::BadXml);
+                }
+                self.document.nodes[idx].end = self.document.nodes.len();
+            }
+        }
+        Ok(())
+    }
+
+    fn on_cdata(&mut self, data: &str) -> Result<()> {
+        if !self.stack.is_empty() {
+            self.pending_text.push_str(data);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = "<library>\n  <shelf id='a'>\n    <book isbn='1' lang='en'>Dune</book>\n    <book isbn='2'>Emma</book>\n  </shelf>\n  \
+                       <shelf id='b'><book isbn='3'>Ulysses <b>of</b> Joyce</book></shelf>\n  <empty/>\n</library>";
+
+    #[test]
+    fn test_navigation() {
+        let doc = FlatParser::parse_str(XML).unwrap();
+        let root = doc.root().unwrap();
+        assert_eq!(doc.name(root), Some("library"));
+        assert_eq!(doc.parent(root), None);
+        assert_eq!(doc.descendants(root), 1..doc.len());
+
+        let missing = doc.len();
+        assert_eq!((doc.name(missing), doc.text(missing), doc.parent(missing)), (None, None, None));
+        assert_eq!(doc.attributes(missing).count(), 0);
+        assert_eq!(doc.children(missing).count(), 0);
+        assert!(doc.descendants(missing).is_empty());
+        assert_eq!(doc.text_content(missing), "");
+        assert_eq!(doc.find_deep(usize::MAX, "book"), None);
+
+        let shelves: Vec<_> = doc.children(root).collect();
+        assert_eq!(shelves.len(), 3);
+        assert_eq!(shelves.iter().map(|&s| doc.attr(s, "id")).collect::<Vec<_>>(), [Some("a"), Some("b"), None]);
+        assert_eq!(doc.name(shelves[2]), Some("empty"));
+        assert_eq!(doc.children(shelves[2]).count(), 0);
+
+        let books: Vec<_> = doc.children(shelves[0]).collect();
+        assert_eq!(books.len(), 2);
+        assert_eq!(doc.attributes(books[0]).collect::<Vec<_>>(), [("isbn", "1"), ("lang", "en")]);
+        assert_eq!(doc.attr(books[1], "lang"), None);
+        assert_eq!(doc.text_content(books[1]), "Emma");
+        assert_eq!(doc.parent(books[1]), Some(shelves[0]));
+        for &book in &books {
+            let text = doc.children(book).next().unwrap();
+            assert_eq!(doc.node(text).unwrap().node_type(), IksType::CData);
+            assert_eq!(doc.name(text), None);
+        }
+
+        assert_eq!(doc.find_deep(root, "book"), Some(books[0]));
+        let ulysses = doc.find(shelves[1], "book").unwrap();
+        assert_eq!(doc.text_content(ulysses), "Ulysses of Joyce");
+        assert_eq!(doc.children(ulysses).map(|c| doc.node(c).unwrap().node_type()).collect::<Vec<_>>(),
+                   [IksType::CData, IksType::Tag, IksType::CData]);
+        assert_eq!(doc.find_deep(shelves[1], "b").map(|b| doc.text_content(b)).as_deref(), Some("of"));
+        assert_eq!(doc.find(root, "book"), None);
+        assert_eq!(doc.find_deep(root, "missing"), None);
+    }
+
+    #[test]
+    fn test_matches_dom() {
+        let dom = crate::DomParser::parse_str(XML).unwrap();
+        let doc = FlatParser::parse_str(XML).unwrap();
+        let dom_books = dom.borrow().count_tags("book");
+        let flat_books = doc.descendants(0).filter(|&node| doc.name(node) == Some("book")).count();
+        assert_eq!((dom_books, flat_books), (3, 3));
+        assert_eq!(doc.text_content(0), dom.borrow().text_content());
+
+        let mut parser = Parser::new(FlatParser::new());
+        for chunk in XML.as_bytes().chunks(3) {
+            parser.parse_bytes(chunk).unwrap();
+        }
+        parser.finish().unwrap();
+        assert_eq!(*parser.handler().document(), doc);
+
+        assert!(matches!(FlatParser::parse_str("<a><b></a>"), Err(IksError::BadXml)));
+        assert!(matches!(FlatParser::parse_str("<a>"), Err(IksError::UnexpectedEof)));
+    }
+}
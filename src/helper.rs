@@ -26,7 +26,7 @@ use crate::constants::memory;
 /// 
 /// The aligned size
 pub fn align_size(size: usize) -> usize {
-    ((size + memory::ALIGNMENT - 1) & !memory::ALIGN_MASK)
+    (size + memory::ALIGNMENT - 1) & !memory::ALIGN_MASK
 }
 
 /// Calculates chunk growth size based on current size and growth factor.
@@ -14,7 +14,7 @@
 use std::alloc::{self, Layout};
 use std::ptr::NonNull;
 use crate::constants::memory;
-use crate::helper::{align_size, calculate_chunk_growth};
+use crate::helper::{align_size, escape_size};
 
 /// A memory-efficient stack allocator for XML parsing.
 /// 
@@ -53,7 +53,6 @@ struct Chunk {
     layout: Layout,
     used: usize,
     capacity: usize,
-    last: usize,
 }
 
 impl IksStack {
@@ -120,7 +119,6 @@ impl IksStack {
             layout,
             used: size,
             capacity: alloc_size,
-            last: 0,
         });
 
         Some(ptr)
@@ -193,6 +191,51 @@ impl IksStack {
         Some(ptr)
     }
 
+    /// Concatenates a string with the XML-escaped form of another.
+    /// 
+    /// Like `strcat`, but `src` is appended with `&`, `<`, `>`, `"` and `'`
+    /// replaced by entity references, so serialized markup can be built in
+    /// the arena without an intermediate `String`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `old` - Optional pointer to existing string
+    /// * `src` - The string to escape and append
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing a pointer to the concatenated string
+    pub fn strcat_escaped(&mut self, old: Option<NonNull<u8>>, src: &str) -> Option<NonNull<u8>> {
+        let old_len = old.map_or(0, |old| unsafe { strlen(old.as_ptr()) });
+        // escape_size counts characters; entity references are ASCII, so
+        // every character it adds is one more byte
+        let escaped_len = src.len() + escape_size(src) - src.chars().count();
+        let total_len = old_len + escaped_len;
+
+        let ptr = self.alloc(total_len + 1, true)?;
+        unsafe {
+            if let Some(old_ptr) = old {
+                std::ptr::copy_nonoverlapping(old_ptr.as_ptr(), ptr.as_ptr(), old_len);
+            }
+            let mut pos = old_len;
+            let mut buf = [0; 4];
+            for c in src.chars() {
+                let bytes = match c {
+                    '&' => "&amp;",
+                    '<' => "&lt;",
+                    '>' => "&gt;",
+                    '"' => "&quot;",
+                    '\'' => "&apos;",
+                    _ => c.encode_utf8(&mut buf),
+                };
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr.as_ptr().add(pos), bytes.len());
+                pos += bytes.len();
+            }
+            *ptr.as_ptr().add(total_len) = 0;
+        }
+        Some(ptr)
+    }
+
     /// Gets statistics about memory usage.
     /// 
     /// # Returns
@@ -240,6 +283,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(useless_ptr_null_checks)]
     fn test_stack_alloc() {
         let mut stack = IksStack::new(128, 256);
         
@@ -272,4 +316,24 @@ mod tests {
             assert_eq!(slice, (s1.to_string() + s2).as_bytes());
         }
     }
+
+    #[test]
+    fn test_strcat_escaped() {
+        let mut stack = IksStack::new(128, 256);
+
+        let start = stack.strdup("<a title=\"", true).unwrap();
+        let ptr = stack.strcat_escaped(Some(start), "Tom & \"Jerry\" <ü>").unwrap();
+        let expected = "<a title=\"Tom &amp; &quot;Jerry&quot; &lt;ü&gt;";
+        unsafe {
+            assert_eq!(strlen(ptr.as_ptr()), expected.len());
+            let slice = std::slice::from_raw_parts(ptr.as_ptr(), expected.len());
+            assert_eq!(slice, expected.as_bytes());
+        }
+
+        let ptr = stack.strcat_escaped(None, "it's").unwrap();
+        unsafe {
+            let slice = std::slice::from_raw_parts(ptr.as_ptr(), 10);
+            assert_eq!(slice, b"it&apos;s\0");
+        }
+    }
 } 
\ No newline at end of file
@@ -17,22 +17,50 @@ pub mod ikstack;
 mod utility;
 mod constants;
 mod helper;
+mod serialize;
+mod config;
+mod digest;
+mod document;
+mod binary;
+mod event;
+mod symbol;
+mod streaming;
+mod merge;
+mod limits;
+mod tokenizer;
+mod flat;
+pub mod xmpp;
 
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use thiserror::Error;
 use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 
-pub use parser::{Parser, SaxHandler};
-pub use dom::DomParser;
-pub use utility::{str_dup, str_cat, str_casecmp, str_len, escape, unescape, set_mem_funcs};
+pub use parser::{Parser, ParserState, ParseStats, SaxHandler, Position, Span, extract_text};
+pub use dom::{DomParser, Progress, ProgressInterval, build_dom};
+pub use document::{AttributeDefault, Doctype, Document};
+pub use digest::{ByteDigest, DigestingParser};
+pub use event::Event;
+pub use symbol::{Symbol, SymbolTable};
+pub use streaming::{Streaming, StreamingSaxHandler};
+pub use merge::{AttributeMerge, ChildMerge, MergeStrategy};
+pub use limits::{Limit, LimitCheckingHandler, Limits};
+pub use tokenizer::{Token, TokenKind, Tokenizer};
+pub use flat::{FlatDocument, FlatNode, FlatParser};
+pub use serialize::SerializeOptions;
+pub use config::{InvalidUtf8, ParserConfig, ParserBuilder};
+pub use utility::{str_dup, str_cat, str_casecmp, str_len, split_qname, escape, escape_for, EscapeContext, unescape, unescape_cow, unescape_iter, needs_escape, needs_escape_attr, set_mem_funcs};
 pub use constants::{memory, xml};
 pub use helper::{align_size, calculate_chunk_growth, escape_size, unescape_size};
 
 /// Represents the type of an XML node in the DOM tree.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IksType {
-    /// No specific type
+    /// Container without markup of its own, such as a document holding
+    /// several top-level nodes; it serializes as its children
     None,
     /// XML element tag
     Tag,
@@ -40,6 +68,55 @@ pub enum IksType {
     Attribute,
     /// Character data (text content)
     CData,
+    /// Comment, with the text between `<!--` and `-->` as content
+    Comment,
+    /// Processing instruction, with the target as name and the rest as content
+    Pi,
+}
+
+/// One step of an `IksNode::find_path` query.
+struct PathStep<'a> {
+    /// Element name, or `*` for any element
+    name: &'a str,
+    /// Attribute the element must have, with its value if one is required
+    attribute: Option<(&'a str, Option<&'a str>)>,
+}
+
+impl<'a> PathStep<'a> {
+    /// Parses a step like `item` or `item[@id='5']`.
+    fn parse(step: &'a str) -> Option<Self> {
+        let (name, attribute) = match step.split_once('[') {
+            None => (step, None),
+            Some((name, filter)) => {
+                let filter = filter.strip_suffix(']')?.strip_prefix('@')?;
+                let attribute = match filter.split_once('=') {
+                    None => (filter, None),
+                    Some((attr, value)) => {
+                        let quote = value.chars().next().filter(|&c| c == '\'' || c == '"')?;
+                        let value = value[1..].strip_suffix(quote)?;
+                        (attr, Some(value))
+                    }
+                };
+                (name, Some(attribute))
+            }
+        };
+        if name.is_empty() {
+            return None;
+        }
+        Some(PathStep { name, attribute })
+    }
+
+    /// Checks whether a node is selected by this step.
+    fn matches(&self, node: &IksNode) -> bool {
+        if node.node_type != IksType::Tag || (self.name != "*" && node.name.as_deref() != Some(self.name)) {
+            return false;
+        }
+        match self.attribute {
+            None => true,
+            Some((attr, None)) => node.find_attrib(attr).is_some(),
+            Some((attr, Some(value))) => node.find_attrib(attr) == Some(value),
+        }
+    }
 }
 
 /// Represents the type of an XML tag.
@@ -62,6 +139,71 @@ pub enum IksError {
     /// Invalid XML syntax
     #[error("Invalid XML")]
     BadXml,
+    /// Malformed attribute, e.g. with a missing `=` or an unquoted value
+    #[error("Malformed attribute `{name}` at line {}, column {}", position.line, position.column)]
+    BadAttribute {
+        /// Name of the offending attribute
+        name: String,
+        /// Position of the first character of the attribute name
+        position: Position,
+    },
+    /// An attribute value is longer than the configured limit
+    #[error("Value of attribute `{name}` at line {}, column {} exceeds {limit} bytes", position.line, position.column)]
+    AttributeTooLong {
+        /// Name of the offending attribute
+        name: String,
+        /// Position of the first character of the attribute name
+        position: Position,
+        /// The configured limit, in bytes
+        limit: usize,
+    },
+    /// Elements are nested deeper than the configured limit
+    #[error("Maximum nesting depth exceeded")]
+    TooDeep,
+    /// The input has more elements than the configured limit
+    #[error("Maximum number of elements exceeded")]
+    TooManyElements,
+    /// Input bytes are not valid UTF-8
+    #[error("Invalid UTF-8 in input")]
+    BadUtf8,
+    /// Input ended inside markup or with elements still open
+    #[error("Unexpected end of input")]
+    UnexpectedEof,
+    /// Input ended inside a `<![CDATA[` section
+    #[error("Unterminated CDATA section starting at line {}, column {}", position.line, position.column)]
+    UnterminatedCData {
+        /// Position of the `<` that opened the section
+        position: Position,
+    },
+    /// A byte order mark after the start of the input
+    #[error("Byte order mark at line {}, column {}", position.line, position.column)]
+    MisplacedBom {
+        /// Position of the byte order mark
+        position: Position,
+    },
+    /// An XML declaration that is not at the start of the input
+    #[error("XML declaration at line {}, column {}", position.line, position.column)]
+    MisplacedDeclaration {
+        /// Position of the `<` opening the declaration
+        position: Position,
+    },
+    /// An element or non-whitespace text after the root element
+    #[error("Content after the root element")]
+    ContentAfterRoot,
+    /// A limit checked by `LimitCheckingHandler` was exceeded
+    #[error("Limit on {limit} ({max}) exceeded")]
+    LimitExceeded {
+        /// The limit that was hit
+        limit: Limit,
+        /// The configured maximum
+        max: usize,
+    },
+    /// A character that the XML version does not allow, given by its code point
+    #[error("Character U+{0:04X} is not allowed in XML")]
+    InvalidChar(u32),
+    /// Binary tree data is truncated or malformed
+    #[error("Invalid binary tree data")]
+    BadBinary,
     /// Error returned from a hook function
     #[error("Hook returned error")]
     Hook,
@@ -104,7 +246,10 @@ pub enum IksError {
 }
 
 /// Result type for iksemel operations
-pub type Result<T> = std::result::Result<T, IksError>;
+/// 
+/// The error type defaults to `IksError`; parsing with a handler that has
+/// its own `SaxHandler::Error` gives `Result<T, H::Error>`.
+pub type Result<T, E = IksError> = std::result::Result<T, E>;
 
 /// Represents a node in the XML DOM tree.
 /// 
@@ -131,6 +276,15 @@ pub type Result<T> = std::result::Result<T, IksError>;
 /// child.set_content("Hello World");
 /// root.add_child(child);
 /// ```
+/// 
+/// # Ownership
+/// 
+/// A node owns its children and its next sibling through `Rc`; the links
+/// back to the parent, the previous sibling and the node itself are `Weak`.
+/// Strong links must therefore only ever point down or to the right, so
+/// dropping the root frees the whole tree. A strong link pointing back to
+/// an ancestor or an earlier sibling forms a cycle that is never freed;
+/// `assert_acyclic` checks for that in debug builds.
 #[derive(Debug)]
 pub struct IksNode {
     node_type: IksType,
@@ -141,6 +295,25 @@ pub struct IksNode {
     parent: Option<Weak<RefCell<IksNode>>>,
     next: Option<Rc<RefCell<IksNode>>>,
     prev: Option<Weak<RefCell<IksNode>>>,
+    self_ref: Option<Weak<RefCell<IksNode>>>,
+    cdata_section: bool,
+    user_data: Option<Box<dyn Any>>,
+    /// Source forms of attribute values, see `DomParser::set_preserve_references`
+    attribute_sources: Vec<AttributeSource>,
+    /// Names of attributes written without a value, like `disabled` in `<input disabled>`
+    valueless_attributes: Vec<String>,
+    /// Source location, see `DomParser::set_track_spans`
+    span: Option<Span>,
+}
+
+/// An attribute value as written in the source, with its references.
+#[derive(Debug, Clone)]
+struct AttributeSource {
+    name: String,
+    /// The decoded value the source form stands for
+    value: String,
+    /// The text between the quotes
+    raw: String,
 }
 
 impl IksNode {
@@ -163,6 +336,12 @@ impl IksNode {
             parent: None,
             next: None,
             prev: None,
+            self_ref: None,
+            cdata_section: false,
+            user_data: None,
+            attribute_sources: Vec::new(),
+            valueless_attributes: Vec::new(),
+            span: None,
         }
     }
 
@@ -185,7 +364,247 @@ impl IksNode {
             parent: None,
             next: None,
             prev: None,
+            self_ref: None,
+            cdata_section: false,
+            user_data: None,
+            attribute_sources: Vec::new(),
+            valueless_attributes: Vec::new(),
+            span: None,
+        }
+    }
+
+    /// Creates a new text node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - The text content of the node
+    /// 
+    /// # Returns
+    /// 
+    /// A new `IksNode` instance of type `CData`
+    pub fn text<S: Into<String>>(data: S) -> Self {
+        let mut node = IksNode::new(IksType::CData);
+        node.set_content(data);
+        node
+    }
+
+    /// Creates a new comment node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `text` - The text between `<!--` and `-->`
+    /// 
+    /// # Returns
+    /// 
+    /// A new `IksNode` instance of type `Comment`
+    pub fn new_comment<S: Into<String>>(text: S) -> Self {
+        let mut node = IksNode::new(IksType::Comment);
+        node.set_content(text);
+        node
+    }
+
+    /// Creates a new processing instruction node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `target` - The instruction target, e.g. `xml-stylesheet`
+    /// * `data` - The rest of the instruction
+    /// 
+    /// # Returns
+    /// 
+    /// A new `IksNode` instance of type `Pi`
+    pub fn new_pi<T: Into<String>, D: Into<String>>(target: T, data: D) -> Self {
+        let mut node = IksNode::new(IksType::Pi);
+        node.name = Some(target.into());
+        node.set_content(data);
+        node
+    }
+
+    /// Creates a new character data node written as a CDATA section.
+    /// 
+    /// The text is serialized verbatim inside `<![CDATA[ ... ]]>` instead of
+    /// being entity-escaped.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - The text content of the section
+    /// 
+    /// # Returns
+    /// 
+    /// A new `IksNode` instance of type `CData`
+    pub fn new_cdata_section<S: Into<String>>(data: S) -> Self {
+        let mut node = IksNode::text(data);
+        node.cdata_section = true;
+        node
+    }
+
+    /// Checks if this node is written as a CDATA section.
+    /// 
+    /// # Returns
+    /// 
+    /// `true` if this is a character data node created as a CDATA section
+    pub fn is_cdata_section(&self) -> bool {
+        self.cdata_section
+    }
+
+    /// Checks if this is a text node made only of whitespace.
+    /// 
+    /// This is the test the parser and the pretty printer use to tell
+    /// insignificant whitespace between elements from real text. CDATA
+    /// sections count as text nodes too.
+    /// 
+    /// # Returns
+    /// 
+    /// `true` for a `CData` node whose content is empty or whitespace,
+    /// `false` for all other nodes
+    pub fn is_whitespace(&self) -> bool {
+        self.node_type == IksType::CData
+            && self.content.as_deref().is_none_or(utility::is_blank)
+    }
+
+    /// Attaches arbitrary data to this node.
+    /// 
+    /// The slot holds one value of any type and replaces what was stored
+    /// before. It is meant for tools that annotate a tree, e.g. with dirty
+    /// flags or index entries. The data is not serialized, compared or
+    /// copied by `clone`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - The value to store
+    pub fn set_user_data<T: Any>(&mut self, data: T) {
+        self.user_data = Some(Box::new(data));
+    }
+
+    /// Gets the data attached with `set_user_data`.
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the data if a value of type `T` is stored
+    pub fn user_data<T: Any>(&self) -> Option<&T> {
+        self.user_data.as_ref().and_then(|data| data.downcast_ref())
+    }
+
+    /// Gets mutable access to the data attached with `set_user_data`.
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the data if a value of type `T` is stored
+    pub fn user_data_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.user_data.as_mut().and_then(|data| data.downcast_mut())
+    }
+
+    /// Removes the data attached to this node.
+    /// 
+    /// # Returns
+    /// 
+    /// The removed data, if any
+    pub fn take_user_data(&mut self) -> Option<Box<dyn Any>> {
+        self.user_data.take()
+    }
+
+    /// Gets the type of this node.
+    /// 
+    /// # Returns
+    /// 
+    /// The `IksType` of the node
+    pub fn node_type(&self) -> IksType {
+        self.node_type
+    }
+
+    /// Gets the name of this node.
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the tag name if this is a tag node
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Splits the name of this node into its namespace prefix and local name.
+    /// 
+    /// # Returns
+    /// 
+    /// A tuple containing the optional prefix and the local name, which is
+    /// empty for nodes without a name
+    pub fn qname(&self) -> (Option<&str>, &str) {
+        split_qname(self.name.as_deref().unwrap_or_default())
+    }
+
+    /// Gets the namespace a prefix is bound to by an attribute of this node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `prefix` - The prefix, or `None` for the default namespace
+    /// 
+    /// # Returns
+    /// 
+    /// The value of the `xmlns:prefix` or `xmlns` attribute, if present
+    fn declared_namespace(&self, prefix: Option<&str>) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(name, _)| match (prefix, name.strip_prefix("xmlns")) {
+                (None, Some(rest)) => rest.is_empty(),
+                (Some(prefix), Some(rest)) => rest.strip_prefix(':') == Some(prefix),
+                (_, None) => false,
+            })
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Resolves a namespace prefix in the scope of this node.
+    /// 
+    /// The declarations of this node are looked at first, then those of its
+    /// ancestors. The `xml` prefix is always bound to its reserved URI, and
+    /// an empty `xmlns=""` leaves the default namespace unset.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `prefix` - The prefix, or `None` for the default namespace
+    /// 
+    /// # Returns
+    /// 
+    /// The namespace URI, or `None` if the prefix is not bound
+    pub fn lookup_namespace(&self, prefix: Option<&str>) -> Option<String> {
+        if prefix == Some("xml") {
+            return Some(xml::XML_NAMESPACE.to_string());
         }
+        let uri = match self.declared_namespace(prefix) {
+            Some(uri) => Some(uri.to_string()),
+            None => self.ancestors().find_map(|node| node.borrow().declared_namespace(prefix).map(str::to_string)),
+        };
+        uri.filter(|uri| !uri.is_empty())
+    }
+
+    /// Gets the namespace URI of this node's name.
+    /// 
+    /// # Returns
+    /// 
+    /// The URI bound to the prefix of the name, or the default namespace
+    /// for an unprefixed name
+    pub fn namespace_uri(&self) -> Option<String> {
+        self.lookup_namespace(self.qname().0)
+    }
+
+    /// Gets the content of this node.
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the text content if it has been set
+    pub fn content(&self) -> Option<&str> {
+        self.content.as_deref()
+    }
+
+    /// Gets the source location of this element.
+    /// 
+    /// The span starts at the `<` of the opening tag and ends at the `>` of
+    /// the closing tag (or of the opening tag for self-closing elements).
+    /// Copies made with `clone_shallow` or `deep_clone` keep it.
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the span if the node was built by a
+    /// `DomParser` with span tracking enabled
+    pub fn span(&self) -> Option<Span> {
+        self.span
     }
 
     /// Gets the parent node of this node.
@@ -197,6 +616,108 @@ impl IksNode {
         self.parent.as_ref().and_then(|w| w.upgrade())
     }
 
+    /// Iterates over the ancestors of this node.
+    /// 
+    /// # Returns
+    /// 
+    /// An iterator starting at the parent and ending at the root
+    pub fn ancestors(&self) -> impl Iterator<Item = Rc<RefCell<IksNode>>> {
+        std::iter::successors(self.parent(), |node| node.borrow().parent())
+    }
+
+    /// Builds a path locating this node from the root of its tree.
+    /// 
+    /// The path lists the element names from the root down, separated by
+    /// `/`, like `/root/item[2]/name`. A 1-based index is added where the
+    /// parent has several children of the same name. Text, comments and
+    /// processing instructions are written as `text()`, `comment()` and
+    /// `processing-instruction()`.
+    /// 
+    /// # Returns
+    /// 
+    /// The path of this node
+    pub fn path(&self) -> String {
+        let mut segments = vec![self.path_segment()];
+        segments.extend(self.ancestors().map(|node| node.borrow().path_segment()));
+        segments.reverse();
+        format!("/{}", segments.join("/"))
+    }
+
+    /// Gets the step of `path` that selects this node within its parent.
+    fn path_segment(&self) -> String {
+        let name = match self.node_type {
+            IksType::CData => "text()",
+            IksType::Comment => "comment()",
+            IksType::Pi => "processing-instruction()",
+            _ => self.name.as_deref().unwrap_or_default(),
+        };
+        let Some(parent) = self.parent() else {
+            return name.to_string();
+        };
+
+        let me = self as *const IksNode;
+        let mut position = 0;
+        let mut count = 0;
+        for sibling in &parent.borrow().children {
+            if std::ptr::eq(sibling.as_ptr(), me) {
+                count += 1;
+                position = count;
+                continue;
+            }
+            let sibling = sibling.borrow();
+            if sibling.node_type == self.node_type && (self.node_type != IksType::Tag || sibling.name == self.name) {
+                count += 1;
+            }
+        }
+        if count > 1 {
+            format!("{}[{}]", name, position)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Checks that no strong reference cycle is reachable from this node.
+    /// 
+    /// Follows the children and next-sibling links, which are the only
+    /// strong ones, and panics if a node can reach itself through them.
+    /// The check costs a full traversal and only runs in debug builds; in
+    /// release builds it does nothing.
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if a cycle is found
+    pub fn assert_acyclic(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let links = |node: &IksNode| node.children.iter().chain(&node.next).cloned().collect::<Vec<_>>();
+        // Nodes on the current path, and nodes whose links were all followed
+        let mut path = HashSet::from([self as *const IksNode]);
+        let mut finished = HashSet::new();
+        let mut stack = vec![(self as *const IksNode, links(self).into_iter())];
+        while let Some((node, pending)) = stack.last_mut() {
+            let node = *node;
+            match pending.next() {
+                Some(link) => {
+                    let target = link.as_ptr() as *const IksNode;
+                    if path.contains(&target) {
+                        panic!("strong reference cycle through node {:?}", link.borrow().name);
+                    }
+                    if finished.insert(target) {
+                        path.insert(target);
+                        let links = links(&link.borrow());
+                        stack.push((target, links.into_iter()));
+                    }
+                }
+                None => {
+                    path.remove(&node);
+                    finished.insert(node);
+                    stack.pop();
+                }
+            }
+        }
+    }
+
     /// Gets the next sibling node.
     /// 
     /// # Returns
@@ -248,7 +769,42 @@ impl IksNode {
             .find(|child| {
                 let child = child.borrow();
                 child.node_type == IksType::Tag && 
-                child.name.as_ref().map_or(false, |n| n == name)
+                child.name.as_deref() == Some(name)
+            })
+            .cloned()
+    }
+
+    /// Finds the first child element with the given namespace and local name.
+    /// 
+    /// Unlike `find`, this does not depend on the prefix a document happens
+    /// to use: `<query xmlns='jabber:iq:roster'/>` and
+    /// `<r:query xmlns:r='jabber:iq:roster'/>` both match
+    /// `find_ns("jabber:iq:roster", "query")`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `uri` - The namespace URI
+    /// * `local` - The local name, without prefix
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the matching child node if found
+    pub fn find_ns(&self, uri: &str, local: &str) -> Option<Rc<RefCell<IksNode>>> {
+        self.children.iter()
+            .find(|child| {
+                let child = child.borrow();
+                let (prefix, name) = child.qname();
+                if child.node_type != IksType::Tag || name != local {
+                    return false;
+                }
+                // Resolve through this node rather than the child's parent
+                // link, which would borrow this node again
+                let resolved = match (prefix, child.declared_namespace(prefix)) {
+                    (_, Some(declared)) => Some(declared.to_string()).filter(|d| !d.is_empty()),
+                    (Some("xml"), None) => Some(xml::XML_NAMESPACE.to_string()),
+                    (_, None) => self.lookup_namespace(prefix),
+                };
+                resolved.as_deref() == Some(uri)
             })
             .cloned()
     }
@@ -270,101 +826,558 @@ impl IksNode {
         })
     }
 
-    /// Adds a child node to this node.
+    /// Finds the first descendant with the specified tag name.
+    /// 
+    /// Descendants are searched depth-first in document order, so the
+    /// first matching element in the source is returned.
     /// 
     /// # Arguments
     /// 
-    /// * `child` - The child node to add
+    /// * `name` - The name of the tag to find
     /// 
     /// # Returns
     /// 
-    /// The added child node wrapped in an `Rc<RefCell<IksNode>>`
-    pub fn add_child(&mut self, child: IksNode) -> Rc<RefCell<IksNode>> {
-        let child_rc = Rc::new(RefCell::new(child));
-        
-        // Set up parent reference
-        if let Some(self_rc) = self.as_rc() {
-            child_rc.borrow_mut().parent = Some(Rc::downgrade(&self_rc));
-        }
-        
-        // Set up sibling references
-        if let Some(last_child) = self.children.last() {
-            child_rc.borrow_mut().prev = Some(Rc::downgrade(last_child));
-            last_child.borrow_mut().next = Some(child_rc.clone());
-        }
-        
-        self.children.push(child_rc.clone());
-        child_rc
+    /// An `Option` containing the matching descendant if found
+    pub fn find_deep(&self, name: &str) -> Option<Rc<RefCell<IksNode>>> {
+        self.find_by_deep(|node| node.node_type == IksType::Tag && node.name.as_deref() == Some(name))
     }
 
-    /// Inserts a new tag node as a sibling.
+    /// Finds the first child node satisfying a predicate.
+    /// 
+    /// The predicate sees every child, including text, comments and
+    /// processing instructions, so it should check `node_type` when only
+    /// elements are of interest.
     /// 
     /// # Arguments
     /// 
-    /// * `name` - The name of the new tag
+    /// * `pred` - Returns `true` for the node to find
     /// 
     /// # Returns
     /// 
-    /// The newly created tag node
-    pub fn insert_sibling<S: Into<String>>(&mut self, name: S) -> IksNode {
-        let mut node = IksNode::new_tag(name);
-        if let Some(parent) = &self.parent {
-            if let Some(parent_rc) = parent.upgrade() {
-                node.parent = Some(Rc::downgrade(&parent_rc));
-            }
-        }
-        node
+    /// An `Option` containing the matching child node if found
+    pub fn find_by<F: Fn(&IksNode) -> bool>(&self, pred: F) -> Option<Rc<RefCell<IksNode>>> {
+        self.children.iter()
+            .find(|child| pred(&child.borrow()))
+            .cloned()
     }
 
-    /// Inserts CDATA content as a child node.
+    /// Finds the first descendant satisfying a predicate.
+    /// 
+    /// Descendants are searched depth-first in document order, like
+    /// `find_deep` does.
     /// 
     /// # Arguments
     /// 
-    /// * `data` - The text content to insert
+    /// * `pred` - Returns `true` for the node to find
     /// 
     /// # Returns
     /// 
-    /// The created CDATA node wrapped in an `Rc<RefCell<IksNode>>`
-    pub fn insert_cdata<S: Into<String>>(&mut self, data: S) -> Rc<RefCell<IksNode>> {
-        let mut cdata = IksNode::new(IksType::CData);
-        cdata.set_content(data);
-        self.add_child(cdata)
+    /// An `Option` containing the matching descendant if found
+    pub fn find_by_deep<F: Fn(&IksNode) -> bool>(&self, pred: F) -> Option<Rc<RefCell<IksNode>>> {
+        let mut stack: Vec<_> = self.children.iter().rev().cloned().collect();
+        while let Some(node) = stack.pop() {
+            let node_ref = node.borrow();
+            if pred(&node_ref) {
+                drop(node_ref);
+                return Some(node);
+            }
+            stack.extend(node_ref.children.iter().rev().cloned());
+        }
+        None
     }
 
-    /// Adds an attribute to this node.
+    /// Counts the descendants with the specified tag name.
+    /// 
+    /// This builds no list of the matches; the walk only
+    /// keeps a stack of handles to the nodes still to visit, like
+    /// `find_by_deep` does. The node itself is not counted.
     /// 
     /// # Arguments
     /// 
-    /// * `name` - The name of the attribute
-    /// * `value` - The value of the attribute
-    pub fn add_attribute<S: Into<String>>(&mut self, name: S, value: S) {
-        self.attributes.push((name.into(), value.into()));
-    }
-
-    /// Sets the content of this node.
+    /// * `name` - The name of the tags to count
     /// 
-    /// # Arguments
+    /// # Returns
     /// 
-    /// * `content` - The content to set
-    pub fn set_content<S: Into<String>>(&mut self, content: S) {
-        self.content = Some(content.into());
+    /// The number of matching elements at any depth below this node
+    pub fn count_tags(&self, name: &str) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<_> = self.children.clone();
+        while let Some(node) = stack.pop() {
+            let node = node.borrow();
+            if node.node_type == IksType::Tag && node.name.as_deref() == Some(name) {
+                count += 1;
+            }
+            stack.extend(node.children.iter().cloned());
+        }
+        count
     }
 
-    /// Inserts a new tag node before this node.
+    /// Finds the first element matching a slash-separated path.
+    /// 
+    /// The path is relative to this node and uses a tiny subset of XPath:
+    /// 
+    /// * `name` selects the child elements with that name and `*` any
+    ///   child element,
+    /// * `name[@attr]` keeps those having the attribute,
+    /// * `name[@attr='value']` (or `"value"`) keeps those where the
+    ///   attribute has exactly that value,
+    /// * steps are joined with `/`, as in `item[@id='5']/name`.
     /// 
     /// # Arguments
     /// 
-    /// * `name` - The name of the new tag
+    /// * `path` - The path to follow
     /// 
     /// # Returns
     /// 
-    /// The newly created tag node
-    pub fn insert_before<S: Into<String>>(&mut self, name: S) -> IksNode {
-        let mut node = IksNode::new_tag(name);
-        if let Some(parent) = &self.parent {
-            node.parent = Some(parent.clone());
+    /// An `Option` containing the first matching element in document
+    /// order, or `None` if nothing matches or the path is malformed
+    pub fn find_path(&self, path: &str) -> Option<Rc<RefCell<IksNode>>> {
+        let steps = path.split('/')
+            .map(PathStep::parse)
+            .collect::<Option<Vec<_>>>()?;
+        let (first, rest) = steps.split_first()?;
+
+        let mut matches: Vec<_> = self.children.iter()
+            .filter(|child| first.matches(&child.borrow()))
+            .cloned()
+            .collect();
+        for step in rest {
+            matches = matches.iter()
+                .flat_map(|node| node.borrow().children.clone())
+                .filter(|child| step.matches(&child.borrow()))
+                .collect();
         }
-        node
+        matches.into_iter().next()
+    }
+
+    /// Gets all text contained in this node and its descendants.
+    /// 
+    /// Only character data counts: the text of comments and processing
+    /// instructions kept by a lossless parse is left out.
+    /// 
+    /// # Returns
+    /// 
+    /// The concatenated character data in document order
+    pub fn text_content(&self) -> String {
+        let mut text = String::new();
+        if let (IksType::CData, Some(content)) = (self.node_type, &self.content) {
+            text.push_str(content);
+        }
+        let mut stack: Vec<_> = self.children.iter().rev().cloned().collect();
+        while let Some(node) = stack.pop() {
+            let node = node.borrow();
+            if let (IksType::CData, Some(content)) = (node.node_type, &node.content) {
+                text.push_str(content);
+            }
+            stack.extend(node.children.iter().rev().cloned());
+        }
+        text
+    }
+
+    /// Finds the text content of the first descendant with the specified tag name.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the tag to find
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the descendant's text content if found
+    pub fn find_cdata_deep(&self, name: &str) -> Option<String> {
+        self.find_deep(name).map(|node| node.borrow().text_content())
+    }
+
+    /// Finds the first child's CDATA content, falling back to a default.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the tag to find
+    /// * `default` - The value to use if the child or its text is missing
+    /// 
+    /// # Returns
+    /// 
+    /// The CDATA content, or `default`
+    pub fn find_cdata_or(&self, name: &str, default: &str) -> String {
+        self.find_cdata(name).unwrap_or_else(|| default.to_string())
+    }
+
+    /// Collects the text of the child elements by name.
+    /// 
+    /// Meant for simple key-value documents such as
+    /// `<config><host>a</host><host>b</host></config>`: every child element
+    /// becomes an entry for its name, and repeated names collect their texts
+    /// in document order.
+    /// 
+    /// # Returns
+    /// 
+    /// A map from child element names to their text contents
+    pub fn to_string_map(&self) -> HashMap<String, Vec<String>> {
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+        for child in &self.children {
+            let child = child.borrow();
+            if let (IksType::Tag, Some(name)) = (child.node_type, &child.name) {
+                map.entry(name.clone()).or_default().push(child.text_content());
+            }
+        }
+        map
+    }
+
+    /// Copies this node without its children.
+    /// 
+    /// This is the same as `Clone::clone`.
+    /// 
+    /// # Returns
+    /// 
+    /// An unlinked copy of the node without children
+    pub fn clone_shallow(&self) -> IksNode {
+        self.clone()
+    }
+
+    /// Copies this node together with its whole subtree.
+    /// 
+    /// The copy is not linked into any tree; its children are linked to
+    /// each other and, once the copy is wrapped with `into_rc`, to it.
+    /// 
+    /// # Returns
+    /// 
+    /// An unlinked copy of the subtree rooted at this node
+    pub fn deep_clone(&self) -> IksNode {
+        let mut root = self.clone_shallow();
+        // Each entry holds a copy still being filled and the children of
+        // its parent that come after it; `pending` lists those of the
+        // innermost copy
+        let mut stack: Vec<(IksNode, std::vec::IntoIter<Rc<RefCell<IksNode>>>)> = Vec::new();
+        let mut pending = self.children.clone().into_iter();
+        loop {
+            match pending.next() {
+                Some(child) => {
+                    let child = child.borrow();
+                    let siblings = std::mem::replace(&mut pending, child.children.clone().into_iter());
+                    stack.push((child.clone_shallow(), siblings));
+                }
+                None => match stack.pop() {
+                    Some((copy, siblings)) => {
+                        pending = siblings;
+                        match stack.last_mut() {
+                            Some((parent, _)) => parent.add_child(copy),
+                            None => root.add_child(copy),
+                        };
+                    }
+                    None => return root,
+                },
+            }
+        }
+    }
+
+    /// Merges the attributes and children of another element into this one.
+    /// 
+    /// This supports layered documents such as configuration, where a base
+    /// is overridden by a patch. `other` is left unchanged; its children are
+    /// copied.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `other` - The element to merge in
+    /// * `strategy` - How attributes and children are combined
+    pub fn merge(&mut self, other: &IksNode, strategy: MergeStrategy) {
+        merge::merge(self, other, strategy);
+    }
+
+    /// Adds a child node to this node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `child` - The child node to add
+    /// 
+    /// # Returns
+    /// 
+    /// The added child node wrapped in an `Rc<RefCell<IksNode>>`
+    pub fn add_child(&mut self, child: IksNode) -> Rc<RefCell<IksNode>> {
+        let child_rc = child.into_rc();
+        
+        // Set up parent reference
+        if let Some(self_rc) = self.as_rc() {
+            child_rc.borrow_mut().parent = Some(Rc::downgrade(&self_rc));
+        }
+        
+        // Set up sibling references
+        if let Some(last_child) = self.children.last() {
+            child_rc.borrow_mut().prev = Some(Rc::downgrade(last_child));
+            last_child.borrow_mut().next = Some(child_rc.clone());
+        }
+        
+        self.children.push(child_rc.clone());
+        child_rc
+    }
+
+    /// Wraps this node into a shared handle.
+    /// 
+    /// Nodes wrapped this way know their own handle, so children added to
+    /// them later (and the children they already have) get a parent link.
+    /// 
+    /// # Returns
+    /// 
+    /// The node wrapped in an `Rc<RefCell<IksNode>>`
+    pub fn into_rc(self) -> Rc<RefCell<IksNode>> {
+        let node_rc = Rc::new(RefCell::new(self));
+        {
+            let mut node = node_rc.borrow_mut();
+            node.self_ref = Some(Rc::downgrade(&node_rc));
+            for child in &node.children {
+                child.borrow_mut().parent = Some(Rc::downgrade(&node_rc));
+            }
+        }
+        node_rc
+    }
+
+    /// Appends an already shared node as the last child of `parent`.
+    /// 
+    /// If the node is still attached somewhere else it is detached first,
+    /// so this can be used to move nodes around a tree.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `parent` - The node to append to
+    /// * `child` - The node to append
+    /// 
+    /// # Returns
+    /// 
+    /// The appended child node
+    pub fn append_child(parent: &Rc<RefCell<IksNode>>, child: Rc<RefCell<IksNode>>) -> Rc<RefCell<IksNode>> {
+        IksNode::detach(&child);
+
+        let mut parent_ref = parent.borrow_mut();
+        {
+            let mut child_ref = child.borrow_mut();
+            child_ref.parent = Some(Rc::downgrade(parent));
+            if let Some(last_child) = parent_ref.children.last() {
+                child_ref.prev = Some(Rc::downgrade(last_child));
+                last_child.borrow_mut().next = Some(child.clone());
+            }
+        }
+        parent_ref.children.push(child.clone());
+        child
+    }
+
+    /// Removes a node from its parent.
+    /// 
+    /// The node is unlinked from its parent's children and from its
+    /// siblings, and keeps its own subtree. Detaching a node that has no
+    /// parent only clears its sibling links.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `node` - The node to detach
+    pub fn detach(node: &Rc<RefCell<IksNode>>) {
+        let (parent, prev, next) = {
+            let node_ref = node.borrow();
+            (node_ref.parent(), node_ref.prev(), node_ref.next())
+        };
+
+        if let Some(parent) = parent {
+            parent.borrow_mut().children.retain(|child| !Rc::ptr_eq(child, node));
+        }
+        if let Some(prev) = &prev {
+            prev.borrow_mut().next = next.clone();
+        }
+        if let Some(next) = &next {
+            next.borrow_mut().prev = prev.as_ref().map(Rc::downgrade);
+        }
+
+        let mut node_ref = node.borrow_mut();
+        node_ref.parent = None;
+        node_ref.prev = None;
+        node_ref.next = None;
+    }
+
+    /// Nests a node inside a new element that takes its place.
+    /// 
+    /// The new element is put where the node was, between the same
+    /// siblings, and the node becomes its only child. A node without a
+    /// parent, such as a document root, is simply made the child of the new
+    /// element, which is then the new root.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `node` - The node to wrap
+    /// * `parent_name` - The name of the new element
+    /// 
+    /// # Returns
+    /// 
+    /// The new element
+    pub fn wrap(node: Rc<RefCell<IksNode>>, parent_name: &str) -> Rc<RefCell<IksNode>> {
+        let wrapper = IksNode::new_tag(parent_name).into_rc();
+        let (parent, prev, next) = {
+            let mut node_ref = node.borrow_mut();
+            (node_ref.parent(), node_ref.prev.take(), node_ref.next.take())
+        };
+
+        if let Some(parent) = parent {
+            if let Some(slot) = parent.borrow_mut().children.iter_mut().find(|child| Rc::ptr_eq(child, &node)) {
+                *slot = wrapper.clone();
+            }
+            let mut wrapper_ref = wrapper.borrow_mut();
+            wrapper_ref.parent = Some(Rc::downgrade(&parent));
+            if let Some(prev) = prev.as_ref().and_then(Weak::upgrade) {
+                prev.borrow_mut().next = Some(wrapper.clone());
+            }
+            if let Some(next) = &next {
+                next.borrow_mut().prev = Some(Rc::downgrade(&wrapper));
+            }
+            wrapper_ref.prev = prev;
+            wrapper_ref.next = next;
+        }
+        node.borrow_mut().parent = None;
+        IksNode::append_child(&wrapper, node);
+        wrapper
+    }
+
+    /// Inserts a new tag node as a sibling.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the new tag
+    /// 
+    /// # Returns
+    /// 
+    /// The newly created tag node
+    pub fn insert_sibling<S: Into<String>>(&mut self, name: S) -> IksNode {
+        let mut node = IksNode::new_tag(name);
+        if let Some(parent) = &self.parent {
+            if let Some(parent_rc) = parent.upgrade() {
+                node.parent = Some(Rc::downgrade(&parent_rc));
+            }
+        }
+        node
+    }
+
+    /// Inserts CDATA content as a child node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - The text content to insert
+    /// 
+    /// # Returns
+    /// 
+    /// The created CDATA node wrapped in an `Rc<RefCell<IksNode>>`
+    pub fn insert_cdata<S: Into<String>>(&mut self, data: S) -> Rc<RefCell<IksNode>> {
+        self.add_child(IksNode::text(data))
+    }
+
+    /// Inserts a comment as a child node.
+    /// 
+    /// The comment is written as `<!--text-->`, so the text should carry
+    /// its own padding, e.g. `" generated "`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `text` - The text between `<!--` and `-->`
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` containing the created comment node, or
+    /// `IksError::BadXml` if the text contains `--` or ends with `-`, which
+    /// XML does not allow in comments
+    pub fn insert_comment<S: Into<String>>(&mut self, text: S) -> Result<Rc<RefCell<IksNode>>> {
+        let text = text.into();
+        if text.contains("--") || text.ends_with('-') {
+            return Err(IksError::BadXml);
+        }
+        Ok(self.add_child(IksNode::new_comment(text)))
+    }
+
+    /// Replaces the text of the first CDATA child of this node.
+    /// 
+    /// Only that child is touched: attributes and the other children keep
+    /// their values and positions. If there is no CDATA child yet, one is
+    /// appended.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `new_text` - The text to store
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the previous text if a CDATA child existed
+    pub fn replace_text_content(&mut self, new_text: &str) -> Option<String> {
+        let cdata = self.children.iter()
+            .find(|child| child.borrow().node_type == IksType::CData);
+        match cdata {
+            Some(cdata) => cdata.borrow_mut().content.replace(new_text.to_string()),
+            None => {
+                self.insert_cdata(new_text);
+                None
+            }
+        }
+    }
+
+    /// Adds an attribute to this node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the attribute
+    /// * `value` - The value of the attribute
+    pub fn add_attribute<S: Into<String>>(&mut self, name: S, value: S) {
+        self.attributes.push((name.into(), value.into()));
+    }
+
+    /// Adds an attribute without a value, like `disabled` in `<input disabled>`.
+    /// 
+    /// The attribute reads as the empty string. It is written as
+    /// `disabled=""` unless `SerializeOptions::valueless_attributes` is set.
+    /// Attributes parsed in lenient mode from such HTML-style markup are
+    /// marked the same way.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the attribute
+    pub fn add_valueless_attribute<S: Into<String>>(&mut self, name: S) {
+        let name = name.into();
+        self.valueless_attributes.push(name.clone());
+        self.attributes.push((name, String::new()));
+    }
+
+    /// Checks whether an attribute was given without a value.
+    /// 
+    /// This tells `<input disabled>` apart from `<input disabled="">`; both
+    /// have the empty string as value. An attribute that was given another
+    /// value since is no longer valueless.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the attribute
+    /// 
+    /// # Returns
+    /// 
+    /// `true` if the attribute is present, empty and marked as valueless
+    pub fn is_valueless_attribute(&self, name: &str) -> bool {
+        self.find_attrib(name) == Some("") && self.valueless_attributes.iter().any(|n| n == name)
+    }
+
+    /// Sets the content of this node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `content` - The content to set
+    pub fn set_content<S: Into<String>>(&mut self, content: S) {
+        self.content = Some(content.into());
+    }
+
+    /// Inserts a new tag node before this node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the new tag
+    /// 
+    /// # Returns
+    /// 
+    /// The newly created tag node
+    pub fn insert_before<S: Into<String>>(&mut self, name: S) -> IksNode {
+        let mut node = IksNode::new_tag(name);
+        if let Some(parent) = &self.parent {
+            node.parent = Some(parent.clone());
+        }
+        node
     }
 
     /// Finds an attribute value by name.
@@ -382,6 +1395,27 @@ impl IksNode {
             .map(|(_, v)| v.as_str())
     }
 
+    /// Gets an attribute value as it was written in the parsed source.
+    /// 
+    /// Source forms are only kept for values containing references, and only
+    /// when the tree was built with `DomParser::set_preserve_references`.
+    /// Once the attribute is given another value the source form no longer
+    /// applies and `None` is returned.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the attribute
+    /// 
+    /// # Returns
+    /// 
+    /// The raw text between the quotes, e.g. `a &#38; b`, if it is known
+    pub fn attribute_source(&self, name: &str) -> Option<&str> {
+        let value = self.find_attrib(name)?;
+        self.attribute_sources.iter()
+            .find(|source| source.name == name && source.value == value)
+            .map(|source| source.raw.as_str())
+    }
+
     /// Finds the first child node with the specified attribute name and value.
     /// 
     /// # Arguments
@@ -401,7 +1435,7 @@ impl IksNode {
                     return false;
                 }
                 if let Some(name) = tag_name {
-                    if child.name.as_ref().map_or(true, |n| n != name) {
+                    if child.name.as_deref() != Some(name) {
                         return false;
                     }
                 }
@@ -421,6 +1455,53 @@ impl IksNode {
             .cloned()
     }
 
+    /// Iterates over the child nodes of this node.
+    /// 
+    /// # Returns
+    /// 
+    /// An iterator over the children in document order
+    pub fn children(&self) -> impl Iterator<Item = Rc<RefCell<IksNode>>> + '_ {
+        self.children.iter().cloned()
+    }
+
+    /// Gets the number of child nodes, including text and comments.
+    /// 
+    /// # Returns
+    /// 
+    /// The number of children
+    pub fn child_count(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Gets the child node at a position.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `index` - The 0-based position among all children
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the child, or `None` if the index is out of range
+    pub fn child_at(&self, index: usize) -> Option<Rc<RefCell<IksNode>>> {
+        self.children.get(index).cloned()
+    }
+
+    /// Gets the child element at a position, skipping non-tag children.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `index` - The 0-based position among the child elements
+    /// 
+    /// # Returns
+    /// 
+    /// An `Option` containing the element, or `None` if there are fewer elements
+    pub fn tag_child_at(&self, index: usize) -> Option<Rc<RefCell<IksNode>>> {
+        self.children.iter()
+            .filter(|child| child.borrow().node_type == IksType::Tag)
+            .nth(index)
+            .cloned()
+    }
+
     /// Checks if this node has any children.
     /// 
     /// # Returns
@@ -430,28 +1511,431 @@ impl IksNode {
         !self.children.is_empty()
     }
 
-    /// Checks if this node has any attributes.
-    /// 
-    /// # Returns
-    /// 
-    /// `true` if this node has one or more attributes
-    pub fn has_attributes(&self) -> bool {
-        !self.attributes.is_empty()
+    /// Checks if this node has any attributes.
+    /// 
+    /// # Returns
+    /// 
+    /// `true` if this node has one or more attributes
+    pub fn has_attributes(&self) -> bool {
+        !self.attributes.is_empty()
+    }
+
+    /// Gets an iterator over the attributes of this node.
+    /// 
+    /// # Returns
+    /// 
+    /// An iterator over `(name, value)` pairs in document order
+    pub fn attributes(&self) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.attributes.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Gets the attributes of this node sorted by name.
+    /// 
+    /// The node itself keeps its source order. Attributes sharing a name,
+    /// which the lenient parser can produce, stay in source order.
+    /// 
+    /// # Returns
+    /// 
+    /// A vector of `(name, value)` pairs ordered by name
+    pub fn attributes_sorted(&self) -> Vec<(&str, &str)> {
+        let mut attributes: Vec<_> = self.attributes().collect();
+        attributes.sort_by(|a, b| a.0.cmp(b.0));
+        attributes
+    }
+
+    /// Gets an iterator over the attribute names of this node.
+    /// 
+    /// # Returns
+    /// 
+    /// An iterator over the names in document order
+    pub fn attribute_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.attributes.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Gets an iterator over the attribute values of this node.
+    /// 
+    /// # Returns
+    /// 
+    /// An iterator over the values in document order
+    pub fn attribute_values(&self) -> impl Iterator<Item = &str> + '_ {
+        self.attributes.iter().map(|(_, value)| value.as_str())
+    }
+
+    /// Consumes this node, yielding its attributes.
+    /// 
+    /// # Returns
+    /// 
+    /// An iterator over the owned `(name, value)` pairs in document order
+    pub fn into_attributes(mut self) -> impl Iterator<Item = (String, String)> {
+        std::mem::take(&mut self.attributes).into_iter()
+    }
+
+    /// Removes all attributes of this node, yielding them.
+    /// 
+    /// The attributes are removed even if the iterator is dropped before
+    /// it is exhausted.
+    /// 
+    /// # Returns
+    /// 
+    /// An iterator over the owned `(name, value)` pairs in document order
+    pub fn drain_attributes(&mut self) -> impl Iterator<Item = (String, String)> + '_ {
+        self.attributes.drain(..)
+    }
+
+    /// Removes all attributes of this node.
+    pub fn clear_attributes(&mut self) {
+        self.attributes.clear();
+        self.attribute_sources.clear();
+        self.valueless_attributes.clear();
+    }
+
+    /// Removes all children of this node.
+    /// 
+    /// The children are unlinked from this node and from each other, so a
+    /// child still referenced elsewhere is left as a detached subtree;
+    /// the others are dropped.
+    pub fn clear_children(&mut self) {
+        for child in std::mem::take(&mut self.children) {
+            let mut child = child.borrow_mut();
+            child.parent = None;
+            child.prev = None;
+            child.next = None;
+        }
+    }
+
+    /// Exchanges two children of this node.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `i` - The position of one child
+    /// * `j` - The position of the other child
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if either position is out of bounds.
+    pub fn swap_children(&mut self, i: usize, j: usize) {
+        self.children.swap(i, j);
+        self.relink_children();
+    }
+
+    /// Sorts the children of this node with a comparator.
+    /// 
+    /// The sort is stable, so children comparing equal keep their order.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `compare` - Orders two children
+    pub fn sort_children_by<F: FnMut(&IksNode, &IksNode) -> Ordering>(&mut self, mut compare: F) {
+        self.children.sort_by(|a, b| compare(&a.borrow(), &b.borrow()));
+        self.relink_children();
+    }
+
+    /// Sets the parent and sibling links of all children to match their
+    /// order in `children`.
+    pub(crate) fn relink_children(&mut self) {
+        let parent = self.self_ref.clone();
+        for (index, child) in self.children.iter().enumerate() {
+            let mut child_ref = child.borrow_mut();
+            child_ref.parent = parent.clone();
+            child_ref.prev = index.checked_sub(1).map(|prev| Rc::downgrade(&self.children[prev]));
+            child_ref.next = self.children.get(index + 1).cloned();
+        }
+    }
+
+    /// Writes this node and its subtree as XML to a byte sink.
+    /// 
+    /// The serializer does not recurse, so arbitrarily deep trees can be
+    /// written without exhausting the stack.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `writer` - The sink to write the XML text to
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or the underlying I/O error
+    pub fn write_to<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        self.write_to_with(writer, &SerializeOptions::default())
+    }
+
+    /// Writes this node and its subtree as XML to a byte sink using the
+    /// given serialization options.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `writer` - The sink to write the XML text to
+    /// * `options` - How to write the document
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or the underlying I/O error
+    pub fn write_to_with<W: std::io::Write>(&self, writer: &mut W, options: &SerializeOptions) -> Result<()> {
+        let mut sink = serialize::IoWriter::new(writer);
+        serialize::serialize_document(self, None, &mut sink, options).map_err(|_| {
+            sink.error.take().unwrap_or_else(|| std::io::Error::other("formatter error"))
+        })?;
+        Ok(())
+    }
+
+    /// Serializes this node using the given serialization options.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `options` - How to write the document
+    /// 
+    /// # Returns
+    /// 
+    /// The XML representation of the node
+    pub fn to_string_with(&self, options: &SerializeOptions) -> String {
+        let mut out = String::new();
+        serialize::serialize_document(self, None, &mut out, options).expect("writing to a String never fails");
+        out
+    }
+
+    /// Serializes the inside of this element, without its own tags.
+    /// 
+    /// # Returns
+    /// 
+    /// The XML of the element's text and children, or an empty string for
+    /// nodes other than elements
+    pub fn inner_xml(&self) -> String {
+        let mut out = String::new();
+        serialize::serialize_inner(self, &mut out).expect("writing to a String never fails");
+        out
+    }
+
+    /// Serializes this node and its subtree as UTF-8 encoded XML bytes.
+    /// 
+    /// # Returns
+    /// 
+    /// A byte vector containing the XML representation of the node
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_to(&mut out).expect("writing to a Vec never fails");
+        out
+    }
+
+    /// Lists the SAX events that describe this node and its subtree.
+    /// 
+    /// This is the inverse of building a tree with `DomParser`: every
+    /// element yields a start and an end tag, with the events of its
+    /// content in between. CDATA sections are reported as plain text.
+    /// 
+    /// # Returns
+    /// 
+    /// The events in document order
+    pub fn to_events(&self) -> Vec<Event> {
+        event::collect(self)
+    }
+
+    /// Replays this node and its subtree into a SAX handler.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `handler` - The handler to receive the events of `to_events`
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success, or the first error of the handler
+    pub fn drive<H: SaxHandler>(&self, handler: &mut H) -> Result<(), H::Error> {
+        for event in self.to_events() {
+            event.dispatch(handler)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes this node and its subtree in a compact binary format.
+    /// 
+    /// The encoding is meant for caching parsed documents: loading it with
+    /// `from_binary` skips tokenizing and unescaping. It starts with the
+    /// magic bytes `IKSB` and a version byte, followed by the nodes in
+    /// document order. Each node is a type byte, a flags byte, the name and
+    /// content if present, the attributes, the names of the attributes
+    /// written without a value if there are any, and the number of children.
+    /// Strings are written as a little-endian `u32` length and UTF-8 bytes.
+    /// 
+    /// # Returns
+    /// 
+    /// A byte vector containing the encoded tree
+    pub fn to_binary(&self) -> Vec<u8> {
+        binary::encode(self)
+    }
+
+    /// Decodes a tree written by `to_binary`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - The encoded tree
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` containing the root of the decoded tree
+    pub fn from_binary(data: &[u8]) -> Result<Rc<RefCell<IksNode>>> {
+        binary::decode(data)
+    }
+
+    /// Serializes this node with one element per line.
+    /// 
+    /// Indentation is only added between elements; elements that contain
+    /// text are printed unchanged, so no whitespace is injected into text.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `indent` - Number of spaces per nesting level
+    /// 
+    /// # Returns
+    /// 
+    /// The indented XML representation of the node
+    pub fn to_string_pretty(&self, indent: usize) -> String {
+        self.to_string_with(&SerializeOptions::new().indent(indent))
+    }
+
+    /// Serializes this subtree as a standalone document.
+    /// 
+    /// The output starts with the standard XML declaration. Namespace
+    /// declarations of the ancestors that are in scope here, and not
+    /// redeclared by this node, are added to its start tag, so a fragment
+    /// pulled out of a larger document (e.g. one stanza of an XMPP stream)
+    /// keeps the meaning of its names. Declarations are copied whether or
+    /// not the subtree uses them.
+    /// 
+    /// # Returns
+    /// 
+    /// The XML document holding a copy of this subtree
+    pub fn to_document(&self) -> String {
+        let mut root = self.clone_shallow();
+        root.attributes.clear();
+        for ancestor in self.ancestors() {
+            for (name, value) in &ancestor.borrow().attributes {
+                let declares = name == "xmlns" || name.starts_with("xmlns:");
+                let shadowed = self.attributes.iter().chain(&root.attributes).any(|(n, _)| n == name);
+                if declares && !shadowed {
+                    root.attributes.push((name.clone(), value.clone()));
+                }
+            }
+        }
+        root.attributes.extend(self.attributes.iter().cloned());
+        // The serializer only follows child links, so sharing them is enough
+        root.children = self.children.clone();
+        root.to_string_with(&SerializeOptions::new().with_prolog())
+    }
+
+    /// Pretty prints only the top levels of this node, for previews.
+    /// 
+    /// Elements nested `max_depth` levels below this node are written as
+    /// `<name>...</name>` when they have any content; see
+    /// `SerializeOptions::collapse_depth`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `indent` - Number of spaces per nesting level
+    /// * `max_depth` - The number of levels below this node to expand
+    /// 
+    /// # Returns
+    /// 
+    /// The indented, possibly elided XML representation of the node
+    pub fn to_string_pretty_depth(&self, indent: usize, max_depth: usize) -> String {
+        self.to_string_with(&SerializeOptions::new().indent(indent).collapse_depth(max_depth))
+    }
+
+    /// Compares two trees structurally.
+    /// 
+    /// Nodes are equal when their type, name, content and attributes (in
+    /// order) match and their children are pairwise equal. Whitespace-only
+    /// text between elements, such as indentation, is ignored; text inside
+    /// elements that contain other text is compared exactly.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `other` - The tree to compare against
+    /// 
+    /// # Returns
+    /// 
+    /// `true` if both trees represent the same document
+    pub fn tree_eq(&self, other: &IksNode) -> bool {
+        self.compare_trees(other, false)
+    }
+
+    /// Compares two trees, either exactly or as `tree_eq` does.
+    /// 
+    /// The trees are walked with an explicit stack, so deep trees do not
+    /// exhaust the call stack.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `other` - The tree to compare against
+    /// * `exact` - Whether whitespace-only text and the CDATA section flag count
+    fn compare_trees(&self, other: &IksNode, exact: bool) -> bool {
+        let node_eq = |a: &IksNode, b: &IksNode| {
+            a.shallow_eq(b) && (!exact || a.cdata_section == b.cdata_section)
+        };
+        let children = |node: &IksNode| {
+            if exact {
+                node.children.clone()
+            } else {
+                node.significant_children()
+            }
+        };
+
+        if !node_eq(self, other) {
+            return false;
+        }
+        let mut stack = vec![(children(self), children(other))];
+        while let Some((left, right)) = stack.pop() {
+            if left.len() != right.len() {
+                return false;
+            }
+            for (a, b) in left.iter().zip(right.iter()) {
+                let (a, b) = (a.borrow(), b.borrow());
+                if !node_eq(&a, &b) {
+                    return false;
+                }
+                stack.push((children(&a), children(&b)));
+            }
+        }
+        true
+    }
+
+    /// Compares the node itself, without its children.
+    fn shallow_eq(&self, other: &IksNode) -> bool {
+        self.node_type == other.node_type
+            && self.name == other.name
+            && self.content == other.content
+            && self.attributes == other.attributes
+    }
+
+    /// Gets the children that carry meaning for structural comparison.
+    fn significant_children(&self) -> Vec<Rc<RefCell<IksNode>>> {
+        if self.has_mixed_content() {
+            return self.children.clone();
+        }
+        self.children.iter()
+            .filter(|child| !child.borrow().is_whitespace())
+            .cloned()
+            .collect()
+    }
+
+    /// Checks if this element holds text other than whitespace.
+    fn has_mixed_content(&self) -> bool {
+        self.content.is_some() || self.children.iter().any(|child| {
+            let child = child.borrow();
+            child.node_type == IksType::CData && !child.is_whitespace()
+        })
     }
 
     /// Gets this node as an Rc if it's part of a tree.
     fn as_rc(&self) -> Option<Rc<RefCell<IksNode>>> {
-        self.parent.as_ref()
-            .and_then(|w| w.upgrade())
-            .map(|p| {
-                p.borrow().children.iter()
-                    .find(|c| Rc::ptr_eq(c, &p))
-                    .cloned()
-            })
-            .flatten()
+        self.self_ref.as_ref().and_then(|w| w.upgrade())
     }
 }
 
+/// Shallow copy of a node.
+/// 
+/// **The copy has no children.** Only the node itself (type, name, content,
+/// attributes) is copied, and the copy is not linked into any tree. This
+/// keeps `clone` cheap and free of reference cycles, but it means that
+/// `node.borrow().clone()` does not copy a parsed document. Prefer the
+/// explicit `clone_shallow` or `deep_clone` to make the intent visible.
 impl Clone for IksNode {
     fn clone(&self) -> Self {
         IksNode {
@@ -463,64 +1947,47 @@ impl Clone for IksNode {
             parent: None,
             next: None,
             prev: None,
+            self_ref: None,
+            cdata_section: self.cdata_section,
+            user_data: None,
+            attribute_sources: self.attribute_sources.clone(),
+            valueless_attributes: self.valueless_attributes.clone(),
+            span: self.span,
         }
     }
 }
 
-impl fmt::Display for IksNode {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.node_type {
-            IksType::Tag => {
-                write!(f, "<{}", self.name.as_ref().unwrap())?;
-                
-                // Write attributes
-                for (name, value) in &self.attributes {
-                    write!(f, " {}=\"{}\"", name, escape_attr(value))?;
-                }
-
-                if self.children.is_empty() && self.content.is_none() {
-                    write!(f, "/>")?;
-                } else {
-                    write!(f, ">")?;
-                    
-                    // Write content if any
-                    if let Some(content) = &self.content {
-                        write!(f, "{}", escape_text(content))?;
-                    }
-
-                    // Write children
-                    for child in &self.children {
-                        write!(f, "{}", child.borrow())?;
-                    }
+/// Exact structural equality.
+/// 
+/// Two nodes are equal when their type, name, content and attributes match
+/// and their children are pairwise equal, all the way down. Attributes are
+/// compared in order, since the order is kept when serializing; every text
+/// node counts, including whitespace-only ones, and a CDATA section is not
+/// equal to plain text with the same content. Parent and sibling links are
+/// not compared, so a subtree equals its detached copy. Use `tree_eq` to
+/// ignore formatting whitespace.
+impl PartialEq for IksNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.compare_trees(other, true)
+    }
+}
 
-                    write!(f, "</{}>", self.name.as_ref().unwrap())?;
-                }
-            }
-            IksType::CData => {
-                if let Some(content) = &self.content {
-                    write!(f, "{}", escape_text(content))?;
-                }
-            }
-            _ => {}
-        }
-        Ok(())
+impl From<&str> for IksNode {
+    fn from(data: &str) -> Self {
+        IksNode::text(data)
     }
 }
 
-/// Escape special XML characters in attribute values
-fn escape_attr(s: &str) -> String {
-    s.replace('&', "&amp;")
-        .replace('\"', "&quot;")
-        .replace('\'', "&apos;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
+impl From<String> for IksNode {
+    fn from(data: String) -> Self {
+        IksNode::text(data)
+    }
 }
 
-/// Escape special XML characters in text content
-fn escape_text(s: &str) -> String {
-    s.replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
+impl fmt::Display for IksNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        serialize::serialize(self, f)
+    }
 }
 
 #[cfg(test)]
@@ -552,6 +2019,409 @@ mod tests {
         assert_eq!(node.to_string(), "<test attr=\"value\">content</test>");
     }
 
+    #[test]
+    fn test_deep_tree_serialization() {
+        const DEPTH: usize = 5000;
+
+        let mut node = IksNode::new_tag("n");
+        node.set_content("leaf");
+        for _ in 1..DEPTH {
+            let mut parent = IksNode::new_tag("n");
+            parent.add_child(node);
+            node = parent;
+        }
+
+        let mut out = Vec::new();
+        node.write_to(&mut out).unwrap();
+        let xml = String::from_utf8(out).unwrap();
+        assert_eq!(xml, format!("{}leaf{}", "<n>".repeat(DEPTH), "</n>".repeat(DEPTH)));
+        assert_eq!(node.to_string(), xml);
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let mut node = IksNode::new_tag("msg");
+        node.add_attribute("to", "juliet@example.com");
+        let mut body = IksNode::new_tag("body");
+        body.insert_cdata("Wherefore art thou, <Romeo> & \u{e7}?");
+        node.add_child(body);
+
+        assert_eq!(node.to_bytes(), node.to_string().into_bytes());
+    }
+
+    #[test]
+    fn test_pretty_print_reparse_stability() {
+        fn parse_preserving(xml: &str) -> Rc<RefCell<IksNode>> {
+            let mut dom = DomParser::new().unwrap();
+            dom.set_preserve_whitespace(true);
+            let mut parser = Parser::new(dom);
+            parser.parse(xml).unwrap();
+            parser.handler().document().unwrap()
+        }
+
+        let xml = "<doc><title>Hi &amp; bye</title><p>Hello <b>world</b>, again </p>\
+                   <list> <item id=\"1\"/><item id=\"2\">two</item></list></doc>";
+        let original = parse_preserving(xml);
+        let pretty = original.borrow().to_string_pretty(2);
+        assert_eq!(
+            pretty,
+            "<doc>\n  <title>Hi &amp; bye</title>\n  <p>Hello <b>world</b>, again </p>\n  \
+             <list>\n    <item id=\"1\"/>\n    <item id=\"2\">two</item>\n  </list>\n</doc>"
+        );
+
+        let reparsed = parse_preserving(&pretty);
+        assert!(original.borrow().tree_eq(&reparsed.borrow()));
+        assert_eq!(reparsed.borrow().to_string_pretty(2), pretty);
+
+        let changed = parse_preserving("<doc><p>Hello<b>world</b>, again </p></doc>");
+        assert!(!original.borrow().tree_eq(&changed.borrow()));
+    }
+
+    #[test]
+    fn test_pretty_print_line_width() {
+        let xml = "<doc><a x=\"1\"/><img src=\"logo.png\" alt=\"Company logo\" width=\"120\"/><b k=\"v\" l=\"w\">\
+                   <c/></b></doc>";
+        let root = DomParser::parse_str(xml).unwrap();
+        let options = SerializeOptions::new().indent(2).max_line_width(40);
+        let pretty = root.borrow().to_string_with(&options);
+        assert_eq!(
+            pretty,
+            "<doc>\n  <a x=\"1\"/>\n  <img\n    src=\"logo.png\"\n    alt=\"Company logo\"\n    width=\"120\"/>\n  \
+             <b k=\"v\" l=\"w\">\n    <c/>\n  </b>\n</doc>"
+        );
+        assert!(root.borrow().tree_eq(&DomParser::parse_str(&pretty).unwrap().borrow()));
+
+        let wide = SerializeOptions::new().indent(2).max_line_width(80);
+        assert_eq!(root.borrow().to_string_with(&wide), root.borrow().to_string_pretty(2));
+    }
+
+    #[test]
+    fn test_pretty_print_depth() {
+        let root = DomParser::parse_str(
+            "<doc><meta/><big id=\"1\"><part><leaf>x</leaf></part></big><name>John</name></doc>"
+        ).unwrap();
+        let root = root.borrow();
+        assert_eq!(
+            root.to_string_pretty_depth(2, 1),
+            "<doc>\n  <meta/>\n  <big id=\"1\">...</big>\n  <name>...</name>\n</doc>"
+        );
+        assert_eq!(
+            root.to_string_pretty_depth(2, 2),
+            "<doc>\n  <meta/>\n  <big id=\"1\">\n    <part>...</part>\n  </big>\n  <name>John</name>\n</doc>"
+        );
+        assert_eq!(root.to_string_pretty_depth(2, 0), "<doc>...</doc>");
+        assert_eq!(root.to_string_pretty_depth(2, 4), root.to_string_pretty(2));
+
+        let mixed = DomParser::parse_str("<a><b>x<c><d/></c></b></a>").unwrap();
+        assert_eq!(mixed.borrow().to_string_pretty_depth(2, 2), "<a>\n  <b>x<c>...</c></b>\n</a>");
+        assert_eq!(mixed.borrow().to_string_pretty_depth(2, 3), "<a>\n  <b>x<c><d/></c></b>\n</a>");
+        let mixed = DomParser::parse_str("<a>x<b><c/></b><e/>y</a>").unwrap();
+        assert_eq!(mixed.borrow().to_string_pretty_depth(2, 1), "<a>x<b>...</b><e/>y</a>");
+    }
+
+    #[test]
+    fn test_namespace_prefixes() {
+        let options = SerializeOptions::new().namespace_prefix("jabber:iq:roster", "roster");
+        for xml in [
+            "<iq><query xmlns='jabber:iq:roster'><item jid='a'/></query></iq>",
+            "<iq xmlns:r='jabber:iq:roster'><r:query><r:item jid='a'/></r:query></iq>",
+            "<iq><x:query xmlns:x='jabber:iq:roster' xmlns='jabber:iq:roster'><item jid='a'/></x:query></iq>",
+        ] {
+            let root = DomParser::parse_str(xml).unwrap();
+            let written = root.borrow().to_string_with(&options);
+            let expected = if xml.starts_with("<iq xmlns") {
+                "<iq xmlns:roster=\"jabber:iq:roster\"><roster:query><roster:item jid=\"a\"/></roster:query></iq>"
+            } else {
+                "<iq><roster:query xmlns:roster=\"jabber:iq:roster\"><roster:item jid=\"a\"/></roster:query></iq>"
+            };
+            assert_eq!(written, expected, "{}", xml);
+        }
+
+        let root = DomParser::parse_str(
+            "<a xmlns='urn:a' xmlns:x='urn:x'><b x:id='1' id='2'/><c xmlns=''/></a>"
+        ).unwrap();
+        let options = SerializeOptions::new().namespace_prefix("urn:a", "p").namespace_prefix("urn:x", "q");
+        assert_eq!(
+            root.borrow().to_string_with(&options),
+            "<p:a xmlns:p=\"urn:a\" xmlns:q=\"urn:x\"><p:b q:id=\"1\" id=\"2\"/><c xmlns=\"\"/></p:a>"
+        );
+        assert_eq!(
+            root.borrow().to_string_with(&options.indent(2)),
+            "<p:a xmlns:p=\"urn:a\" xmlns:q=\"urn:x\">\n  <p:b q:id=\"1\" id=\"2\"/>\n  <c xmlns=\"\"/>\n</p:a>"
+        );
+
+        // An empty prefix is ignored rather than writing `<:q xmlns:="urn:x">`
+        let options = SerializeOptions::new().namespace_prefix("urn:a", "").namespace_prefix("urn:x", "");
+        assert_eq!(
+            root.borrow().to_string_with(&options),
+            "<a xmlns=\"urn:a\" xmlns:x=\"urn:x\"><b x:id=\"1\" id=\"2\"/><c xmlns=\"\"/></a>"
+        );
+        let root = DomParser::parse_str("<x:q xmlns:x='urn:x'/>").unwrap();
+        let options = SerializeOptions::new().namespace_prefix("urn:x", "");
+        assert_eq!(root.borrow().to_string_with(&options), "<x:q xmlns:x=\"urn:x\"/>");
+    }
+
+    #[test]
+    fn test_sorted_attributes() {
+        let root = DomParser::parse_str("<doc z=\"1\" a=\"2\" m=\"3\"><item id=\"7\" class=\"x\">text</item></doc>").unwrap();
+        assert_eq!(
+            root.borrow().to_string(),
+            "<doc z=\"1\" a=\"2\" m=\"3\"><item id=\"7\" class=\"x\">text</item></doc>"
+        );
+
+        let sorted = SerializeOptions::new().sort_attributes();
+        assert_eq!(
+            root.borrow().to_string_with(&sorted),
+            "<doc a=\"2\" m=\"3\" z=\"1\"><item class=\"x\" id=\"7\">text</item></doc>"
+        );
+        assert_eq!(
+            root.borrow().to_string_with(&sorted.indent(2)),
+            "<doc a=\"2\" m=\"3\" z=\"1\">\n  <item class=\"x\" id=\"7\">text</item>\n</doc>"
+        );
+        assert_eq!(root.borrow().attribute_names().collect::<Vec<_>>(), ["z", "a", "m"]);
+    }
+
+    #[test]
+    fn test_attributes_sorted() {
+        let root = DomParser::parse_str("<doc z=\"1\" a=\"2\" m=\"3\" b=\"\"/>").unwrap();
+        let root = root.borrow();
+        assert_eq!(root.attributes_sorted(), [("a", "2"), ("b", ""), ("m", "3"), ("z", "1")]);
+        assert_eq!(root.attributes().collect::<Vec<_>>(), [("z", "1"), ("a", "2"), ("m", "3"), ("b", "")]);
+        assert!(IksNode::new_tag("empty").attributes_sorted().is_empty());
+    }
+
+    #[test]
+    fn test_omit_empty_attributes() {
+        let root = DomParser::parse_str("<input name=\"q\" value=\"\"><hint title=\"\"/></input>").unwrap();
+        assert_eq!(root.borrow().to_string(), "<input name=\"q\" value=\"\"><hint title=\"\"/></input>");
+
+        let options = SerializeOptions::new().omit_empty_attributes();
+        assert_eq!(root.borrow().to_string_with(&options), "<input name=\"q\"><hint/></input>");
+        assert_eq!(root.borrow().find_attrib("value"), Some(""));
+    }
+
+    #[test]
+    fn test_valueless_attributes() {
+        let mut parser = crate::ParserBuilder::new().lenient().build(DomParser::new().unwrap());
+        parser.parse("<form><input disabled name=\"q\" value=\"\"><option selected/></input></form>").unwrap();
+        let root = parser.handler().document().unwrap();
+        let input = root.borrow().find("input").unwrap();
+        assert!(input.borrow().is_valueless_attribute("disabled"));
+        assert!(!input.borrow().is_valueless_attribute("value"));
+        assert!(!input.borrow().is_valueless_attribute("missing"));
+        assert_eq!(input.borrow().find_attrib("disabled"), Some(""));
+
+        let html = SerializeOptions::new().valueless_attributes();
+        assert_eq!(
+            root.borrow().to_string(),
+            "<form><input disabled=\"\" name=\"q\" value=\"\"><option selected=\"\"/></input></form>"
+        );
+        assert_eq!(
+            root.borrow().to_string_with(&html),
+            "<form><input disabled name=\"q\" value=\"\"><option selected/></input></form>"
+        );
+
+        let mut button = IksNode::new_tag("button");
+        button.add_valueless_attribute("hidden");
+        assert_eq!(button.to_string_with(&html), "<button hidden/>");
+        button.clear_attributes();
+        button.add_attribute("hidden", "");
+        assert_eq!(button.to_string_with(&html), "<button hidden=\"\"/>");
+    }
+
+    #[test]
+    fn test_void_and_expanded_elements() {
+        let xhtml = SerializeOptions::new().void_element("br").expand_element("div");
+        let root = DomParser::parse_str("<p>a<br/>b<div/><div class=\"x\"/></p>").unwrap();
+        assert_eq!(root.borrow().to_string(), "<p>a<br/>b<div/><div class=\"x\"/></p>");
+        assert_eq!(
+            root.borrow().to_string_with(&xhtml),
+            "<p>a<br/>b<div></div><div class=\"x\"></div></p>"
+        );
+
+        let mut br = IksNode::new_tag("br");
+        br.set_content("");
+        assert_eq!(br.to_string(), "<br></br>");
+        assert_eq!(br.to_string_with(&xhtml), "<br/>");
+
+        let body = DomParser::parse_str("<body><div/><br/><div><br/></div></body>").unwrap();
+        assert_eq!(
+            body.borrow().to_string_with(&xhtml.indent(2)),
+            "<body>\n  <div></div>\n  <br/>\n  <div>\n    <br/>\n  </div>\n</body>"
+        );
+    }
+
+    #[test]
+    fn test_partial_eq() {
+        let a = DomParser::parse_str("<a x=\"1\" y=\"2\"><b>text</b><c/></a>").unwrap();
+        let b = DomParser::parse_str("<a x=\"1\" y=\"2\"><b>text</b><c/></a>").unwrap();
+        assert_eq!(*a.borrow(), *b.borrow());
+        assert_eq!(*a.borrow().find("b").unwrap().borrow(), *b.borrow().find("b").unwrap().borrow());
+
+        for other in [
+            "<a y=\"2\" x=\"1\"><b>text</b><c/></a>",
+            "<a x=\"1\" y=\"2\"><b>text!</b><c/></a>",
+            "<a x=\"1\" y=\"2\"><b>text</b></a>",
+            "<a x=\"1\" y=\"2\"><b><![CDATA[text]]></b><c/></a>",
+        ] {
+            let mut dom = DomParser::new().unwrap();
+            dom.set_lossless(true);
+            let mut parser = Parser::new(dom);
+            parser.parse(other).unwrap();
+            let other = parser.handler().document().unwrap();
+            assert_ne!(*a.borrow(), *other.borrow());
+        }
+
+        let mut dom = DomParser::new().unwrap();
+        dom.set_preserve_whitespace(true);
+        let mut parser = Parser::new(dom);
+        parser.parse("<a x=\"1\" y=\"2\">\n  <b>text</b>\n  <c/>\n</a>").unwrap();
+        let indented = parser.handler().document().unwrap();
+        assert_ne!(*a.borrow(), *indented.borrow());
+        assert!(a.borrow().tree_eq(&indented.borrow()));
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let mut dom = DomParser::new().unwrap();
+        dom.set_lossless(true);
+        let mut parser = Parser::new(dom);
+        parser.parse("<doc id=\"1\" lang=\"tr\"><!-- note --><p>Merhaba <b>d\u{fc}nya</b></p>\
+                      <?app run?><code><![CDATA[a < b]]></code><empty/></doc>").unwrap();
+        let original = parser.handler().document().unwrap();
+
+        let data = original.borrow().to_binary();
+        assert_eq!(&data[..4], b"IKSB");
+        let decoded = IksNode::from_binary(&data).unwrap();
+        assert_eq!(*decoded.borrow(), *original.borrow());
+        assert_eq!(decoded.borrow().to_string(), original.borrow().to_string());
+
+        let b = decoded.borrow().find("p").unwrap().borrow().find("b").unwrap();
+        let p = b.borrow().parent().unwrap();
+        assert_eq!(p.borrow().name(), Some("p"));
+        assert!(Rc::ptr_eq(&p.borrow().parent().unwrap(), &decoded));
+    }
+
+    #[test]
+    fn test_binary_valueless_attributes() {
+        let mut parser = crate::ParserBuilder::new().lenient().build(DomParser::new().unwrap());
+        parser.parse("<form><input disabled name=\"q\" value=\"\"/></form>").unwrap();
+        let original = parser.handler().document().unwrap();
+
+        let data = original.borrow().to_binary();
+        let decoded = IksNode::from_binary(&data).unwrap();
+        let input = decoded.borrow().find("input").unwrap();
+        assert!(input.borrow().is_valueless_attribute("disabled"));
+        assert!(!input.borrow().is_valueless_attribute("value"));
+        let html = SerializeOptions::new().valueless_attributes();
+        assert_eq!(
+            decoded.borrow().to_string_with(&html),
+            "<form><input disabled name=\"q\" value=\"\"/></form>"
+        );
+
+        // Version 1 has no valueless attributes but is still read
+        let mut old = DomParser::parse_str("<a x=\"1\"><b>text</b></a>").unwrap().borrow().to_binary();
+        old[4] = 1;
+        assert_eq!(IksNode::from_binary(&old).unwrap().borrow().to_string(), "<a x=\"1\"><b>text</b></a>");
+        let mut old = data.clone();
+        old[4] = 1;
+        assert!(matches!(IksNode::from_binary(&old), Err(IksError::BadBinary)));
+    }
+
+    #[test]
+    fn test_binary_rejects_corrupt_input() {
+        let data = DomParser::parse_str("<a x=\"1\"><b>text</b></a>").unwrap().borrow().to_binary();
+        assert!(IksNode::from_binary(&data).is_ok());
+
+        assert!(matches!(IksNode::from_binary(b""), Err(IksError::BadBinary)));
+        assert!(matches!(IksNode::from_binary(b"<a/>"), Err(IksError::BadBinary)));
+        for len in 0..data.len() {
+            assert!(matches!(IksNode::from_binary(&data[..len]), Err(IksError::BadBinary)));
+        }
+
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(IksNode::from_binary(&trailing).is_err());
+
+        let mut version = data.clone();
+        version[4] = 99;
+        assert!(IksNode::from_binary(&version).is_err());
+
+        let mut node_type = data.clone();
+        node_type[5] = 42;
+        assert!(IksNode::from_binary(&node_type).is_err());
+    }
+
+    #[test]
+    fn test_shallow_and_deep_clone() {
+        let root = DomParser::parse_str("<a x=\"1\"><b>text<c/></b><d/></a>").unwrap();
+        let root = root.borrow();
+
+        let shallow = root.clone_shallow();
+        assert_eq!(shallow.to_string(), "<a x=\"1\"/>");
+        assert!(!shallow.has_children());
+
+        let deep = root.deep_clone();
+        assert_eq!(deep, *root);
+        assert!(deep.parent().is_none());
+
+        let deep = deep.into_rc();
+        let b = deep.borrow().find("b").unwrap();
+        assert!(Rc::ptr_eq(&b.borrow().parent().unwrap(), &deep));
+        assert_eq!(b.borrow().next().unwrap().borrow().name(), Some("d"));
+        let c = b.borrow().find("c").unwrap();
+        assert!(Rc::ptr_eq(&c.borrow().parent().unwrap(), &b));
+        assert_eq!(c.borrow().prev().unwrap().borrow().content(), Some("text"));
+        b.borrow_mut().add_attribute("changed", "yes");
+        assert!(root.find("b").unwrap().borrow().find_attrib("changed").is_none());
+    }
+
+    #[test]
+    fn test_serialize_without_escaping() {
+        let mut node = IksNode::new_tag("a");
+        node.add_attribute("plain", "value");
+        node.add_attribute("quoted", "it's \"x\" & <y>");
+        node.insert_cdata("plain text");
+        node.insert_cdata(" & <escaped>");
+
+        assert_eq!(
+            node.to_string(),
+            "<a plain=\"value\" quoted=\"it&apos;s &quot;x&quot; &amp; &lt;y&gt;\">plain text &amp; &lt;escaped&gt;</a>"
+        );
+    }
+
+    #[test]
+    fn test_serialize_with_prolog() {
+        let mut root = IksNode::new_tag("root");
+        root.add_child(IksNode::new_tag("child"));
+
+        assert_eq!(root.to_string(), "<root><child/></root>");
+
+        let options = SerializeOptions::new().with_prolog();
+        let xml = root.to_string_with(&options);
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?><root>"));
+        assert_eq!(xml.matches("<?xml").count(), 1);
+
+        let mut out = Vec::new();
+        root.write_to_with(&mut out, &SerializeOptions::new().prolog("<?xml version=\"1.1\"?>")).unwrap();
+        assert_eq!(out, b"<?xml version=\"1.1\"?><root><child/></root>");
+    }
+
+    #[test]
+    fn test_cdata_end_marker_in_output() {
+        let mut text = IksNode::new_tag("a");
+        text.insert_cdata("x]]>y");
+        assert_eq!(text.to_string(), "<a>x]]&gt;y</a>");
+
+        let mut section = IksNode::new_tag("a");
+        section.add_child(IksNode::new_cdata_section("x]]>y"));
+        let xml = section.to_string();
+        assert_eq!(xml, "<a><![CDATA[x]]]]><![CDATA[>y]]></a>");
+
+        let reparsed = DomParser::parse_str(&xml).unwrap();
+        assert_eq!(reparsed.borrow().to_string(), "<a>x]]&gt;y</a>");
+    }
+
     #[test]
     fn test_node_navigation() {
         let root = Rc::new(RefCell::new(IksNode::new_tag("root")));
@@ -584,18 +2454,472 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_detach_and_reattach() {
+        let root = DomParser::parse_str("<root><a><x id=\"1\"/></a><b/><c/></root>").unwrap();
+        let a = root.borrow().find("a").unwrap();
+        let b = root.borrow().find("b").unwrap();
+        let c = root.borrow().find("c").unwrap();
+        let x = a.borrow().find("x").unwrap();
+
+        IksNode::detach(&x);
+        assert!(x.borrow().parent().is_none());
+        assert!(!a.borrow().has_children());
+
+        IksNode::append_child(&b, x.clone());
+        assert!(Rc::ptr_eq(&x.borrow().parent().unwrap(), &b));
+        assert_eq!(root.borrow().to_string(), "<root><a/><b><x id=\"1\"/></b><c/></root>");
+
+        // Detaching a middle child relinks its siblings
+        IksNode::detach(&b);
+        assert!(Rc::ptr_eq(&a.borrow().next().unwrap(), &c));
+        assert!(Rc::ptr_eq(&c.borrow().prev().unwrap(), &a));
+        assert_eq!(root.borrow().to_string(), "<root><a/><c/></root>");
+
+        // Detaching a node without a parent is a no-op
+        IksNode::detach(&b);
+        assert_eq!(b.borrow().to_string(), "<b><x id=\"1\"/></b>");
+    }
+
     #[test]
     fn test_cdata_handling() {
         let root = Rc::new(RefCell::new(IksNode::new_tag("root")));
         
         let mut child = IksNode::new_tag("child");
-        let cdata = child.insert_cdata("Hello World");
+        child.insert_cdata("Hello World");
         root.borrow_mut().add_child(child);
         
         let content = root.borrow().find_cdata("child").unwrap();
         assert_eq!(content, "Hello World");
     }
 
+    #[test]
+    fn test_text_nodes() {
+        let mut greeting = IksNode::new_tag("greeting");
+        greeting.add_child(IksNode::text("Hello, "));
+        greeting.add_child(IksNode::from(String::from("<world>")));
+        greeting.add_child("!".into());
+
+        assert_eq!(greeting.to_string(), "<greeting>Hello, &lt;world&gt;!</greeting>");
+        assert_eq!(greeting.text_content(), "Hello, <world>!");
+    }
+
+    #[test]
+    fn test_text_content_skips_markup() {
+        let mut dom = DomParser::new().unwrap();
+        dom.set_lossless(true);
+        let mut parser = Parser::new(dom);
+        parser.parse("<notes><note>a<!--secret--><?pi data?>b<![CDATA[c]]></note></notes>").unwrap();
+        let root = parser.handler().document().unwrap();
+        let root = root.borrow();
+        assert_eq!(root.text_content(), "abc");
+        assert_eq!(root.find_cdata_deep("note").as_deref(), Some("abc"));
+        assert_eq!(root.to_string_map()["note"], ["abc"]);
+
+        let comment = root.find("note").unwrap().borrow().children().nth(1).unwrap();
+        assert_eq!(comment.borrow().node_type(), IksType::Comment);
+        assert_eq!(comment.borrow().text_content(), "");
+    }
+
+    #[test]
+    fn test_find_path() {
+        let root = DomParser::parse_str(
+            "<catalog><item id=\"4\"><name>Pen</name></item><item id=\"5\" sale=\"\"><name>Ink</name></item>\
+             <group><item id=\"5\"><name>Nib</name></item></group></catalog>"
+        ).unwrap();
+        let root = root.borrow();
+        let text = |node: Option<Rc<RefCell<IksNode>>>| node.map(|n| n.borrow().text_content());
+
+        assert_eq!(text(root.find_path("item/name")), Some("Pen".to_string()));
+        assert_eq!(text(root.find_path("item[@id='5']/name")), Some("Ink".to_string()));
+        assert_eq!(text(root.find_path("item[@id=\"5\"]/name")), Some("Ink".to_string()));
+        assert_eq!(text(root.find_path("item[@sale]/name")), Some("Ink".to_string()));
+        assert_eq!(text(root.find_path("group/item[@id='5']/name")), Some("Nib".to_string()));
+        assert_eq!(text(root.find_path("*/item/name")), Some("Nib".to_string()));
+
+        assert!(root.find_path("item[@id='6']/name").is_none());
+        assert!(root.find_path("item[@color]").is_none());
+        assert!(root.find_path("item/price").is_none());
+        assert!(root.find_path("item[@id='5'/name").is_none());
+        assert!(root.find_path("item//name").is_none());
+    }
+
+    #[test]
+    fn test_node_path() {
+        let root = DomParser::parse_str(
+            "<root><item><name>a</name></item><meta/><item><name>b</name><note/>text<note/></item></root>"
+        ).unwrap();
+        let second = root.borrow().tag_child_at(2).unwrap();
+        let name = second.borrow().find("name").unwrap();
+        assert_eq!(name.borrow().path(), "/root/item[2]/name");
+        assert_eq!(name.borrow_mut().path(), "/root/item[2]/name");
+        assert_eq!(name.borrow().ancestors().count(), 2);
+
+        assert_eq!(root.borrow().path(), "/root");
+        assert_eq!(root.borrow().tag_child_at(1).unwrap().borrow().path(), "/root/meta");
+        assert_eq!(second.borrow().tag_child_at(2).unwrap().borrow().path(), "/root/item[2]/note[2]");
+        assert_eq!(second.borrow().child_at(2).unwrap().borrow().path(), "/root/item[2]/text()");
+        assert_eq!(name.borrow().child_at(0).unwrap().borrow().path(), "/root/item[2]/name/text()");
+    }
+
+    #[test]
+    fn test_acyclic() {
+        let root = DomParser::parse_str("<a><b><c/>text<d/></b><e/><f>x</f></a>").unwrap();
+        root.borrow().assert_acyclic();
+        let b = root.borrow().find("b").unwrap();
+        b.borrow().assert_acyclic();
+
+        let mut built = IksNode::new_tag("root");
+        built.add_child(IksNode::new_tag("one"));
+        built.add_child(IksNode::new_tag("two"));
+        built.assert_acyclic();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "strong reference cycle")]
+    fn test_cycle_detected() {
+        let root = IksNode::new_tag("root").into_rc();
+        let child = IksNode::append_child(&root, IksNode::new_tag("child").into_rc());
+        child.borrow_mut().next = Some(root.clone());
+        root.borrow().assert_acyclic();
+    }
+
+    #[test]
+    fn test_wrap() {
+        let root = DomParser::parse_str("<doc><a/><item id=\"1\"/><b/></doc>").unwrap();
+        let item = root.borrow().find("item").unwrap();
+        let list = IksNode::wrap(item.clone(), "list");
+
+        assert_eq!(root.borrow().to_string(), "<doc><a/><list><item id=\"1\"/></list><b/></doc>");
+        assert!(Rc::ptr_eq(&list.borrow().parent().unwrap(), &root));
+        assert!(Rc::ptr_eq(&item.borrow().parent().unwrap(), &list));
+        assert!(item.borrow().prev().is_none() && item.borrow().next().is_none());
+        let a = root.borrow().find("a").unwrap();
+        let b = root.borrow().find("b").unwrap();
+        assert!(Rc::ptr_eq(&a.borrow().next().unwrap(), &list));
+        assert!(Rc::ptr_eq(&b.borrow().prev().unwrap(), &list));
+        assert!(Rc::ptr_eq(&list.borrow().prev().unwrap(), &a));
+        assert_eq!(item.borrow().path(), "/doc/list/item");
+        root.borrow().assert_acyclic();
+
+        let wrapped = IksNode::wrap(root.clone(), "envelope");
+        assert!(wrapped.borrow().parent().is_none());
+        assert!(Rc::ptr_eq(&root.borrow().parent().unwrap(), &wrapped));
+        assert_eq!(wrapped.borrow().to_string(), "<envelope><doc><a/><list><item id=\"1\"/></list><b/></doc></envelope>");
+    }
+
+    #[test]
+    fn test_to_string_map() {
+        let root = DomParser::parse_str(
+            "<config><host>a</host><port>80</port>text<host>b</host><empty/><!-- c --></config>"
+        ).unwrap();
+        let map = root.borrow().to_string_map();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["host"], ["a", "b"]);
+        assert_eq!(map["port"], ["80"]);
+        assert_eq!(map["empty"], [""]);
+    }
+
+    #[test]
+    fn test_inner_xml() {
+        let root = DomParser::parse_str("<a><b/>text</a>").unwrap();
+        assert_eq!(root.borrow().inner_xml(), "<b/>text");
+
+        let root = DomParser::parse_str("<a x=\"1\">1 &lt; 2<c><d y=\"2\">deep</d></c></a>").unwrap();
+        assert_eq!(root.borrow().inner_xml(), "1 &lt; 2<c><d y=\"2\">deep</d></c>");
+        assert_eq!(root.borrow().find("c").unwrap().borrow().inner_xml(), "<d y=\"2\">deep</d>");
+        assert_eq!(IksNode::new_tag("empty").inner_xml(), "");
+        assert_eq!(IksNode::text("plain").inner_xml(), "");
+    }
+
+    #[test]
+    fn test_find_ns() {
+        let query = |xml: &str| {
+            let root = DomParser::parse_str(xml).unwrap();
+            let found = root.borrow().find_ns("jabber:iq:roster", "query");
+            found.map(|node| node.borrow().name().unwrap().to_string())
+        };
+        assert_eq!(query("<iq><query xmlns='jabber:iq:roster'/></iq>").as_deref(), Some("query"));
+        assert_eq!(query("<iq xmlns:r='jabber:iq:roster'><r:query/></iq>").as_deref(), Some("r:query"));
+        assert_eq!(query("<iq><x:query xmlns:x='jabber:iq:roster'/></iq>").as_deref(), Some("x:query"));
+        assert_eq!(query("<iq xmlns='jabber:iq:roster'><query/></iq>").as_deref(), Some("query"));
+        assert_eq!(query("<iq xmlns='jabber:iq:roster'><query xmlns=''/></iq>"), None);
+        assert_eq!(query("<iq><query xmlns='jabber:iq:auth'/><query xmlns='jabber:iq:roster'/></iq>").as_deref(), Some("query"));
+        assert_eq!(query("<iq><query/></iq>"), None);
+
+        let root = DomParser::parse_str("<s xmlns:r='jabber:iq:roster'><iq><r:query><item/></r:query></iq></s>").unwrap();
+        let iq = root.borrow().find("iq").unwrap();
+        let found = iq.borrow().find_ns("jabber:iq:roster", "query").unwrap();
+        assert_eq!(found.borrow().namespace_uri().as_deref(), Some("jabber:iq:roster"));
+        assert_eq!(found.borrow().find("item").unwrap().borrow().namespace_uri(), None);
+        assert_eq!(found.borrow().lookup_namespace(Some("xml")).as_deref(), Some(xml::XML_NAMESPACE));
+    }
+
+    #[test]
+    fn test_container_node() {
+        let mut container = IksNode::new(IksType::None);
+        container.add_child(IksNode::new_tag("a"));
+        let mut b = IksNode::new_tag("b");
+        b.add_attribute("x", "1");
+        b.add_child(IksNode::new_tag("c"));
+        container.add_child(b);
+
+        assert_eq!(container.to_string(), "<a/><b x=\"1\"><c/></b>");
+        assert_eq!(container.inner_xml(), container.to_string());
+        assert_eq!(container.to_string_pretty(2), "<a/>\n<b x=\"1\">\n  <c/>\n</b>");
+        let events = container.to_events();
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[0], Event::StartTag { name: "a".to_string(), attributes: Vec::new() });
+
+        let mut wrapper = IksNode::new_tag("root");
+        wrapper.add_child(container);
+        assert_eq!(wrapper.to_string(), "<root><a/><b x=\"1\"><c/></b></root>");
+    }
+
+    #[test]
+    fn test_move_attributes() {
+        let mut source = IksNode::new_tag("item");
+        source.add_attribute("jid", "romeo@montague.net");
+        source.add_attribute("name", "Romeo");
+        source.add_attribute("subscription", "both");
+
+        let mut target = IksNode::new_tag("contact");
+        target.add_attribute("group", "Friends");
+        for (name, value) in source.drain_attributes() {
+            target.add_attribute(name, value);
+        }
+        assert!(!source.has_attributes());
+        assert_eq!(target.attribute_names().collect::<Vec<_>>(), ["group", "jid", "name", "subscription"]);
+        assert_eq!(target.find_attrib("name"), Some("Romeo"));
+
+        let owned: Vec<_> = target.into_attributes().collect();
+        assert_eq!(owned[0], ("group".to_string(), "Friends".to_string()));
+        assert_eq!(owned.len(), 4);
+    }
+
+    #[test]
+    fn test_to_document() {
+        let root = DomParser::parse_str(
+            "<stream:stream xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams' to='ex.org'>\
+             <message xmlns:x='urn:x' to='juliet'><body>hi</body><x:ping/></message></stream:stream>"
+        ).unwrap();
+        let message = root.borrow().find("message").unwrap();
+        let doc = message.borrow().to_document();
+        assert_eq!(
+            doc,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><message xmlns=\"jabber:client\" \
+             xmlns:stream=\"http://etherx.jabber.org/streams\" xmlns:x=\"urn:x\" to=\"juliet\">\
+             <body>hi</body><x:ping/></message>"
+        );
+
+        let extracted = DomParser::parse_str(&doc).unwrap();
+        assert_eq!(extracted.borrow().namespace_uri().as_deref(), Some("jabber:client"));
+        assert!(extracted.borrow().find_ns("urn:x", "ping").is_some());
+        assert_eq!(message.borrow().parent().map(|p| p.borrow().child_count()), Some(1));
+        assert_eq!(message.borrow().child_count(), 2);
+    }
+
+    #[test]
+    fn test_reorder_children() {
+        let root = DomParser::parse_str("<list><i n=\"3\"/><i n=\"1\"/>text<i n=\"2\"/></list>").unwrap();
+        let key = |node: &IksNode| node.find_attrib("n").map(str::to_string);
+        root.borrow_mut().sort_children_by(|a, b| key(a).cmp(&key(b)));
+        assert_eq!(root.borrow().to_string(), "<list>text<i n=\"1\"/><i n=\"2\"/><i n=\"3\"/></list>");
+
+        let check_links = |root: &Rc<RefCell<IksNode>>| {
+            let children: Vec<_> = root.borrow().children().collect();
+            for (index, child) in children.iter().enumerate() {
+                let child = child.borrow();
+                assert!(Rc::ptr_eq(&child.parent().unwrap(), root));
+                assert_eq!(child.prev().map(|p| Rc::as_ptr(&p)), index.checked_sub(1).map(|i| Rc::as_ptr(&children[i])));
+                assert_eq!(child.next().map(|n| Rc::as_ptr(&n)), children.get(index + 1).map(Rc::as_ptr));
+            }
+        };
+        check_links(&root);
+
+        root.borrow_mut().swap_children(0, 3);
+        assert_eq!(root.borrow().to_string(), "<list><i n=\"3\"/><i n=\"1\"/><i n=\"2\"/>text</list>");
+        check_links(&root);
+        assert_eq!(root.borrow().first_tag().unwrap().borrow().find_attrib("n"), Some("3"));
+        root.borrow().assert_acyclic();
+    }
+
+    #[test]
+    fn test_insert_comment() {
+        let root = IksNode::new_tag("config").into_rc();
+        let comment = root.borrow_mut().insert_comment(" generated, do not edit ").unwrap();
+        assert_eq!(comment.borrow().node_type(), IksType::Comment);
+        IksNode::append_child(&root, IksNode::new_tag("item").into_rc());
+        assert_eq!(root.borrow().to_string(), "<config><!-- generated, do not edit --><item/></config>");
+        assert!(Rc::ptr_eq(&comment.borrow().parent().unwrap(), &root));
+    }
+
+    #[test]
+    fn test_insert_comment_rejects_double_hyphen() {
+        let mut root = IksNode::new_tag("config");
+        for text in ["a -- b", "--", "trailing-"] {
+            assert!(matches!(root.insert_comment(text), Err(IksError::BadXml)), "{}", text);
+        }
+        assert!(!root.has_children());
+        assert!(root.insert_comment(" a - b ").is_ok());
+    }
+
+    #[test]
+    fn test_is_whitespace() {
+        let mut dom = DomParser::new().unwrap();
+        dom.set_preserve_whitespace(true);
+        let mut parser = Parser::new(dom);
+        parser.parse("<a>\n  <b> \t </b>\n  <c> x </c><d/>\n</a>").unwrap();
+        let root = parser.finish_document().unwrap();
+        let root = root.borrow();
+        assert!(root.children().next().unwrap().borrow().is_whitespace());
+        assert!(!root.is_whitespace());
+        let b = root.find("b").unwrap();
+        assert!(b.borrow().children().next().unwrap().borrow().is_whitespace());
+        let c = root.find("c").unwrap();
+        assert!(!c.borrow().children().next().unwrap().borrow().is_whitespace());
+        assert!(!root.find("d").unwrap().borrow().is_whitespace());
+
+        assert!(IksNode::text("").is_whitespace());
+        assert!(IksNode::text(" \r\n\t").is_whitespace());
+        assert!(IksNode::new_cdata_section("  ").is_whitespace());
+        assert!(!IksNode::text(" mixed\n").is_whitespace());
+        assert!(!IksNode::new_comment(" ").is_whitespace());
+    }
+
+    #[test]
+    fn test_clear() {
+        let root = DomParser::parse_str("<tpl id=\"1\" lang=\"en\"><a>x</a><b/>text</tpl>").unwrap();
+        let a = root.borrow().find("a").unwrap();
+        let b = root.borrow().find("b").unwrap();
+
+        root.borrow_mut().clear_children();
+        assert_eq!(root.borrow().child_count(), 0);
+        assert!(a.borrow().parent().is_none());
+        assert!(a.borrow().next().is_none());
+        assert!(b.borrow().prev().is_none());
+        assert_eq!(a.borrow().to_string(), "<a>x</a>");
+
+        root.borrow_mut().clear_attributes();
+        assert!(!root.borrow().has_attributes());
+        assert_eq!(root.borrow().to_string(), "<tpl/>");
+        IksNode::append_child(&root, IksNode::new_tag("c").into_rc());
+        assert_eq!(root.borrow().to_string(), "<tpl><c/></tpl>");
+    }
+
+    #[test]
+    fn test_positional_access() {
+        let root = DomParser::parse_str("<p>Hello <b>bold</b> and <i>italic</i>!</p>").unwrap();
+        let root = root.borrow();
+        assert_eq!(root.child_count(), 5);
+
+        assert_eq!(root.child_at(0).unwrap().borrow().content(), Some("Hello "));
+        assert_eq!(root.child_at(1).unwrap().borrow().name(), Some("b"));
+        assert_eq!(root.child_at(4).unwrap().borrow().content(), Some("!"));
+        assert!(root.child_at(5).is_none());
+
+        assert_eq!(root.tag_child_at(0).unwrap().borrow().name(), Some("b"));
+        assert_eq!(root.tag_child_at(1).unwrap().borrow().name(), Some("i"));
+        assert!(root.tag_child_at(2).is_none());
+        assert_eq!(root.child_at(1).unwrap().borrow().child_count(), 1);
+    }
+
+    #[test]
+    fn test_count_tags() {
+        let root = DomParser::parse_str(
+            "<list><item>a</item><group><item>b<item>c</item></item><other/></group><!-- item --><item/>item</list>"
+        ).unwrap();
+        let root = root.borrow();
+        assert_eq!(root.count_tags("item"), 4);
+        assert_eq!(root.count_tags("group"), 1);
+        assert_eq!(root.count_tags("list"), 0);
+        assert_eq!(root.find("group").unwrap().borrow().count_tags("item"), 2);
+        assert_eq!(IksNode::new_tag("item").count_tags("item"), 0);
+    }
+
+    #[test]
+    fn test_find_by_predicate() {
+        let root = DomParser::parse_str(
+            "<doc><list id=\"a\"><i/><i/></list><group><list id=\"b\"><i/><i/><i/><i/></list></group>\
+             <list id=\"c\"><i/><i/><i/><i/><i/></list></doc>"
+        ).unwrap();
+        let big = |node: &IksNode| node.node_type == IksType::Tag && node.children.len() > 3;
+
+        let child = root.borrow().find_by(big).unwrap();
+        assert_eq!(child.borrow().find_attrib("id"), Some("c"));
+        let descendant = root.borrow().find_by_deep(big).unwrap();
+        assert_eq!(descendant.borrow().find_attrib("id"), Some("b"));
+
+        assert!(root.borrow().find_by(|node| node.name() == Some("group")).is_some());
+        assert!(root.borrow().find_by_deep(|node| node.children.len() > 5).is_none());
+    }
+
+    #[test]
+    fn test_user_data() {
+        #[derive(Debug, PartialEq)]
+        struct Annotation {
+            dirty: bool,
+            line: usize,
+        }
+
+        let root = DomParser::parse_str("<a><b/></a>").unwrap();
+        let b = root.borrow().find("b").unwrap();
+        assert!(b.borrow().user_data::<Annotation>().is_none());
+
+        b.borrow_mut().set_user_data(Annotation { dirty: false, line: 1 });
+        b.borrow_mut().user_data_mut::<Annotation>().unwrap().dirty = true;
+        assert_eq!(b.borrow().user_data(), Some(&Annotation { dirty: true, line: 1 }));
+        assert!(b.borrow().user_data::<String>().is_none());
+        assert!(b.borrow().clone().user_data::<Annotation>().is_none());
+        assert_eq!(root.borrow().to_string(), "<a><b/></a>");
+
+        assert!(b.borrow_mut().take_user_data().is_some());
+        assert!(b.borrow().user_data::<Annotation>().is_none());
+    }
+
+    #[test]
+    fn test_qname() {
+        let node = IksNode::new_tag("stream:features");
+        assert_eq!(node.qname(), (Some("stream"), "features"));
+
+        let node = IksNode::new_tag("features");
+        assert_eq!(node.qname(), (None, "features"));
+    }
+
+    #[test]
+    fn test_replace_text_content() {
+        let xml = "<server><host>example.com</host><port proto=\"tcp\">80</port><path>/</path></server>";
+        let root = DomParser::parse_str(xml).unwrap();
+
+        let port = root.borrow().find("port").unwrap();
+        let old = port.borrow_mut().replace_text_content("443");
+        assert_eq!(old.as_deref(), Some("80"));
+        assert_eq!(
+            root.borrow().to_string(),
+            "<server><host>example.com</host><port proto=\"tcp\">443</port><path>/</path></server>"
+        );
+
+        let mut empty = IksNode::new_tag("port");
+        assert_eq!(empty.replace_text_content("8080"), None);
+        assert_eq!(empty.to_string(), "<port>8080</port>");
+    }
+
+    #[test]
+    fn test_find_cdata_deep_and_default() {
+        let xml = "<config><server><net><host>example.com</host><port>80</port></net></server><name>demo</name></config>";
+        let root = DomParser::parse_str(xml).unwrap();
+        let root = root.borrow();
+
+        assert_eq!(root.find_cdata("host"), None);
+        assert_eq!(root.find_cdata_deep("host").as_deref(), Some("example.com"));
+        assert_eq!(root.find_cdata_deep("net").as_deref(), Some("example.com80"));
+        assert_eq!(root.find_cdata_deep("missing"), None);
+
+        assert_eq!(root.find_cdata_or("name", "default"), "demo");
+        assert_eq!(root.find_cdata_or("timeout", "30"), "30");
+    }
+
     #[test]
     fn test_attributes() {
         let mut node = IksNode::new_tag("test");
@@ -607,4 +2931,17 @@ mod tests {
         assert_eq!(node.find_attrib("class"), Some("test"));
         assert_eq!(node.find_attrib("missing"), None);
     }
+
+    #[test]
+    fn test_attribute_iterators() {
+        let mut node = IksNode::new_tag("item");
+        node.add_attribute("id", "7");
+        node.add_attribute("type", "book");
+        node.add_attribute("lang", "tr");
+
+        assert_eq!(node.attribute_names().collect::<Vec<_>>(), ["id", "type", "lang"]);
+        assert_eq!(node.attribute_values().collect::<Vec<_>>(), ["7", "book", "tr"]);
+        assert_eq!(node.attributes().nth(1), Some(("type", "book")));
+        assert_eq!(IksNode::new_tag("empty").attribute_names().count(), 0);
+    }
 } 
\ No newline at end of file
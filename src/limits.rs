@@ -0,0 +1,331 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::fmt;
+use crate::{Doctype, IksError, Result, SaxHandler, Span, Symbol, TagType};
+use crate::constants::xml;
+
+/// A limit enforced by `LimitCheckingHandler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// Nesting depth of elements
+    Depth,
+    /// Number of attributes of one tag
+    Attributes,
+    /// Length of a tag name
+    TagLength,
+    /// Length of an attribute name
+    AttrNameLength,
+    /// Length of a decoded attribute value
+    AttrValueLength,
+    /// Length of a run of character data, or of one CDATA section
+    CDataLength,
+    /// Length of a comment
+    CommentLength,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Depth => "nesting depth",
+            Limit::Attributes => "attribute count",
+            Limit::TagLength => "tag name length",
+            Limit::AttrNameLength => "attribute name length",
+            Limit::AttrValueLength => "attribute value length",
+            Limit::CDataLength => "character data length",
+            Limit::CommentLength => "comment length",
+        })
+    }
+}
+
+/// The bounds checked by `LimitCheckingHandler`.
+///
+/// Lengths are in bytes. The defaults are the `MAX_*` constants of the
+/// `xml` module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum nesting depth of elements
+    pub max_depth: usize,
+    /// Maximum number of attributes of one tag
+    pub max_attributes: usize,
+    /// Maximum length of a tag name
+    pub max_tag_length: usize,
+    /// Maximum length of an attribute name
+    pub max_attr_name_length: usize,
+    /// Maximum length of a decoded attribute value
+    pub max_attr_value_length: usize,
+    /// Maximum length of character data between two pieces of markup, and
+    /// of a single CDATA section
+    pub max_cdata_length: usize,
+    /// Maximum length of a comment
+    pub max_comment_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: xml::MAX_NESTING_DEPTH,
+            max_attributes: xml::MAX_ATTRIBUTES,
+            max_tag_length: xml::MAX_TAG_LENGTH,
+            max_attr_name_length: xml::MAX_ATTR_NAME_LENGTH,
+            max_attr_value_length: xml::MAX_ATTR_VALUE_LENGTH,
+            max_cdata_length: xml::MAX_CDATA_LENGTH,
+            max_comment_length: xml::MAX_COMMENT_LENGTH,
+        }
+    }
+}
+
+/// Wraps a handler and rejects input exceeding a set of `Limits`.
+///
+/// Every event is checked before it is passed on, and the first one that
+/// crosses a limit fails the parse with `IksError::LimitExceeded`, naming
+/// the limit. This adds validation to any handler without configuring the
+/// parser; the parser's own limits, such as `ParserConfig::max_depth`,
+/// still apply.
+///
+/// The wrapper never asks for attributes one at a time, so a wrapped
+/// `Streaming` handler gets them replayed from `on_tag`.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::{DomParser, IksError, Limit, LimitCheckingHandler, Limits, Parser};
+///
+/// let limits = Limits { max_depth: 2, ..Limits::default() };
+/// let mut parser = Parser::new(LimitCheckingHandler::with_limits(DomParser::new().unwrap(), limits));
+/// match parser.parse("<a><b><c/></b></a>") {
+///     Err(IksError::LimitExceeded { limit, max }) => assert_eq!((limit, max), (Limit::Depth, 2)),
+///     other => panic!("unexpected result: {:?}", other),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct LimitCheckingHandler<H> {
+    handler: H,
+    limits: Limits,
+    /// Number of open elements
+    depth: usize,
+    /// Length of the character data since the last markup
+    text: usize,
+}
+
+impl<H: SaxHandler> LimitCheckingHandler<H> {
+    /// Wraps a handler, checking the default limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler to pass the checked events to
+    ///
+    /// # Returns
+    ///
+    /// A new `LimitCheckingHandler` instance
+    pub fn new(handler: H) -> Self {
+        Self::with_limits(handler, Limits::default())
+    }
+
+    /// Wraps a handler, checking the given limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler to pass the checked events to
+    /// * `limits` - The limits to enforce
+    ///
+    /// # Returns
+    ///
+    /// A new `LimitCheckingHandler` instance
+    pub fn with_limits(handler: H, limits: Limits) -> Self {
+        Self { handler, limits, depth: 0, text: 0 }
+    }
+
+    /// Gets the enforced limits.
+    ///
+    /// # Returns
+    ///
+    /// A reference to the limits
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Gets a reference to the wrapped handler.
+    ///
+    /// # Returns
+    ///
+    /// A reference to the handler
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Gets a mutable reference to the wrapped handler.
+    ///
+    /// # Returns
+    ///
+    /// A mutable reference to the handler
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Unwraps the handler.
+    ///
+    /// # Returns
+    ///
+    /// The wrapped handler
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+
+    /// Checks a tag against the limits and tracks the nesting depth.
+    fn check_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+        self.text = 0;
+        check(Limit::TagLength, name.len(), self.limits.max_tag_length)?;
+        if tag_type == TagType::Close {
+            self.depth = self.depth.saturating_sub(1);
+            return Ok(());
+        }
+        check(Limit::Depth, self.depth + 1, self.limits.max_depth)?;
+        check(Limit::Attributes, attributes.len(), self.limits.max_attributes)?;
+        for (name, value) in attributes {
+            check(Limit::AttrNameLength, name.len(), self.limits.max_attr_name_length)?;
+            check(Limit::AttrValueLength, value.len(), self.limits.max_attr_value_length)?;
+        }
+        if tag_type == TagType::Open {
+            self.depth += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Fails with `IksError::LimitExceeded` if a value is above its maximum.
+fn check(limit: Limit, value: usize, max: usize) -> Result<()> {
+    if value > max {
+        return Err(IksError::LimitExceeded { limit, max });
+    }
+    Ok(())
+}
+
+impl<H: SaxHandler> SaxHandler for LimitCheckingHandler<H> {
+    type Error = H::Error;
+
+    fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<(), H::Error> {
+        self.check_tag(name, attributes, tag_type)?;
+        self.handler.on_tag(name, attributes, tag_type)
+    }
+
+    fn on_tag_symbol(&mut self, symbol: Symbol, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<(), H::Error> {
+        self.check_tag(name, attributes, tag_type)?;
+        self.handler.on_tag_symbol(symbol, name, attributes, tag_type)
+    }
+
+    fn on_cdata(&mut self, data: &str) -> Result<(), H::Error> {
+        // Chunk boundaries split text into several calls
+        self.text += data.len();
+        check(Limit::CDataLength, self.text, self.limits.max_cdata_length)?;
+        self.handler.on_cdata(data)
+    }
+
+    fn on_cdata_section(&mut self, data: &str) -> Result<(), H::Error> {
+        self.text = 0;
+        check(Limit::CDataLength, data.len(), self.limits.max_cdata_length)?;
+        self.handler.on_cdata_section(data)
+    }
+
+    fn on_comment(&mut self, text: &str) -> Result<(), H::Error> {
+        self.text = 0;
+        check(Limit::CommentLength, text.len(), self.limits.max_comment_length)?;
+        self.handler.on_comment(text)
+    }
+
+    fn on_pi(&mut self, target: &str, data: &str) -> Result<(), H::Error> {
+        self.text = 0;
+        self.handler.on_pi(target, data)
+    }
+
+    fn on_tag_span(&mut self, span: Span) {
+        self.handler.on_tag_span(span);
+    }
+
+    fn on_attribute_source(&mut self, name: &str, raw: &str) {
+        self.handler.on_attribute_source(name, raw);
+    }
+
+    fn on_valueless_attribute(&mut self, name: &str) {
+        self.handler.on_valueless_attribute(name);
+    }
+
+    fn on_doctype(&mut self, doctype: &Doctype) -> Result<(), H::Error> {
+        self.handler.on_doctype(doctype)
+    }
+
+    fn on_markup_decl(&mut self, decl: &str) -> Result<(), H::Error> {
+        self.handler.on_markup_decl(decl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DomParser, Parser};
+
+    fn tripped(limits: Limits, xml: &str) -> Option<(Limit, usize)> {
+        let mut parser = Parser::new(LimitCheckingHandler::with_limits(DomParser::new().unwrap(), limits));
+        let result = xml.as_bytes().chunks(5).try_for_each(|chunk| parser.parse_bytes(chunk));
+        match result {
+            Ok(()) => None,
+            Err(IksError::LimitExceeded { limit, max }) => Some((limit, max)),
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_each_limit() {
+        let small = Limits {
+            max_depth: 2,
+            max_attributes: 2,
+            max_tag_length: 4,
+            max_attr_name_length: 3,
+            max_attr_value_length: 5,
+            max_cdata_length: 8,
+            max_comment_length: 6,
+        };
+        let within = "<a x='1' yy='abcde'><b>12345678</b><b><![CDATA[12345678]]><!--123456--></b></a>";
+        assert_eq!(tripped(small.clone(), within), None);
+
+        for (xml, limit, max) in [
+            ("<a><b><c/></b></a>", Limit::Depth, 2),
+            ("<a x='1' y='2' z='3'/>", Limit::Attributes, 2),
+            ("<abcde/>", Limit::TagLength, 4),
+            ("<a long='1'/>", Limit::AttrNameLength, 3),
+            ("<a x='abc&amp;de'/>", Limit::AttrValueLength, 5),
+            ("<a>123456789</a>", Limit::CDataLength, 8),
+            ("<a><![CDATA[123456789]]></a>", Limit::CDataLength, 8),
+            ("<a><!--1234567--></a>", Limit::CommentLength, 6),
+        ] {
+            assert_eq!(tripped(small.clone(), xml), Some((limit, max)), "{}", xml);
+        }
+        assert_eq!(tripped(small.clone(), "<a>1234<b/>56789</a>"), None);
+        assert_eq!(tripped(small, "<a>1234<!---->5678</a>"), None);
+
+        let error = IksError::LimitExceeded { limit: Limit::AttrNameLength, max: 3 };
+        assert_eq!(error.to_string(), "Limit on attribute name length (3) exceeded");
+    }
+
+    #[test]
+    fn test_default_limits() {
+        let mut parser = Parser::new(LimitCheckingHandler::new(DomParser::new().unwrap()));
+        parser.parse("<a><b x='1'>text</b></a>").unwrap();
+        assert_eq!(parser.handler().limits().max_depth, xml::MAX_NESTING_DEPTH);
+        let root = parser.handler().handler().document().unwrap();
+        assert_eq!(root.borrow().to_string(), "<a><b x=\"1\">text</b></a>");
+
+        let deep = "<a>".repeat(xml::MAX_NESTING_DEPTH + 1);
+        assert_eq!(tripped(Limits::default(), &deep), Some((Limit::Depth, xml::MAX_NESTING_DEPTH)));
+    }
+}
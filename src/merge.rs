@@ -0,0 +1,243 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::collections::HashSet;
+use crate::{IksNode, IksType};
+
+/// How `IksNode::merge` combines the attributes of two elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttributeMerge {
+    /// An attribute of the other element replaces one of the same name
+    #[default]
+    Override,
+    /// An attribute already present is kept; only new names are added
+    Keep,
+}
+
+/// How `IksNode::merge` combines the children of two elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChildMerge {
+    /// Copies of all children of the other element are appended
+    #[default]
+    Append,
+    /// Child elements of the other element replace all child elements of
+    /// the same name, at the position of the first one they replace; other
+    /// children are appended
+    ReplaceByName,
+}
+
+/// How `IksNode::merge` combines two elements.
+///
+/// The default overrides attributes and appends children.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::{AttributeMerge, ChildMerge, DomParser, MergeStrategy};
+///
+/// let base = DomParser::parse_str("<config level='1'><host>a</host><port>80</port></config>").unwrap();
+/// let patch = DomParser::parse_str("<config level='2'><host>b</host></config>").unwrap();
+/// let strategy = MergeStrategy::new()
+///     .attributes(AttributeMerge::Override)
+///     .children(ChildMerge::ReplaceByName);
+/// base.borrow_mut().merge(&patch.borrow(), strategy);
+/// assert_eq!(base.borrow().to_string(), "<config level=\"2\"><host>b</host><port>80</port></config>");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MergeStrategy {
+    /// How attributes are combined
+    pub attributes: AttributeMerge,
+    /// How children are combined
+    pub children: ChildMerge,
+}
+
+impl MergeStrategy {
+    /// Creates the default strategy.
+    ///
+    /// # Returns
+    ///
+    /// A new `MergeStrategy` instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how attributes are combined.
+    ///
+    /// # Arguments
+    ///
+    /// * `merge` - The attribute strategy
+    ///
+    /// # Returns
+    ///
+    /// The updated strategy
+    pub fn attributes(mut self, merge: AttributeMerge) -> Self {
+        self.attributes = merge;
+        self
+    }
+
+    /// Sets how children are combined.
+    ///
+    /// # Arguments
+    ///
+    /// * `merge` - The child strategy
+    ///
+    /// # Returns
+    ///
+    /// The updated strategy
+    pub fn children(mut self, merge: ChildMerge) -> Self {
+        self.children = merge;
+        self
+    }
+}
+
+/// Merges the attributes and children of `other` into `node`.
+///
+/// # Arguments
+///
+/// * `node` - The element receiving the merge
+/// * `other` - The element to take attributes and copies of children from
+/// * `strategy` - How to combine the two
+pub(crate) fn merge(node: &mut IksNode, other: &IksNode, strategy: MergeStrategy) {
+    for (name, value) in &other.attributes {
+        match node.attributes.iter_mut().find(|(existing, _)| existing == name) {
+            Some(existing) => {
+                if strategy.attributes == AttributeMerge::Override {
+                    existing.1 = value.clone();
+                }
+            }
+            None => node.attributes.push((name.clone(), value.clone())),
+        }
+    }
+
+    match strategy.children {
+        ChildMerge::Append => {
+            for child in &other.children {
+                node.add_child(child.borrow().deep_clone());
+            }
+        }
+        ChildMerge::ReplaceByName => replace_by_name(node, other),
+    }
+}
+
+/// Gets the name of a node if it is an element.
+fn element_name(node: &IksNode) -> Option<&str> {
+    match node.node_type {
+        IksType::Tag => node.name.as_deref(),
+        _ => None,
+    }
+}
+
+/// Replaces the child elements of `node` that share a name with a child
+/// element of `other`.
+fn replace_by_name(node: &mut IksNode, other: &IksNode) {
+    let replacing: HashSet<String> = other.children.iter()
+        .filter_map(|child| element_name(&child.borrow()).map(str::to_string))
+        .collect();
+    let copies_named = |name: &str| {
+        other.children.iter()
+            .filter(|child| element_name(&child.borrow()) == Some(name))
+            .map(|child| child.borrow().deep_clone())
+            .collect::<Vec<_>>()
+    };
+
+    let mut kept = Vec::new();
+    let mut copies = Vec::new();
+    let mut inserted = HashSet::new();
+    for child in std::mem::take(&mut node.children) {
+        let name = element_name(&child.borrow()).map(str::to_string);
+        match name {
+            Some(name) if replacing.contains(&name) => {
+                if inserted.insert(name.clone()) {
+                    // Remember where the copies go; they are linked below
+                    copies.push((kept.len(), copies_named(&name)));
+                }
+                let mut removed = child.borrow_mut();
+                removed.parent = None;
+                removed.prev = None;
+                removed.next = None;
+            }
+            _ => kept.push(child),
+        }
+    }
+
+    let mut children = Vec::with_capacity(kept.len());
+    let mut copies = copies.into_iter().peekable();
+    for (index, child) in kept.into_iter().enumerate() {
+        while let Some((_, nodes)) = copies.next_if(|(at, _)| *at == index) {
+            children.extend(nodes.into_iter().map(IksNode::into_rc));
+        }
+        children.push(child);
+    }
+    for (_, nodes) in copies {
+        children.extend(nodes.into_iter().map(IksNode::into_rc));
+    }
+    for child in &other.children {
+        let child = child.borrow();
+        if element_name(&child).is_none_or(|name| !inserted.contains(name)) {
+            children.push(child.deep_clone().into_rc());
+        }
+    }
+
+    node.children = children;
+    node.relink_children();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use crate::DomParser;
+
+    #[test]
+    fn test_attribute_strategies() {
+        let base = DomParser::parse_str("<server host='a' port='80'/>").unwrap();
+        let patch = DomParser::parse_str("<server port='8080' tls='on'/>").unwrap();
+
+        let overridden = base.borrow().deep_clone().into_rc();
+        overridden.borrow_mut().merge(&patch.borrow(), MergeStrategy::new());
+        assert_eq!(overridden.borrow().to_string(), "<server host=\"a\" port=\"8080\" tls=\"on\"/>");
+
+        let kept = base.borrow().deep_clone().into_rc();
+        kept.borrow_mut().merge(&patch.borrow(), MergeStrategy::new().attributes(AttributeMerge::Keep));
+        assert_eq!(kept.borrow().to_string(), "<server host=\"a\" port=\"80\" tls=\"on\"/>");
+    }
+
+    #[test]
+    fn test_child_strategies() {
+        let base = DomParser::parse_str("<config><host>a</host><host>b</host><port>80</port></config>").unwrap();
+        let patch = DomParser::parse_str("<config><host>c</host><user>me</user></config>").unwrap();
+
+        let appended = base.borrow().deep_clone().into_rc();
+        appended.borrow_mut().merge(&patch.borrow(), MergeStrategy::new());
+        assert_eq!(
+            appended.borrow().to_string(),
+            "<config><host>a</host><host>b</host><port>80</port><host>c</host><user>me</user></config>"
+        );
+        assert_eq!(appended.borrow().to_string_map()["host"], ["a", "b", "c"]);
+
+        let replaced = base.borrow().deep_clone().into_rc();
+        replaced.borrow_mut().merge(&patch.borrow(), MergeStrategy::new().children(ChildMerge::ReplaceByName));
+        assert_eq!(
+            replaced.borrow().to_string(),
+            "<config><host>c</host><port>80</port><user>me</user></config>"
+        );
+        let host = replaced.borrow().find("host").unwrap();
+        assert!(Rc::ptr_eq(&host.borrow().parent().unwrap(), &replaced));
+        let port = host.borrow().next().unwrap();
+        assert_eq!(port.borrow().name(), Some("port"));
+        assert!(Rc::ptr_eq(&port.borrow().prev().unwrap(), &host));
+        replaced.borrow().assert_acyclic();
+
+        assert_eq!(patch.borrow().to_string(), "<config><host>c</host><user>me</user></config>");
+    }
+}
@@ -11,58 +11,133 @@
  Affero General Public License for more details.
 */
 
+use std::fmt;
+use std::collections::HashMap;
+use std::io;
 use std::str;
-use crate::{IksError, Result, TagType};
+use crate::{Doctype, IksError, InvalidUtf8, ParserConfig, Result, StreamingSaxHandler, Symbol, SymbolTable, TagType};
+use crate::constants::{memory, xml};
+use crate::helper::escape_size;
+use crate::utility::escape;
 
-/// Helper function to calculate the size needed for escaping a string.
+/// Appends the replacement text of an entity or character reference.
 /// 
 /// # Arguments
 /// 
-/// * `s` - The string to calculate escape size for
+/// * `config` - The parser configuration holding the custom entities
+/// * `xml11` - Whether the document declared XML version 1.1
+/// * `name` - The reference between `&` and `;`
+/// * `out` - The buffer to append the replacement text to
 /// 
 /// # Returns
 /// 
-/// The number of characters needed to escape the string
-fn escape_size(s: &str) -> usize {
-    s.chars().map(|c| match c {
-        '&' => 5,  // &amp;
-        '<' => 4,  // &lt;
-        '>' => 4,  // &gt;
-        '"' => 6,  // &quot;
-        '\'' => 6, // &apos;
-        _ => 1,
-    }).sum()
+/// A `Result` that fails for unknown entities in strict mode and for
+/// references to characters the XML version does not allow
+fn expand_reference(config: &ParserConfig, xml11: bool, name: &str, out: &mut String) -> Result<()> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix('x') {
+            Some(hex) if !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                u32::from_str_radix(hex, 16).map_err(|_| IksError::BadXml)?
+            }
+            None if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) => {
+                number.parse().map_err(|_| IksError::BadXml)?
+            }
+            _ => return Err(IksError::BadXml),
+        };
+        // XML 1.1 allows every control character except NUL as a reference
+        let allowed = match char::from_u32(code) {
+            Some('\t' | '\n' | '\r') => true,
+            Some('\u{0}' | '\u{FFFE}' | '\u{FFFF}') | None => false,
+            Some('\u{1}'..='\u{1F}') => xml11,
+            Some(_) => true,
+        };
+        if !allowed {
+            return Err(IksError::InvalidChar(code));
+        }
+        out.extend(char::from_u32(code));
+        return Ok(());
+    }
+    match config.resolve_entity(name) {
+        Some(entity) => out.push_str(entity),
+        None if config.lenient => {
+            out.push('&');
+            out.push_str(name);
+            out.push(';');
+        }
+        None => return Err(IksError::BadXml),
+    }
+    Ok(())
+}
+
+/// Finds the value of a pseudo-attribute such as `version` in the XML declaration.
+/// 
+/// # Arguments
+/// 
+/// * `data` - The declaration after `<?xml`
+/// * `name` - The pseudo-attribute to look up
+/// 
+/// # Returns
+/// 
+/// An `Option` containing the quoted value if the pseudo-attribute is present
+fn pseudo_attribute<'a>(data: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = data;
+    while let Some(start) = rest.find(name) {
+        let after = rest[start + name.len()..].trim_start();
+        if let Some(value) = after.strip_prefix('=') {
+            let value = value.trim_start();
+            let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+            let end = value[1..].find(quote)? + 1;
+            return Some(&value[1..end]);
+        }
+        rest = &rest[start + name.len()..];
+    }
+    None
 }
 
-/// Helper function to escape XML special characters.
+/// Checks whether text consists only of XML whitespace.
+/// 
+/// Unlike `str::trim`, this does not treat other Unicode spaces as blank.
 /// 
 /// # Arguments
 /// 
-/// * `s` - The string to escape
+/// * `text` - The text to check
 /// 
 /// # Returns
 /// 
-/// The escaped string
-fn escape(s: &str) -> String {
-    let mut result = String::with_capacity(escape_size(s));
-    for c in s.chars() {
-        match c {
-            '&' => result.push_str("&amp;"),
-            '<' => result.push_str("&lt;"),
-            '>' => result.push_str("&gt;"),
-            '"' => result.push_str("&quot;"),
-            '\'' => result.push_str("&apos;"),
-            _ => result.push(c),
-        }
-    }
-    result
+/// `true` if `text` is empty or made only of spaces, tabs and line breaks
+fn is_xml_blank(text: &str) -> bool {
+    text.chars().all(|c| matches!(c, ' ' | '\t' | '\n' | '\r'))
+}
+
+/// Converts an element or attribute name to lowercase in place.
+/// 
+/// # Arguments
+/// 
+/// * `name` - The name to convert
+fn lowercase(name: &mut String) {
+    if name.is_ascii() {
+        name.make_ascii_lowercase();
+    } else {
+        *name = name.to_lowercase();
+    }
 }
 
+/// Callback receiving raw input, see `Parser::set_tee`.
+type TeeHook = Box<dyn FnMut(&[u8])>;
+
 /// Trait for handling SAX-style XML parsing events.
 /// 
 /// This trait defines the callbacks that will be invoked during XML parsing.
 /// Implement this trait to handle XML parsing events in a streaming fashion.
 pub trait SaxHandler {
+    /// The error the callbacks fail with.
+    /// 
+    /// Errors found by the parser itself are converted into it, so a
+    /// handler can use an error type of its own, e.g. an enum with a
+    /// variant wrapping `IksError`, and get it back from `Parser::parse`
+    /// unchanged. Handlers without errors of their own use `IksError`.
+    type Error: From<IksError>;
+
     /// Called when a tag is encountered during parsing.
     /// 
     /// # Arguments
@@ -74,7 +149,7 @@ pub trait SaxHandler {
     /// # Returns
     /// 
     /// A `Result` indicating success or failure
-    fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()>;
+    fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<(), Self::Error>;
     
     /// Called when character data is encountered during parsing.
     /// 
@@ -85,11 +160,202 @@ pub trait SaxHandler {
     /// # Returns
     /// 
     /// A `Result` indicating success or failure
-    fn on_cdata(&mut self, data: &str) -> Result<()>;
+    fn on_cdata(&mut self, data: &str) -> Result<(), Self::Error>;
+
+    /// Called instead of `on_tag` when the parser interns tag names.
+    /// 
+    /// The parser only calls this method after `Parser::set_symbols`. The
+    /// default implementation ignores the symbol and forwards to `on_tag`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `symbol` - The symbol of the tag name in the parser's table
+    /// * `name` - The name of the tag
+    /// * `attributes` - Vector of (name, value) pairs for the tag's attributes
+    /// * `tag_type` - The type of tag (open, close, or single)
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn on_tag_symbol(&mut self, _symbol: Symbol, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<(), Self::Error> {
+        self.on_tag(name, attributes, tag_type)
+    }
+
+    /// Called with the content of a `<![CDATA[ ... ]]>` section.
+    /// 
+    /// The default implementation treats the section as ordinary character
+    /// data and forwards it to `on_cdata`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - The text inside the section
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn on_cdata_section(&mut self, data: &str) -> Result<(), Self::Error> {
+        self.on_cdata(data)
+    }
+
+    /// Called when a comment is encountered during parsing.
+    /// 
+    /// The default implementation ignores comments.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `text` - The text between `<!--` and `-->`
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn on_comment(&mut self, _text: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called when a processing instruction is encountered during parsing.
+    /// 
+    /// The XML declaration `<?xml ...?>` is reported like any other
+    /// processing instruction. The default implementation ignores them.
+    /// 
+    /// The parser only accepts the declaration at the very start of the
+    /// input, after an optional byte order mark; in lenient mode it may
+    /// also follow whitespace. Anywhere else it fails with
+    /// `IksError::MisplacedDeclaration`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `target` - The name following `<?`
+    /// * `data` - The rest of the instruction, without leading whitespace
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn on_pi(&mut self, _target: &str, _data: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called with the location of a tag right before `on_tag` reports it.
+    /// 
+    /// The default implementation ignores the location; handlers that map
+    /// nodes back to their source can record it.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `span` - The location of the tag, from `<` up to and including `>`
+    fn on_tag_span(&mut self, _span: Span) {}
+
+    /// Called with the source form of an attribute value that contains
+    /// references, before the attribute is reported.
+    /// 
+    /// The default implementation ignores it; handlers that need to write
+    /// the value back exactly as it was, e.g. keeping `&#38;` instead of
+    /// `&amp;`, can record it.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the attribute
+    /// * `raw` - The text between the quotes, with references undecoded
+    fn on_attribute_source(&mut self, _name: &str, _raw: &str) {}
+
+    /// Called for an attribute written without a value, before it is
+    /// reported with an empty value.
+    /// 
+    /// Such attributes, like `disabled` in `<input disabled>`, are only
+    /// accepted in lenient mode. The default implementation ignores them.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the attribute
+    fn on_valueless_attribute(&mut self, _name: &str) {}
+
+    /// Called when a `<!DOCTYPE ...>` declaration has been parsed.
+    /// 
+    /// The default implementation ignores the declaration.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `doctype` - The parsed declaration
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn on_doctype(&mut self, _doctype: &Doctype) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for a markup declaration other than `<!DOCTYPE ...>`.
+    /// 
+    /// Declarations such as `<!ENTITY ...>` or `<!ELEMENT ...>` outside a
+    /// document type declaration are consumed without being interpreted;
+    /// the default implementation ignores them.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `decl` - The text between `<!` and `>`
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn on_markup_decl(&mut self, _decl: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Gets the handler that wants start tags reported attribute by attribute.
+    /// 
+    /// When this returns a handler, the parser calls its `on_start_tag` and
+    /// `on_attribute` while reading a start tag instead of collecting the
+    /// attributes, and `on_tag` then gets an empty attribute list. The
+    /// default implementation returns `None`; `Streaming` returns itself.
+    /// 
+    /// # Returns
+    /// 
+    /// The streaming handler, or `None` to receive attributes through `on_tag`
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingSaxHandler> {
+        None
+    }
+}
+
+/// A position in the parser input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    /// Byte offset from the start of the input
+    pub offset: usize,
+    /// Line number (1-based)
+    pub line: usize,
+    /// Column number, as reported by `Parser::column`
+    pub column: usize,
+}
+
+/// A range of the parser input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    /// Position of the first character
+    pub start: Position,
+    /// Position of the last character; its `offset` points just past it
+    pub end: Position,
+}
+
+/// Counts of what a parser has seen, see `Parser::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ParseStats {
+    /// Opening tags of elements with content, like `<a>`
+    pub open_tags: usize,
+    /// Closing tags, like `</a>`
+    pub close_tags: usize,
+    /// Empty-element tags, like `<a/>`
+    pub single_tags: usize,
+    /// Calls of `on_cdata` and `on_cdata_section`
+    pub text_events: usize,
+    /// Bytes of decoded character data reported in those calls
+    pub text_bytes: usize,
+    /// Bytes of input consumed
+    pub bytes: usize,
+    /// Deepest element nesting reached
+    pub max_depth: usize,
 }
 
 /// Represents the current state of the XML parser.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 enum State {
     /// Parsing character data
     CData,
@@ -103,28 +369,22 @@ enum State {
     Attribute,
     /// Parsing an attribute name
     AttributeName,
+    /// Expecting the `=` after an attribute name
+    AttributeEq,
     /// Parsing an attribute value
     AttributeValue,
     /// Parsing a single-quoted attribute value
     ValueApos,
     /// Parsing a double-quoted attribute value
     ValueQuot,
-    /// Parsing whitespace
-    Whitespace,
     /// Parsing an entity
     Entity,
     /// Parsing a comment
     Comment,
-    /// At the end of a comment
-    CommentEnd,
     /// Parsing markup
     Markup,
-    /// At the end of markup
+    /// Inside a markup declaration such as `<!DOCTYPE ...>`
     MarkupEnd,
-    /// Parsing a CDATA section
-    CDataSection,
-    /// At the end of a CDATA section
-    CDataSectionEnd,
     /// First dash of a comment
     Comment1,
     /// Second dash of a comment
@@ -151,8 +411,98 @@ enum State {
     SectCDataE2,
     /// Parsing a processing instruction
     Pi,
-    /// Parsing a UTF-8 sequence
-    Utf8Sequence,
+    /// Question mark that may end a processing instruction
+    PiEnd,
+}
+
+/// What the input held before its first markup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lead {
+    /// Nothing, apart from a byte order mark
+    Empty,
+    /// Only whitespace
+    Blank,
+    /// Other text
+    Text,
+}
+
+/// A markup declaration being read.
+#[derive(Debug, Clone, Default)]
+struct Declaration {
+    /// Text after `<!` read so far
+    text: String,
+    /// The quote character of the literal being read, if any
+    quote: Option<char>,
+    /// Nesting depth of `[` brackets, e.g. an internal DTD subset
+    brackets: usize,
+    /// Whether a comment inside the brackets is being read
+    in_comment: bool,
+}
+
+impl Declaration {
+    /// Adds a character to the declaration.
+    /// 
+    /// Quoted literals, bracketed sections and comments inside them may all
+    /// contain a `>` that does not end the declaration.
+    /// 
+    /// # Returns
+    /// 
+    /// `true` if the character was the `>` ending the declaration
+    fn feed(&mut self, c: char) -> bool {
+        if self.in_comment {
+            self.text.push(c);
+            self.in_comment = !self.text.ends_with("-->");
+            return false;
+        }
+        match (self.quote, c) {
+            (Some(quote), _) if c == quote => self.quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => self.quote = Some(c),
+            (None, '[') => self.brackets += 1,
+            (None, ']') => self.brackets = self.brackets.saturating_sub(1),
+            (None, '>') if self.brackets == 0 => return true,
+            _ => {}
+        }
+        self.text.push(c);
+        if self.brackets > 0 && self.text.ends_with("<!--") {
+            self.in_comment = true;
+        }
+        false
+    }
+}
+
+/// A saved copy of a parser's position in the input.
+/// 
+/// Created by `Parser::snapshot` and applied again with `Parser::restore`.
+/// It holds the state machine and all partially parsed buffers, but not
+/// the handler: events delivered after the snapshot are not undone.
+#[derive(Debug, Clone)]
+pub struct ParserState {
+    state: State,
+    buffer: String,
+    tag_name: String,
+    attr_name: String,
+    attr_value: String,
+    attributes: Vec<(String, String)>,
+    tag_type: TagType,
+    entity: String,
+    line: usize,
+    column: usize,
+    offset: usize,
+    tag_start: Position,
+    attr_start: Position,
+    token_start: Position,
+    depth: usize,
+    elements: usize,
+    roots: usize,
+    declaration: Declaration,
+    markup: String,
+    xml11: bool,
+    utf8: Vec<u8>,
+    markups: usize,
+    lead: Lead,
+    attribute_defaults: HashMap<String, Vec<(String, String)>>,
+    streamed_attributes: Vec<String>,
 }
 
 /// SAX-style XML parser that processes XML data and calls appropriate handler methods.
@@ -163,11 +513,13 @@ enum State {
 /// # Examples
 /// 
 /// ```
-/// use iksemel::{Parser, SaxHandler, TagType, Result};
+/// use iksemel::{IksError, Parser, SaxHandler, TagType, Result};
 /// 
 /// struct MyHandler;
 /// 
 /// impl SaxHandler for MyHandler {
+///     type Error = IksError;
+/// 
 ///     fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
 ///         println!("Found tag: {} ({:?})", name, tag_type);
 ///         Ok(())
@@ -193,10 +545,32 @@ pub struct Parser<H: SaxHandler> {
     attributes: Vec<(String, String)>,
     tag_type: TagType,
     entity: String,
-    utf8_sequence: u32,
-    utf8_bytes_left: u8,
     line: usize,
     column: usize,
+    offset: usize,
+    tag_start: Position,
+    attr_start: Position,
+    token_start: Position,
+    depth: usize,
+    elements: usize,
+    roots: usize,
+    declaration: Declaration,
+    markup: String,
+    xml11: bool,
+    utf8: Vec<u8>,
+    /// Number of `<` seen outside of markup, including the current one
+    markups: usize,
+    lead: Lead,
+    config: ParserConfig,
+    symbols: Option<SymbolTable>,
+    tee: Option<TeeHook>,
+    stats: ParseStats,
+    /// Default attribute values from the DTD by element name, kept when
+    /// `ParserConfig::apply_dtd_defaults` is set
+    attribute_defaults: HashMap<String, Vec<(String, String)>>,
+    /// Names of the attributes of the current tag already reported to a
+    /// streaming handler, kept only for elements with DTD defaults
+    streamed_attributes: Vec<String>,
 }
 
 impl<H: SaxHandler> Parser<H> {
@@ -210,6 +584,20 @@ impl<H: SaxHandler> Parser<H> {
     /// 
     /// A new `Parser` instance
     pub fn new(handler: H) -> Self {
+        Self::with_config(handler, ParserConfig::default())
+    }
+
+    /// Creates a new parser with the given handler and configuration.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `handler` - The handler to receive parsing events
+    /// * `config` - The parser configuration
+    /// 
+    /// # Returns
+    /// 
+    /// A new `Parser` instance
+    pub fn with_config(handler: H, config: ParserConfig) -> Self {
         Parser {
             handler,
             state: State::CData,
@@ -220,13 +608,149 @@ impl<H: SaxHandler> Parser<H> {
             attributes: Vec::new(),
             tag_type: TagType::Open,
             entity: String::new(),
-            utf8_sequence: 0,
-            utf8_bytes_left: 0,
-            line: 1,
+            line: if config.track_positions { 1 } else { 0 },
             column: 0,
+            offset: 0,
+            tag_start: Position::default(),
+            attr_start: Position::default(),
+            token_start: Position::default(),
+            depth: 0,
+            elements: 0,
+            roots: 0,
+            declaration: Declaration::default(),
+            markup: String::new(),
+            xml11: false,
+            utf8: Vec::new(),
+            markups: 0,
+            lead: Lead::Empty,
+            config,
+            symbols: None,
+            tee: None,
+            stats: ParseStats::default(),
+            attribute_defaults: HashMap::new(),
+            streamed_attributes: Vec::new(),
+        }
+    }
+
+    /// Captures the current parsing state.
+    /// 
+    /// Together with `restore` this allows retrying after a transient
+    /// error, e.g. when a chunk turns out to be corrupted.
+    /// 
+    /// # Returns
+    /// 
+    /// A `ParserState` that can be restored later
+    pub fn snapshot(&self) -> ParserState {
+        ParserState {
+            state: self.state.clone(),
+            buffer: self.buffer.clone(),
+            tag_name: self.tag_name.clone(),
+            attr_name: self.attr_name.clone(),
+            attr_value: self.attr_value.clone(),
+            attributes: self.attributes.clone(),
+            tag_type: self.tag_type,
+            entity: self.entity.clone(),
+            line: self.line,
+            column: self.column,
+            offset: self.offset,
+            tag_start: self.tag_start,
+            attr_start: self.attr_start,
+            token_start: self.token_start,
+            depth: self.depth,
+            elements: self.elements,
+            roots: self.roots,
+            declaration: self.declaration.clone(),
+            markup: self.markup.clone(),
+            xml11: self.xml11,
+            utf8: self.utf8.clone(),
+            markups: self.markups,
+            lead: self.lead,
+            attribute_defaults: self.attribute_defaults.clone(),
+            streamed_attributes: self.streamed_attributes.clone(),
         }
     }
 
+    /// Restores a previously captured parsing state.
+    /// 
+    /// The handler is left untouched.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `state` - The state returned by an earlier `snapshot`
+    pub fn restore(&mut self, state: ParserState) {
+        self.state = state.state;
+        self.buffer = state.buffer;
+        self.tag_name = state.tag_name;
+        self.attr_name = state.attr_name;
+        self.attr_value = state.attr_value;
+        self.attributes = state.attributes;
+        self.tag_type = state.tag_type;
+        self.entity = state.entity;
+        self.line = state.line;
+        self.column = state.column;
+        self.offset = state.offset;
+        self.tag_start = state.tag_start;
+        self.attr_start = state.attr_start;
+        self.token_start = state.token_start;
+        self.depth = state.depth;
+        self.elements = state.elements;
+        self.roots = state.roots;
+        self.declaration = state.declaration;
+        self.markup = state.markup;
+        self.xml11 = state.xml11;
+        self.utf8 = state.utf8;
+        self.markups = state.markups;
+        self.lead = state.lead;
+        self.attribute_defaults = state.attribute_defaults;
+        self.streamed_attributes = state.streamed_attributes;
+    }
+
+    /// Gets the parser configuration.
+    /// 
+    /// # Returns
+    /// 
+    /// A reference to the configuration
+    pub fn config(&self) -> &ParserConfig {
+        &self.config
+    }
+
+    /// Enables interning of tag names.
+    /// 
+    /// Every tag name is looked up in the table, and added when it is new,
+    /// and the handler receives its symbol through `on_tag_symbol`. Since
+    /// unknown names are added too, the table grows with the number of
+    /// distinct names in the input; use `ParserConfig::allowed_elements` to
+    /// bound it for untrusted input.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `symbols` - The table to intern names in, usually pre-seeded by the handler
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = Some(symbols);
+    }
+
+    /// Gets the table used to intern tag names.
+    /// 
+    /// # Returns
+    /// 
+    /// The table passed to `set_symbols`, with all names seen so far
+    pub fn symbols(&self) -> Option<&SymbolTable> {
+        self.symbols.as_ref()
+    }
+
+    /// Sets a callback receiving every chunk of input before it is parsed.
+    /// 
+    /// The callback gets the data exactly as passed to `parse` or
+    /// `parse_bytes`, so the raw stream can be logged, e.g. for debugging a
+    /// connection, without the caller keeping its own copy.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tee` - The callback receiving the raw input
+    pub fn set_tee<F: FnMut(&[u8]) + 'static>(&mut self, tee: F) {
+        self.tee = Some(Box::new(tee));
+    }
+
     /// Gets a reference to the handler.
     /// 
     /// # Returns
@@ -245,6 +769,71 @@ impl<H: SaxHandler> Parser<H> {
         &mut self.handler
     }
 
+    /// Replaces the handler, keeping the position in the input.
+    /// 
+    /// Partially parsed markup stays buffered in the parser, so the new
+    /// handler receives all events from the next one on. This suits
+    /// protocols that change processing mode mid-stream, such as XMPP
+    /// switching from stream negotiation to stanza routing.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `handler` - The handler to receive the following events
+    /// 
+    /// # Returns
+    /// 
+    /// The previous handler
+    pub fn set_handler(&mut self, handler: H) -> H {
+        std::mem::replace(&mut self.handler, handler)
+    }
+
+    /// Transforms the handler, possibly into one of another type.
+    /// 
+    /// Like `set_handler` this keeps the parser state, but it can build the
+    /// new handler from the old one.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `f` - Turns the current handler into the new one
+    /// 
+    /// # Returns
+    /// 
+    /// A parser continuing at the same position with the new handler
+    pub fn map_handler<H2: SaxHandler, F: FnOnce(H) -> H2>(self, f: F) -> Parser<H2> {
+        Parser {
+            handler: f(self.handler),
+            state: self.state,
+            buffer: self.buffer,
+            tag_name: self.tag_name,
+            attr_name: self.attr_name,
+            attr_value: self.attr_value,
+            attributes: self.attributes,
+            tag_type: self.tag_type,
+            entity: self.entity,
+            line: self.line,
+            column: self.column,
+            offset: self.offset,
+            tag_start: self.tag_start,
+            attr_start: self.attr_start,
+            token_start: self.token_start,
+            depth: self.depth,
+            elements: self.elements,
+            roots: self.roots,
+            declaration: self.declaration,
+            markup: self.markup,
+            xml11: self.xml11,
+            utf8: self.utf8,
+            markups: self.markups,
+            lead: self.lead,
+            config: self.config,
+            symbols: self.symbols,
+            tee: self.tee,
+            stats: self.stats,
+            attribute_defaults: self.attribute_defaults,
+            streamed_attributes: self.streamed_attributes,
+        }
+    }
+
     /// Parses a chunk of XML data.
     /// 
     /// This method processes the input string character by character,
@@ -258,12 +847,35 @@ impl<H: SaxHandler> Parser<H> {
     /// # Returns
     /// 
     /// A `Result` indicating success or failure
-    pub fn parse(&mut self, data: &str) -> Result<()> {
+    pub fn parse(&mut self, data: &str) -> Result<(), H::Error> {
+        if let Some(tee) = self.tee.as_mut() {
+            tee(data.as_bytes());
+        }
+        self.feed(data)
+    }
+
+    /// Runs the state machine over a chunk without passing it to the tee.
+    fn feed(&mut self, data: &str) -> Result<(), H::Error> {
         for c in data.chars() {
-            self.column += 1;
-            if c == '\n' {
-                self.line += 1;
-                self.column = 0;
+            let char_offset = self.offset;
+            self.offset += c.len_utf8();
+            if c == '\u{feff}' && char_offset == 0 {
+                continue;
+            }
+            if self.config.track_positions {
+                if c == '\t' {
+                    let width = self.config.tab_width.max(1);
+                    self.column = (self.column / width + 1) * width;
+                } else {
+                    self.column += 1;
+                }
+                if c == '\n' {
+                    self.line += 1;
+                    self.column = 0;
+                }
+            }
+            if c == '\u{feff}' && self.config.reject_inner_bom {
+                return Err(IksError::MisplacedBom { position: self.position(char_offset) }.into());
             }
 
             match self.state {
@@ -271,19 +883,25 @@ impl<H: SaxHandler> Parser<H> {
                     match c {
                         '<' => {
                             if !self.buffer.is_empty() {
+                                self.note_leading_text();
+                                self.check_trailing_text()?;
+                                self.count_text();
                                 self.handler.on_cdata(&self.buffer)?;
                                 self.buffer.clear();
                             }
+                            self.tag_start = self.position(char_offset);
+                            self.token_start = self.tag_start;
+                            self.markups += 1;
                             self.state = State::TagStart;
                         }
                         '&' => {
-                            if !self.buffer.is_empty() {
-                                self.handler.on_cdata(&self.buffer)?;
-                                self.buffer.clear();
-                            }
+                            self.token_start = self.position(char_offset);
                             self.state = State::Entity;
                         }
-                        _ => self.buffer.push(c)
+                        _ => {
+                            self.check_char(c)?;
+                            self.buffer.push(c);
+                        }
                     }
                 }
                 State::TagStart => {
@@ -317,67 +935,81 @@ impl<H: SaxHandler> Parser<H> {
                             self.state = State::CData;
                         }
                         _ => {
+                            self.declaration.feed(c);
                             self.state = State::MarkupEnd;
                         }
                     }
                 }
+                State::MarkupEnd => {
+                    if self.declaration.feed(c) {
+                        let declaration = std::mem::take(&mut self.declaration);
+                        self.handle_declaration(&declaration.text)?;
+                        self.state = State::CData;
+                    }
+                }
                 State::Comment => {
                     if c != '-' {
-                        return Err(IksError::BadXml);
+                        return Err(IksError::BadXml.into());
                     }
                     self.state = State::Comment1;
                 }
                 State::Comment1 => {
                     if c == '-' {
                         self.state = State::Comment2;
+                    } else {
+                        self.markup.push(c);
                     }
                 }
                 State::Comment2 => {
                     if c == '-' {
                         self.state = State::Comment3;
                     } else {
+                        self.markup.push('-');
+                        self.markup.push(c);
                         self.state = State::Comment1;
                     }
                 }
                 State::Comment3 => {
                     if c != '>' {
-                        return Err(IksError::BadXml);
+                        return Err(IksError::BadXml.into());
                     }
+                    self.handler.on_comment(&self.markup)?;
+                    self.markup.clear();
                     self.state = State::CData;
                 }
                 State::Sect => {
                     if c != 'C' {
-                        return Err(IksError::BadXml);
+                        return Err(IksError::BadXml.into());
                     }
                     self.state = State::SectCData;
                 }
                 State::SectCData => {
                     if c != 'D' {
-                        return Err(IksError::BadXml);
+                        return Err(IksError::BadXml.into());
                     }
                     self.state = State::SectCData1;
                 }
                 State::SectCData1 => {
                     if c != 'A' {
-                        return Err(IksError::BadXml);
+                        return Err(IksError::BadXml.into());
                     }
                     self.state = State::SectCData2;
                 }
                 State::SectCData2 => {
                     if c != 'T' {
-                        return Err(IksError::BadXml);
+                        return Err(IksError::BadXml.into());
                     }
                     self.state = State::SectCData3;
                 }
                 State::SectCData3 => {
                     if c != 'A' {
-                        return Err(IksError::BadXml);
+                        return Err(IksError::BadXml.into());
                     }
                     self.state = State::SectCData4;
                 }
                 State::SectCData4 => {
                     if c != '[' {
-                        return Err(IksError::BadXml);
+                        return Err(IksError::BadXml.into());
                     }
                     self.state = State::SectCDataC;
                 }
@@ -385,6 +1017,7 @@ impl<H: SaxHandler> Parser<H> {
                     if c == ']' {
                         self.state = State::SectCDataE;
                     } else {
+                        self.check_char(c)?;
                         self.buffer.push(c);
                     }
                 }
@@ -399,6 +1032,11 @@ impl<H: SaxHandler> Parser<H> {
                 }
                 State::SectCDataE2 => {
                     if c == '>' {
+                        self.note_leading_text();
+                        self.check_trailing_text()?;
+                        self.count_text();
+                        self.handler.on_cdata_section(&self.buffer)?;
+                        self.buffer.clear();
                         self.state = State::CData;
                     } else if c == ']' {
                         self.buffer.push(']');
@@ -410,21 +1048,40 @@ impl<H: SaxHandler> Parser<H> {
                     }
                 }
                 State::Pi => {
-                    if c == '>' {
-                        self.state = State::CData;
+                    if c == '?' {
+                        self.state = State::PiEnd;
+                    } else {
+                        self.markup.push(c);
+                    }
+                }
+                State::PiEnd => {
+                    match c {
+                        '>' => {
+                            self.handle_pi()?;
+                            self.state = State::CData;
+                        }
+                        '?' => self.markup.push('?'),
+                        _ => {
+                            self.markup.push('?');
+                            self.markup.push(c);
+                            self.state = State::Pi;
+                        }
                     }
                 }
                 State::Tag => {
                     match c {
                         '>' => {
+                            self.start_tag()?;
                             self.handle_tag_end()?;
                         }
                         '/' => {
+                            self.start_tag()?;
                             self.tag_type = TagType::Single;
                             self.state = State::TagEnd;
                         }
                         ' ' | '\t' | '\n' | '\r' => {
                             if !self.tag_name.is_empty() {
+                                self.start_tag()?;
                                 self.state = State::Attribute;
                             }
                         }
@@ -442,6 +1099,8 @@ impl<H: SaxHandler> Parser<H> {
                         }
                         ' ' | '\t' | '\n' | '\r' => {}
                         _ => {
+                            self.attr_start = self.position(char_offset);
+                            self.token_start = self.attr_start;
                             self.attr_name.push(c);
                             self.state = State::AttributeName;
                         }
@@ -454,62 +1113,81 @@ impl<H: SaxHandler> Parser<H> {
                         }
                         ' ' | '\t' | '\n' | '\r' => {
                             if !self.attr_name.is_empty() {
-                                self.state = State::AttributeValue;
+                                self.state = State::AttributeEq;
                             }
                         }
+                        '>' => {
+                            self.push_valueless_attribute()?;
+                            self.handle_tag_end()?;
+                        }
+                        '/' => {
+                            self.push_valueless_attribute()?;
+                            self.tag_type = TagType::Single;
+                            self.state = State::TagEnd;
+                        }
                         _ => self.attr_name.push(c)
                     }
                 }
-                State::AttributeValue => {
+                State::AttributeEq => {
                     match c {
-                        '\'' => self.state = State::ValueApos,
-                        '"' => self.state = State::ValueQuot,
+                        '=' => self.state = State::AttributeValue,
                         ' ' | '\t' | '\n' | '\r' => {}
-                        _ => return Err(IksError::BadXml)
-                    }
-                }
-                State::ValueApos => {
+                        '>' => {
+                            self.push_valueless_attribute()?;
+                            self.handle_tag_end()?;
+                        }
+                        '/' => {
+                            self.push_valueless_attribute()?;
+                            self.tag_type = TagType::Single;
+                            self.state = State::TagEnd;
+                        }
+                        _ => {
+                            self.push_valueless_attribute()?;
+                            self.attr_start = self.position(char_offset);
+                            self.token_start = self.attr_start;
+                            self.attr_name.push(c);
+                            self.state = State::AttributeName;
+                        }
+                    }
+                }
+                State::AttributeValue => {
+                    match c {
+                        '\'' => self.state = State::ValueApos,
+                        '"' => self.state = State::ValueQuot,
+                        ' ' | '\t' | '\n' | '\r' => {}
+                        _ => return Err(self.attribute_error().into())
+                    }
+                }
+                State::ValueApos => {
                     match c {
                         '\'' => {
-                            self.attributes.push((
-                                std::mem::take(&mut self.attr_name),
-                                std::mem::take(&mut self.attr_value)
-                            ));
+                            self.push_attribute()?;
                             self.state = State::Attribute;
                         }
-                        _ => self.attr_value.push(c)
+                        '<' => return Err(self.attribute_error().into()),
+                        _ => self.push_value_char(c)?,
                     }
                 }
                 State::ValueQuot => {
                     match c {
                         '"' => {
-                            self.attributes.push((
-                                std::mem::take(&mut self.attr_name),
-                                std::mem::take(&mut self.attr_value)
-                            ));
+                            self.push_attribute()?;
                             self.state = State::Attribute;
                         }
-                        _ => self.attr_value.push(c)
+                        '<' => return Err(self.attribute_error().into()),
+                        _ => self.push_value_char(c)?,
                     }
                 }
                 State::Entity => {
                     match c {
                         ';' => {
-                            let entity = match self.entity.as_str() {
-                                "amp" => "&",
-                                "lt" => "<",
-                                "gt" => ">",
-                                "apos" => "'",
-                                "quot" => "\"",
-                                _ => return Err(IksError::BadXml)
-                            };
-                            self.buffer.push_str(entity);
+                            expand_reference(&self.config, self.xml11, &self.entity, &mut self.buffer)?;
                             self.entity.clear();
                             self.state = State::CData;
                         }
                         _ => {
-                            if self.entity.len() >= 8 {
-                                return Err(IksError::BadXml);
+                            if self.entity.len() >= self.max_entity_length() {
+                                return Err(IksError::BadXml.into());
                             }
                             self.entity.push(c);
                         }
@@ -522,45 +1200,7 @@ impl<H: SaxHandler> Parser<H> {
                             self.tag_name.clear();
                             self.attributes.clear();
                         }
-                        _ => return Err(IksError::BadXml)
-                    }
-                }
-                State::Utf8Sequence => {
-                    if self.utf8_bytes_left > 0 {
-                        if (c as u8 & 0xC0) != 0x80 {
-                            return Err(IksError::BadXml);
-                        }
-                        self.utf8_sequence = (self.utf8_sequence << 6) | (c as u32 & 0x3F);
-                        self.utf8_bytes_left -= 1;
-                        if self.utf8_bytes_left == 0 {
-                            // Validate UTF-8 sequence
-                            if self.utf8_sequence < 0x80 || 
-                               (self.utf8_sequence >= 0x800 && self.utf8_sequence < 0x10000) ||
-                               (self.utf8_sequence >= 0x10000 && self.utf8_sequence < 0x110000) {
-                                self.buffer.push(char::from_u32(self.utf8_sequence).unwrap());
-                            } else {
-                                return Err(IksError::BadXml);
-                            }
-                            self.state = State::CData;
-                        }
-                    }
-                }
-                _ => {
-                    if (c as u8 & 0x80) != 0 {
-                        // Start of UTF-8 sequence
-                        let bytes = match c as u8 & 0xE0 {
-                            0xC0 => 2,
-                            0xE0 => 3,
-                            0xF0 => 4,
-                            0xF8 => 5,
-                            0xFC => 6,
-                            _ => return Err(IksError::BadXml),
-                        };
-                        self.utf8_sequence = c as u32 & (0x7F >> (bytes - 1));
-                        self.utf8_bytes_left = bytes - 1;
-                        self.state = State::Utf8Sequence;
-                    } else {
-                        self.buffer.push(c);
+                        _ => return Err(IksError::BadXml.into())
                     }
                 }
             }
@@ -568,6 +1208,9 @@ impl<H: SaxHandler> Parser<H> {
 
         // Handle any remaining character data
         if !self.buffer.is_empty() && self.state == State::CData {
+            self.note_leading_text();
+            self.check_trailing_text()?;
+            self.count_text();
             self.handler.on_cdata(&self.buffer)?;
             self.buffer.clear();
         }
@@ -575,73 +1218,542 @@ impl<H: SaxHandler> Parser<H> {
         Ok(())
     }
 
-    /// Handles the end of a tag.
+    /// Parses a chunk of XML data given as UTF-8 bytes.
     /// 
-    /// This method is called when a tag is fully parsed and calls the
-    /// appropriate handler method.
+    /// Chunks may split a multi-byte character; its leading bytes are kept
+    /// until the next call supplies the rest. Invalid sequences are handled
+    /// according to `ParserConfig::on_invalid_utf8`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - The UTF-8 encoded XML data to parse
     /// 
     /// # Returns
     /// 
     /// A `Result` indicating success or failure
-    fn handle_tag_end(&mut self) -> Result<()> {
-        let result = self.handler.on_tag(
-            &self.tag_name,
-            &self.attributes,
-            self.tag_type
-        );
-        
-        // Only clear tag_name and attributes if it's not a single tag
-        // This allows single tags to be properly handled as children
-        if self.tag_type != TagType::Single {
-            self.tag_name.clear();
-            self.attributes.clear();
+    pub fn parse_bytes(&mut self, data: &[u8]) -> Result<(), H::Error> {
+        if let Some(tee) = self.tee.as_mut() {
+            tee(data);
+        }
+        let mut data = self.complete_utf8(data)?;
+        loop {
+            match str::from_utf8(data) {
+                Ok(text) => return self.feed(text),
+                Err(e) => {
+                    let (valid, rest) = data.split_at(e.valid_up_to());
+                    self.feed(str::from_utf8(valid).expect("prefix is valid UTF-8"))?;
+                    match e.error_len() {
+                        Some(len) => {
+                            self.invalid_utf8()?;
+                            data = &rest[len..];
+                        }
+                        None => {
+                            self.utf8 = rest.to_vec();
+                            return Ok(());
+                        }
+                    }
+                }
+            }
         }
-        
-        self.state = State::CData;
-        
-        result
     }
 
-    /// Serializes the current XML state to a string.
+    /// Finishes a character left incomplete by the previous chunk.
     /// 
-    /// This method is useful for debugging or when you need to see the
-    /// current state of the parser as XML.
+    /// # Arguments
+    /// 
+    /// * `data` - The new chunk
     /// 
     /// # Returns
     /// 
-    /// A string representation of the current XML state
-    pub fn to_string(&self) -> String {
-        let mut result = String::new();
-        
-        // Handle CDATA
-        if !self.buffer.is_empty() {
-            result.push_str(&escape(&self.buffer));
+    /// A `Result` containing the part of the chunk following the character
+    fn complete_utf8<'a>(&mut self, data: &'a [u8]) -> Result<&'a [u8], H::Error> {
+        if self.utf8.is_empty() {
+            return Ok(data);
+        }
+        let pending = self.utf8.len();
+        let mut sequence = std::mem::take(&mut self.utf8);
+        sequence.extend(data.iter().take(4 - pending));
+        match str::from_utf8(&sequence) {
+            Ok(text) => {
+                self.feed(text)?;
+                Ok(&data[sequence.len() - pending..])
+            }
+            Err(e) if e.valid_up_to() > 0 => {
+                self.feed(str::from_utf8(&sequence[..e.valid_up_to()]).expect("prefix is valid UTF-8"))?;
+                Ok(&data[e.valid_up_to() - pending..])
+            }
+            Err(e) => match e.error_len() {
+                Some(len) => {
+                    self.invalid_utf8()?;
+                    Ok(&data[len.saturating_sub(pending)..])
+                }
+                None => {
+                    self.utf8 = sequence;
+                    Ok(&[])
+                }
+            },
         }
+    }
 
-        // Handle tag
-        if !self.tag_name.is_empty() {
-            result.push('<');
-            if self.tag_type == TagType::Close {
-                result.push('/');
+    /// Handles an invalid UTF-8 sequence according to the configuration.
+    fn invalid_utf8(&mut self) -> Result<(), H::Error> {
+        match self.config.on_invalid_utf8 {
+            InvalidUtf8::Error => Err(IksError::BadUtf8.into()),
+            InvalidUtf8::Replace => self.feed("\u{FFFD}"),
+        }
+    }
+
+    /// Signals the end of the input.
+    /// 
+    /// `parse` accepts any prefix of a document, since more data may follow
+    /// in the next chunk. Once all data has been fed, call this to check
+    /// that the input did not stop inside markup or with elements open.
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` that fails with `IksError::UnexpectedEof` on truncated
+    /// input, or with `IksError::UnterminatedCData` if it ended inside a
+    /// CDATA section
+    pub fn finish(&mut self) -> Result<(), H::Error> {
+        if !self.utf8.is_empty() {
+            self.utf8.clear();
+            self.invalid_utf8()?;
+        }
+        if matches!(
+            self.state,
+            State::SectCData | State::SectCData1 | State::SectCData2 | State::SectCData3 | State::SectCData4
+                | State::SectCDataC | State::SectCDataE | State::SectCDataE2
+        ) {
+            return Err(IksError::UnterminatedCData { position: self.tag_start }.into());
+        }
+        if self.state != State::CData || self.depth > 0 {
+            return Err(IksError::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+
+    /// Checks whether the input seen so far is exactly one complete document.
+    /// 
+    /// This is true once a single root element has been opened and closed
+    /// and the parser is back between tags. It is meant for polling a
+    /// streaming parser, e.g. to know when a whole response has arrived;
+    /// unlike `finish` it does not consume the parser's verdict as an error.
+    /// 
+    /// # Returns
+    /// 
+    /// `true` if one root element was completed and nothing is left open
+    pub fn is_document_complete(&self) -> bool {
+        self.state == State::CData && self.depth == 0 && self.roots == 1
+    }
+
+    /// Parses a complete document held in memory.
+    /// 
+    /// This is `parse` followed by `finish`, so unlike `parse` it fails
+    /// when the data is truncated.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `data` - The whole XML document
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    pub fn parse_complete(&mut self, data: &str) -> Result<(), H::Error> {
+        self.parse(data)?;
+        self.finish()
+    }
+
+    /// Parses a complete document given as a sequence of chunks.
+    /// 
+    /// Each chunk is passed to `parse` and `finish` is called after the
+    /// last one. Chunks may split the document anywhere, e.g. inside a
+    /// tag or an entity reference.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `chunks` - The pieces of the document, in order
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` that fails with the first error of `parse` or `finish`
+    pub fn parse_chunks<I: IntoIterator<Item = S>, S: AsRef<str>>(&mut self, chunks: I) -> Result<(), H::Error> {
+        for chunk in chunks {
+            self.parse(chunk.as_ref())?;
+        }
+        self.finish()
+    }
+
+    /// Parses a complete document read from a byte stream.
+    /// 
+    /// The input is read in blocks of `memory::FILE_BUFFER_SIZE` bytes and
+    /// fed to `parse_bytes`, so the whole document is never held in memory.
+    /// Once the reader is exhausted, `finish` is called.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `reader` - The source of the UTF-8 encoded document
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` that fails with `IksError::Io` if reading fails, or with
+    /// any error of `parse_bytes` and `finish`
+    pub fn parse_reader<R: io::Read>(&mut self, mut reader: R) -> Result<(), H::Error> {
+        let mut block = vec![0; memory::FILE_BUFFER_SIZE];
+        loop {
+            match reader.read(&mut block) {
+                Ok(0) => return self.finish(),
+                Ok(len) => self.parse_bytes(&block[..len])?,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(IksError::from(e).into()),
             }
-            result.push_str(&escape(&self.tag_name));
+        }
+    }
 
-            // Handle attributes
-            for (name, value) in &self.attributes {
-                result.push(' ');
-                result.push_str(&escape(name));
-                result.push('=');
-                result.push('"');
-                result.push_str(&escape(value));
-                result.push('"');
+    /// Gets the longest entity name the parser accepts.
+    fn max_entity_length(&self) -> usize {
+        self.config.entities.keys()
+            .map(String::len)
+            .fold(xml::MAX_ENTITY_LENGTH, usize::max)
+    }
+
+    /// Handles a complete `<! ... >` markup declaration.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `text` - The declaration between `<!` and `>`
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn handle_declaration(&mut self, text: &str) -> Result<(), H::Error> {
+        if let Some(body) = text.strip_prefix("DOCTYPE") {
+            if self.depth > 0 || !body.starts_with(char::is_whitespace) {
+                return Err(IksError::BadXml.into());
+            }
+            let doctype = Doctype::parse(body)?;
+            if self.config.apply_dtd_defaults {
+                for mut default in doctype.attribute_defaults() {
+                    if self.config.lowercase_names {
+                        lowercase(&mut default.element);
+                        lowercase(&mut default.attribute);
+                    }
+                    let attributes = self.attribute_defaults.entry(default.element).or_default();
+                    if !attributes.iter().any(|(name, _)| *name == default.attribute) {
+                        attributes.push((default.attribute, default.value));
+                    }
+                }
             }
+            self.handler.on_doctype(&doctype)
+        } else {
+            self.handler.on_markup_decl(text)
+        }
+    }
 
-            if self.tag_type == TagType::Single {
-                result.push('/');
+    /// Handles a complete `<? ... ?>` processing instruction.
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn handle_pi(&mut self) -> Result<(), H::Error> {
+        let markup = std::mem::take(&mut self.markup);
+        let (target, data) = markup.split_once(char::is_whitespace).unwrap_or((&markup, ""));
+        if target.is_empty() {
+            return Err(IksError::BadXml.into());
+        }
+        if target == "xml" {
+            let leading_blank = self.lead == Lead::Blank && self.config.lenient;
+            if self.markups != 1 || !(self.lead == Lead::Empty || leading_blank) {
+                return Err(IksError::MisplacedDeclaration { position: self.tag_start }.into());
             }
-            result.push('>');
+            self.xml11 = pseudo_attribute(data, "version") == Some("1.1");
+        }
+        self.handler.on_pi(target, data.trim_start())
+    }
+
+    /// Checks that a character may appear literally in the document.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `c` - The character read from the input
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` that fails with `IksError::InvalidChar` for characters the
+    /// declared XML version does not allow
+    fn check_char(&self, c: char) -> Result<()> {
+        let allowed = match c {
+            '\t' | '\n' | '\r' => true,
+            '\u{0}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}' => false,
+            // XML 1.1 requires the C1 controls, except NEL, to be escaped
+            '\u{7F}'..='\u{84}' | '\u{86}'..='\u{9F}' => !self.xml11,
+            _ => true,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(IksError::InvalidChar(c as u32))
+        }
+    }
+
+    /// Appends a character to the attribute value being read.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `c` - The character to append
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success, or `IksError::AttributeTooLong` if the
+    /// value would exceed the configured limit
+    fn push_value_char(&mut self, c: char) -> Result<()> {
+        self.check_char(c)?;
+        if let Some(limit) = self.config.max_attr_value_length {
+            if self.attr_value.len() + c.len_utf8() > limit {
+                return Err(IksError::AttributeTooLong {
+                    name: self.attr_name.clone(),
+                    position: self.attr_start,
+                    limit,
+                });
+            }
+        }
+        self.attr_value.push(c);
+        Ok(())
+    }
+
+    /// Builds the error for a malformed attribute.
+    /// 
+    /// # Returns
+    /// 
+    /// An `IksError::BadAttribute` naming the attribute being read
+    fn attribute_error(&self) -> IksError {
+        IksError::BadAttribute {
+            name: self.attr_name.clone(),
+            position: self.attr_start,
+        }
+    }
+
+    /// Gets the position of a character that was just read.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `offset` - The byte offset of the character
+    fn position(&self, offset: usize) -> Position {
+        Position {
+            offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Stores an attribute written without a value, like `<input disabled>`.
+    /// 
+    /// Such attributes are only accepted in lenient mode, where they get an
+    /// empty value.
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` that fails in strict mode
+    fn push_valueless_attribute(&mut self) -> Result<(), H::Error> {
+        if !self.config.lenient {
+            return Err(self.attribute_error().into());
+        }
+        let name = self.take_attr_name();
+        self.handler.on_valueless_attribute(&name);
+        self.store_attribute(name, String::new())
+    }
+
+    /// Stores the attribute that was just read, decoding its entity references.
+    /// 
+    /// A quote of either kind may appear in the decoded value, whatever the
+    /// delimiter was; the serializer escapes it again on output.
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating whether the value was well-formed
+    fn push_attribute(&mut self) -> Result<(), H::Error> {
+        let raw = std::mem::take(&mut self.attr_value);
+        let mut value = String::with_capacity(raw.len());
+        let mut rest = raw.as_str();
+        while let Some(start) = rest.find('&') {
+            value.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+            let Some(end) = rest.find(';').filter(|&end| !rest[..end].contains('&')) else {
+                if !self.config.lenient {
+                    return Err(self.attribute_error().into());
+                }
+                // An `&` that starts no reference is kept as written
+                value.push('&');
+                continue;
+            };
+            expand_reference(&self.config, self.xml11, &rest[..end], &mut value).map_err(|e| match e {
+                IksError::BadXml => self.attribute_error(),
+                e => e,
+            })?;
+            rest = &rest[end + 1..];
+        }
+        value.push_str(rest);
+        let name = self.take_attr_name();
+        if raw.contains('&') {
+            self.handler.on_attribute_source(&name, &raw);
+        }
+        self.store_attribute(name, value)
+    }
+
+    /// Takes the name of the attribute just read, lowercased if configured.
+    fn take_attr_name(&mut self) -> String {
+        let mut name = std::mem::take(&mut self.attr_name);
+        if self.config.lowercase_names {
+            lowercase(&mut name);
+        }
+        name
+    }
+
+    /// Passes an attribute to a streaming handler or keeps it for `on_tag`.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - The name of the attribute
+    /// * `value` - The decoded value
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn store_attribute(&mut self, name: String, value: String) -> Result<(), H::Error> {
+        match self.handler.as_streaming() {
+            Some(handler) => {
+                handler.on_attribute(&name, &value)?;
+                if self.attribute_defaults.contains_key(&self.tag_name) {
+                    self.streamed_attributes.push(name);
+                }
+                Ok(())
+            }
+            None => {
+                self.attributes.push((name, value));
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds the DTD default values of the attributes the current tag omits.
+    /// 
+    /// The defaults go through `store_attribute` like written attributes,
+    /// so streaming handlers get them before the tag ends.
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn add_attribute_defaults(&mut self) -> Result<(), H::Error> {
+        let Some(defaults) = self.attribute_defaults.get(&self.tag_name) else {
+            return Ok(());
+        };
+        let missing: Vec<_> = defaults.iter()
+            .filter(|(name, _)| {
+                !self.attributes.iter().any(|(n, _)| n == name) && !self.streamed_attributes.contains(name)
+            })
+            .cloned()
+            .collect();
+        for (name, value) in missing {
+            self.store_attribute(name, value)?;
+        }
+        self.streamed_attributes.clear();
+        Ok(())
+    }
+
+    /// Handles the end of the name of a start tag.
+    /// 
+    /// The element limits are checked here, before any attribute is
+    /// reported to a streaming handler. End tags are ignored.
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating whether the element is acceptable
+    fn start_tag(&mut self) -> Result<(), H::Error> {
+        if self.config.lowercase_names {
+            lowercase(&mut self.tag_name);
+        }
+        if self.tag_type == TagType::Close {
+            return Ok(());
+        }
+        if self.config.max_depth.is_some_and(|max| self.depth >= max) {
+            return Err(IksError::TooDeep.into());
+        }
+        if self.config.max_elements.is_some_and(|max| self.elements >= max) {
+            return Err(IksError::TooManyElements.into());
+        }
+        self.elements += 1;
+        if self.depth == 0 {
+            if self.roots > 0 && !self.config.lenient {
+                return Err(IksError::ContentAfterRoot.into());
+            }
+            self.roots += 1;
+        }
+        if self.config.allowed_elements.as_ref().is_some_and(|allowed| !allowed.contains(&self.tag_name)) {
+            return Err(IksError::BadXml.into());
+        }
+        match self.handler.as_streaming() {
+            Some(handler) => handler.on_start_tag(&self.tag_name).map_err(Into::into),
+            None => Ok(()),
+        }
+    }
+
+    /// Handles the end of a tag.
+    /// 
+    /// This method is called when a tag is fully parsed and calls the
+    /// appropriate handler method.
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` indicating success or failure
+    fn handle_tag_end(&mut self) -> Result<(), H::Error> {
+        match self.tag_type {
+            TagType::Open => {
+                self.depth += 1;
+                self.stats.open_tags += 1;
+                self.stats.max_depth = self.stats.max_depth.max(self.depth);
+            }
+            TagType::Single => {
+                self.stats.single_tags += 1;
+                self.stats.max_depth = self.stats.max_depth.max(self.depth + 1);
+            }
+            TagType::Close => {
+                self.depth = self.depth.saturating_sub(1);
+                self.stats.close_tags += 1;
+            }
+        }
+
+        if self.tag_type != TagType::Close {
+            self.add_attribute_defaults()?;
         }
 
+        self.handler.on_tag_span(Span {
+            start: self.tag_start,
+            end: Position {
+                offset: self.offset,
+                line: self.line,
+                column: self.column,
+            },
+        });
+        let result = match &mut self.symbols {
+            Some(symbols) => {
+                let symbol = symbols.intern(&self.tag_name);
+                self.handler.on_tag_symbol(symbol, &self.tag_name, &self.attributes, self.tag_type)
+            }
+            None => self.handler.on_tag(
+                &self.tag_name,
+                &self.attributes,
+                self.tag_type
+            ),
+        };
+        
+        // Only clear tag_name and attributes if it's not a single tag
+        // This allows single tags to be properly handled as children
+        if self.tag_type != TagType::Single {
+            self.tag_name.clear();
+            self.attributes.clear();
+        }
+        
+        self.state = State::CData;
+        
         result
     }
 
@@ -691,7 +1803,8 @@ impl<H: SaxHandler> Parser<H> {
     /// 
     /// # Returns
     /// 
-    /// The current line number (1-based)
+    /// The current line number (1-based), or 0 if
+    /// `ParserConfig::track_positions` is off
     pub fn line(&self) -> usize {
         self.line
     }
@@ -700,18 +1813,193 @@ impl<H: SaxHandler> Parser<H> {
     /// 
     /// # Returns
     /// 
-    /// The current column number (0-based)
+    /// The current column number (0-based), always 0 if
+    /// `ParserConfig::track_positions` is off
     pub fn column(&self) -> usize {
         self.column
     }
+
+    /// Gets the position where the current token started.
+    /// 
+    /// A token is a tag, including comments and other markup, an attribute
+    /// or an entity reference in text. Unlike `line` and `column`, which
+    /// follow the last consumed character, this stays on the first
+    /// character of the token, so errors can point where e.g. a tag began.
+    /// 
+    /// # Returns
+    /// 
+    /// The position of the first character of the current or last token
+    pub fn token_start(&self) -> Position {
+        self.token_start
+    }
+
+    /// Gets the line on which the current token started.
+    /// 
+    /// # Returns
+    /// 
+    /// The line number of the token start (1-based)
+    pub fn token_line(&self) -> usize {
+        self.token_start.line
+    }
+
+    /// Gets the column at which the current token started.
+    /// 
+    /// # Returns
+    /// 
+    /// The column number of the token start, counted like `column`
+    pub fn token_column(&self) -> usize {
+        self.token_start.column
+    }
+
+    /// Gets counts of the tags and text seen so far.
+    /// 
+    /// The counters are kept for every parse and cost a few additions per
+    /// event. Like the handler, they are not rolled back by `restore`.
+    /// 
+    /// # Returns
+    /// 
+    /// The statistics of the input parsed up to now
+    pub fn stats(&self) -> ParseStats {
+        ParseStats { bytes: self.offset, ..self.stats }
+    }
+
+    /// Remembers what the text held in the buffer before the first markup is.
+    /// 
+    /// This decides whether a later XML declaration is still at the start of
+    /// the input, see `handle_pi`.
+    fn note_leading_text(&mut self) {
+        if self.markups == 0 && self.lead != Lead::Text {
+            self.lead = if is_xml_blank(&self.buffer) { Lead::Blank } else { Lead::Text };
+        }
+    }
+
+    /// Checks the text held in the buffer against the misc section rules.
+    /// 
+    /// After the root element only whitespace may follow, unless parsing
+    /// is lenient.
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` that fails with `IksError::ContentAfterRoot` for other text
+    fn check_trailing_text(&self) -> Result<()> {
+        let trailing = self.depth == 0 && self.roots > 0 && !self.config.lenient;
+        if trailing && !is_xml_blank(&self.buffer) {
+            return Err(IksError::ContentAfterRoot);
+        }
+        Ok(())
+    }
+
+    /// Counts the text held in the buffer as one text event.
+    fn count_text(&mut self) {
+        self.stats.text_events += 1;
+        self.stats.text_bytes += self.buffer.len();
+    }
+
+    /// Gets the number of bytes consumed so far.
+    /// 
+    /// # Returns
+    /// 
+    /// The byte offset of the next character in the input
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<H: SaxHandler> fmt::Display for Parser<H> {
+    /// Serializes the current XML state.
+    /// 
+    /// This is useful for debugging or when you need to see the
+    /// current state of the parser as XML.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut result = String::new();
+        
+        // Handle CDATA
+        if !self.buffer.is_empty() {
+            result.push_str(&escape(&self.buffer));
+        }
+
+        // Handle tag
+        if !self.tag_name.is_empty() {
+            result.push('<');
+            if self.tag_type == TagType::Close {
+                result.push('/');
+            }
+            result.push_str(&escape(&self.tag_name));
+
+            // Handle attributes
+            for (name, value) in &self.attributes {
+                result.push(' ');
+                result.push_str(&escape(name));
+                result.push('=');
+                result.push('"');
+                result.push_str(&escape(value));
+                result.push('"');
+            }
+
+            if self.tag_type == TagType::Single {
+                result.push('/');
+            }
+            result.push('>');
+        }
+
+        f.write_str(&result)
+    }
+}
+
+/// Handler collecting the character data inside the root element.
+#[derive(Default)]
+struct TextCollector {
+    text: String,
+    depth: usize,
+}
+
+impl SaxHandler for TextCollector {
+    type Error = IksError;
+
+    fn on_tag(&mut self, _name: &str, _attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+        match tag_type {
+            TagType::Open => self.depth += 1,
+            TagType::Close => self.depth = self.depth.saturating_sub(1),
+            TagType::Single => {}
+        }
+        Ok(())
+    }
+
+    fn on_cdata(&mut self, data: &str) -> Result<()> {
+        if self.depth > 0 {
+            self.text.push_str(data);
+        }
+        Ok(())
+    }
+}
+
+/// Extracts all text of a document without building a tree.
+/// 
+/// The result is what `IksNode::text_content` gives for the root element,
+/// including the content of CDATA sections, but only the text itself is
+/// kept in memory while parsing.
+/// 
+/// # Arguments
+/// 
+/// * `xml` - The complete XML document
+/// 
+/// # Returns
+/// 
+/// A `Result` containing the concatenated text in document order
+pub fn extract_text(xml: &str) -> Result<String> {
+    let mut parser = Parser::new(TextCollector::default());
+    parser.parse_complete(xml)?;
+    Ok(parser.handler.text)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    type TagEvent = (String, Vec<(String, String)>, TagType);
+
     struct TestHandler {
-        tags: Vec<(String, Vec<(String, String)>, TagType)>,
+        tags: Vec<TagEvent>,
         cdata: Vec<String>,
     }
     
@@ -725,6 +2013,8 @@ mod tests {
     }
     
     impl SaxHandler for TestHandler {
+        type Error = IksError;
+
         fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
             self.tags.push((
                 name.to_string(),
@@ -757,4 +2047,457 @@ mod tests {
         assert_eq!(parser.handler.tags[1].0, "root");
         assert_eq!(parser.handler.tags[1].2, TagType::Close);
     }
-} 
\ No newline at end of file
+
+    /// Parses `xml` split into the given chunks and returns the tag events
+    /// along with the text between tags, with adjacent text runs merged.
+    fn parse_chunks(chunks: &[&str]) -> (Vec<TagEvent>, Vec<String>) {
+        let mut parser = Parser::new(TestHandler::new());
+        for chunk in chunks {
+            parser.parse(chunk).unwrap();
+        }
+        let handler = parser.handler;
+        (handler.tags, handler.cdata)
+    }
+
+    struct MergingHandler(TestHandler);
+
+    impl SaxHandler for MergingHandler {
+        type Error = IksError;
+
+        fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+            self.0.cdata.push(String::new());
+            self.0.on_tag(name, attributes, tag_type)
+        }
+
+        fn on_cdata(&mut self, data: &str) -> Result<()> {
+            match self.0.cdata.last_mut() {
+                Some(last) => last.push_str(data),
+                None => self.0.cdata.push(data.to_string()),
+            }
+            Ok(())
+        }
+    }
+
+    fn parse_merged(chunks: &[&str]) -> (Vec<TagEvent>, Vec<String>) {
+        let mut parser = Parser::new(MergingHandler(TestHandler::new()));
+        for chunk in chunks {
+            parser.parse(chunk).unwrap();
+        }
+        let handler = parser.handler.0;
+        (handler.tags, handler.cdata)
+    }
+
+    #[test]
+    fn test_chunk_boundaries() {
+        let xml = "<?xml version=\"1.0\"?><root a=\"1\" bb='two words'>\
+                   text &amp; more<!-- a comment --><child  x = \"&lt;\" y='\u{e7}'/>\
+                   <![CDATA[raw ] ]] <data>]]>\u{20ac} end</root >";
+        let expected = parse_merged(&[xml]);
+        assert_eq!(expected.0.len(), 3);
+
+        for (i, _) in xml.char_indices().skip(1) {
+            let (head, tail) = xml.split_at(i);
+            assert_eq!(parse_merged(&[head, tail]), expected, "split at byte {}", i);
+        }
+
+        let chars: Vec<String> = xml.chars().map(String::from).collect();
+        let chars: Vec<&str> = chars.iter().map(String::as_str).collect();
+        assert_eq!(parse_merged(&chars), expected);
+    }
+
+    #[test]
+    fn test_chunked_attributes() {
+        let (tags, _) = parse_chunks(&["<ro", "ot na", "me=\"va", "lue\" other='", "x'", "/", ">"]);
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].0, "root");
+        assert_eq!(tags[0].1, vec![
+            ("name".to_string(), "value".to_string()),
+            ("other".to_string(), "x".to_string()),
+        ]);
+        assert_eq!(tags[0].2, TagType::Single);
+    }
+
+    #[test]
+    fn test_attribute_value_quotes() {
+        let mut parser = Parser::new(crate::DomParser::new().unwrap());
+        parser.parse("<a x='a\"b' y=\"it&apos;s &amp; &quot;q&quot;\"/>").unwrap();
+        let root = parser.handler().document().unwrap();
+        let root = root.borrow();
+        assert_eq!(root.find_attrib("x"), Some("a\"b"));
+        assert_eq!(root.find_attrib("y"), Some("it's & \"q\""));
+        assert_eq!(root.to_string(), "<a x=\"a&quot;b\" y=\"it&apos;s &amp; &quot;q&quot;\"/>");
+
+        let mut parser = Parser::new(TestHandler::new());
+        assert!(matches!(parser.parse("<a x=\"1<2\"/>"), Err(IksError::BadAttribute { .. })));
+        let mut parser = Parser::new(TestHandler::new());
+        assert!(matches!(parser.parse("<a x=\"&bogus;\"/>"), Err(IksError::BadAttribute { .. })));
+    }
+
+    #[test]
+    fn test_parse_complete() {
+        let mut parser = Parser::new(TestHandler::new());
+        parser.parse_complete("<root><child/></root>").unwrap();
+
+        for truncated in ["<root><child/>", "<root><chi", "<root attr=\"v"] {
+            let mut parser = Parser::new(TestHandler::new());
+            assert!(parser.parse(truncated).is_ok());
+            let mut parser = Parser::new(TestHandler::new());
+            assert!(matches!(parser.parse_complete(truncated), Err(IksError::UnexpectedEof)));
+        }
+    }
+
+    #[test]
+    fn test_extract_text() {
+        let xml = "<?xml version=\"1.0\"?>\n<p>Rust is <b>fast</b>, <i>safe</i> &amp; <![CDATA[<fun>]]>!<br/></p>\n";
+        assert_eq!(extract_text(xml).unwrap(), "Rust is fast, safe & <fun>!");
+        assert_eq!(
+            extract_text(xml).unwrap(),
+            crate::DomParser::parse_str(xml).unwrap().borrow().text_content()
+        );
+        assert!(matches!(extract_text("<p>cut"), Err(IksError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn test_swap_handlers() {
+        let mut parser = Parser::new(TestHandler::new());
+        parser.parse("<stream><features/><starttls x=\"").unwrap();
+        let setup = parser.set_handler(TestHandler::new());
+        parser.parse("1\"/>text<message>hi</message>").unwrap();
+
+        let names = |handler: &TestHandler| handler.tags.iter().map(|t| t.0.as_str()).collect::<Vec<_>>().join(",");
+        assert_eq!(names(&setup), "stream,features");
+        assert_eq!(names(parser.handler()), "starttls,message,message");
+        assert_eq!(parser.handler().tags[0].1, [("x".to_string(), "1".to_string())]);
+        assert_eq!(parser.handler().cdata, ["text", "hi"]);
+
+        let mut parser = parser.map_handler(MergingHandler);
+        parser.parse("<a/>tail</stream>").unwrap();
+        assert!(parser.is_document_complete());
+        assert_eq!(parser.handler().0.tags.len(), 5);
+        assert_eq!(parser.handler().0.cdata, ["text", "hi", "tail", ""]);
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        let xml = "<name>Süleyman 🦀</name>".as_bytes();
+        for split in 0..xml.len() {
+            let mut parser = Parser::new(TestHandler::new());
+            parser.parse_bytes(&xml[..split]).unwrap();
+            parser.parse_bytes(&xml[split..]).unwrap();
+            parser.finish().unwrap();
+            assert_eq!(parser.handler().cdata.concat(), "Süleyman 🦀", "split at {}", split);
+        }
+
+        let invalid: &[u8] = b"<a>x\xff\xfey</a>";
+        let mut parser = Parser::new(TestHandler::new());
+        assert!(matches!(parser.parse_bytes(invalid), Err(IksError::BadUtf8)));
+        let mut parser = Parser::new(TestHandler::new());
+        parser.parse_bytes(b"<a>\xf0\x9f").unwrap();
+        assert!(matches!(parser.parse_bytes(b"x</a>"), Err(IksError::BadUtf8)));
+
+        let config = ParserConfig { on_invalid_utf8: InvalidUtf8::Replace, ..ParserConfig::default() };
+        let mut parser = Parser::with_config(TestHandler::new(), config.clone());
+        parser.parse_bytes(b"<r>").unwrap();
+        parser.parse_bytes(invalid).unwrap();
+        parser.parse_bytes(b"<b>\xf0\x9f").unwrap();
+        parser.parse_bytes(b"z</b>").unwrap();
+        assert_eq!(parser.handler().cdata.concat(), "x\u{FFFD}\u{FFFD}y\u{FFFD}z");
+
+        // The replacement is text after the root, which only lenient mode accepts
+        let mut parser = Parser::with_config(TestHandler::new(), ParserConfig { lenient: true, ..config });
+        parser.parse_bytes(b"<a/>\xe2\x82").unwrap();
+        parser.finish().unwrap();
+        assert_eq!(parser.handler().cdata.concat(), "\u{FFFD}");
+    }
+
+    #[test]
+    fn test_handler_error_type() {
+        #[derive(Debug)]
+        enum AppError {
+            Parse(IksError),
+            Forbidden(String),
+        }
+
+        impl From<IksError> for AppError {
+            fn from(e: IksError) -> Self {
+                AppError::Parse(e)
+            }
+        }
+
+        struct Guard;
+
+        impl SaxHandler for Guard {
+            type Error = AppError;
+
+            fn on_tag(&mut self, name: &str, _attributes: &[(String, String)], _tag_type: TagType) -> Result<(), AppError> {
+                if name == "script" {
+                    return Err(AppError::Forbidden(name.to_string()));
+                }
+                Ok(())
+            }
+
+            fn on_cdata(&mut self, _data: &str) -> Result<(), AppError> {
+                Ok(())
+            }
+        }
+
+        let mut parser = Parser::new(Guard);
+        match parser.parse("<p>text<script/></p>") {
+            Err(AppError::Forbidden(name)) => assert_eq!(name, "script"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let mut parser = Parser::new(Guard);
+        assert!(matches!(parser.parse_complete("<p>"), Err(AppError::Parse(IksError::UnexpectedEof))));
+        let mut parser = Parser::new(Guard);
+        parser.parse_complete("<p><br/></p>").unwrap();
+    }
+
+    #[test]
+    fn test_parse_chunks() {
+        let chunks = vec!["<do", "c a='1", "'>x &am", "p; y<b", "/></d", "oc>"];
+        let mut parser = Parser::new(TestHandler::new());
+        parser.parse_chunks(&chunks).unwrap();
+        let handler = parser.handler();
+        assert_eq!(handler.tags.len(), 3);
+        assert_eq!(handler.tags[0].1, [("a".to_string(), "1".to_string())]);
+        assert_eq!(handler.cdata.concat(), "x & y");
+
+        let mut parser = Parser::new(TestHandler::new());
+        let lines = "<doc>\n<a>\n".lines().map(|line| format!("{}\n", line));
+        assert!(matches!(parser.parse_chunks(lines), Err(IksError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn test_stats() {
+        let xml = "<doc><a>x &amp; y</a><b/><c><d><e/></d></c><![CDATA[raw]]></doc>";
+        let mut parser = Parser::new(TestHandler::new());
+        for chunk in xml.as_bytes().chunks(7) {
+            parser.parse(std::str::from_utf8(chunk).unwrap()).unwrap();
+        }
+        parser.finish().unwrap();
+
+        let stats = parser.stats();
+        assert_eq!((stats.open_tags, stats.close_tags, stats.single_tags), (4, 4, 2));
+        assert_eq!(stats.max_depth, 4);
+        assert_eq!(stats.text_events, parser.handler().cdata.len());
+        assert_eq!(stats.text_bytes, "x & yraw".len());
+        assert_eq!(stats.bytes, xml.len());
+        assert_eq!(Parser::new(TestHandler::new()).stats(), ParseStats::default());
+    }
+
+    #[test]
+    fn test_tee() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut parser = Parser::new(TestHandler::new());
+        let log = seen.clone();
+        parser.set_tee(move |data| log.borrow_mut().extend_from_slice(data));
+
+        let xml = "<message to='a@b'><body>Süleyman 🦀</body></message>".as_bytes();
+        parser.parse("<stream>").unwrap();
+        for chunk in xml.chunks(5) {
+            parser.parse_bytes(chunk).unwrap();
+        }
+        parser.parse_complete("</stream>").unwrap();
+
+        let mut expected = b"<stream>".to_vec();
+        expected.extend_from_slice(xml);
+        expected.extend_from_slice(b"</stream>");
+        assert_eq!(*seen.borrow(), expected);
+        assert_eq!(parser.handler().cdata.concat(), "Süleyman 🦀");
+    }
+
+    #[test]
+    fn test_document_complete() {
+        let mut parser = Parser::new(TestHandler::new());
+        assert!(!parser.is_document_complete());
+        for chunk in ["<?xml version=\"1.0\"?>", "<iq type=\"result\">", "<query/>", "</i", "q>"] {
+            assert!(!parser.is_document_complete());
+            parser.parse(chunk).unwrap();
+        }
+        assert!(parser.is_document_complete());
+        parser.parse("\n").unwrap();
+        assert!(parser.is_document_complete());
+
+        let lenient = ParserConfig { lenient: true, ..ParserConfig::default() };
+        let mut parser = Parser::with_config(TestHandler::new(), lenient.clone());
+        parser.parse("<ping/>").unwrap();
+        assert!(parser.is_document_complete());
+        parser.parse("<ping/>").unwrap();
+        assert!(!parser.is_document_complete());
+
+        let mut parser = Parser::with_config(TestHandler::new(), lenient);
+        parser.parse("<a><b/></a><c>").unwrap();
+        assert!(!parser.is_document_complete());
+    }
+
+    #[test]
+    fn test_content_after_root() {
+        let mut parser = Parser::new(TestHandler::new());
+        parser.parse_complete("<?xml version=\"1.0\"?>\n<!-- head -->\n<doc>x</doc>\n<!-- tail -->\n<?pi data?>\n").unwrap();
+        assert_eq!(parser.handler().cdata.concat(), "\n\nx\n\n\n");
+
+        for trailing in ["<doc/>text", "<doc/>\n<!-- c --> text\n", "<doc/><doc/>", "<doc/> <other>", "<doc/><![CDATA[x]]>", "<doc/>&amp;"] {
+            let mut parser = Parser::new(TestHandler::new());
+            let result = parser.parse(trailing).and_then(|_| parser.finish());
+            assert!(matches!(result, Err(IksError::ContentAfterRoot)), "{}: {:?}", trailing, result);
+
+            let lenient = ParserConfig { lenient: true, ..ParserConfig::default() };
+            let mut parser = Parser::with_config(TestHandler::new(), lenient);
+            assert!(parser.parse(trailing).is_ok(), "{}", trailing);
+        }
+    }
+
+    #[test]
+    fn test_unterminated_cdata_section() {
+        for truncated in ["<root>\n  <![CDATA[if (a < b)", "<root>\n  <![CDATA[x]]", "<root>\n  <![CDA"] {
+            let mut parser = Parser::new(TestHandler::new());
+            match parser.parse_complete(truncated) {
+                Err(IksError::UnterminatedCData { position }) => {
+                    assert_eq!((position.offset, position.line, position.column), (9, 2, 3));
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+
+        let mut parser = Parser::new(TestHandler::new());
+        assert!(matches!(parser.parse_complete("<root><![CDATA[x]]>"), Err(IksError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn test_control_characters() {
+        fn parse(xml: &str) -> Result<Vec<String>> {
+            let mut parser = Parser::new(TestHandler::new());
+            parser.parse_complete(xml)?;
+            Ok(parser.handler().cdata.clone())
+        }
+
+        assert!(matches!(parse("<a>tab\there\u{b}</a>"), Err(IksError::InvalidChar(0x0B))));
+        assert!(matches!(parse("<a x=\"\u{1}\"/>"), Err(IksError::InvalidChar(0x01))));
+        assert!(matches!(parse("<a><![CDATA[\u{c}]]></a>"), Err(IksError::InvalidChar(0x0C))));
+        assert!(matches!(parse("<a>&#xB;</a>"), Err(IksError::InvalidChar(0x0B))));
+        assert_eq!(parse("<a>&#65;&#x42;\u{85}</a>").unwrap().concat(), "AB\u{85}");
+
+        let xml11 = "<?xml version=\"1.1\"?><a>";
+        assert!(matches!(parse(&format!("{}\u{b}</a>", xml11)), Err(IksError::InvalidChar(0x0B))));
+        assert!(matches!(parse(&format!("{}\u{80}</a>", xml11)), Err(IksError::InvalidChar(0x80))));
+        assert!(matches!(parse(&format!("{}&#0;</a>", xml11)), Err(IksError::InvalidChar(0))));
+        assert_eq!(parse(&format!("{}&#xB;\u{85}</a>", xml11)).unwrap().concat(), "\u{b}\u{85}");
+        assert!(parse("<?xml version='1.0'?><a>\u{80}</a>").is_ok());
+    }
+
+    #[test]
+    fn test_attribute_errors() {
+        fn error(xml: &str) -> IksError {
+            let mut parser = Parser::new(TestHandler::new());
+            parser.parse(xml).unwrap_err()
+        }
+
+        match error("<a b=c>") {
+            IksError::BadAttribute { name, position } => {
+                assert_eq!(name, "b");
+                assert_eq!((position.offset, position.line, position.column), (3, 1, 4));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match error("<root>\n  <item id=\"1\" flag class=\"x\"/>") {
+            IksError::BadAttribute { name, position } => {
+                assert_eq!(name, "flag");
+                assert_eq!((position.line, position.column), (2, 16));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert!(matches!(error("<a x=\"1<2\"/>"), IksError::BadAttribute { name, .. } if name == "x"));
+        assert!(matches!(error("<a x=\"&nope;\"/>"), IksError::BadAttribute { name, .. } if name == "x"));
+        assert_eq!(error("<a b=c>").to_string(), "Malformed attribute `b` at line 1, column 4");
+    }
+
+    #[test]
+    fn test_token_start() {
+        let mut parser = Parser::new(TestHandler::new());
+        parser.parse("<root>\n  <item\n    ").unwrap();
+        assert_eq!((parser.token_line(), parser.token_column()), (2, 3));
+        assert_eq!(parser.token_start().offset, 9);
+        assert_eq!((parser.line(), parser.column()), (3, 4));
+
+        parser.parse("id=\"1\"\n").unwrap();
+        assert_eq!((parser.token_line(), parser.token_column()), (3, 5));
+        parser.parse("  />x &amp").unwrap();
+        assert_eq!((parser.token_line(), parser.token_column()), (4, 7));
+    }
+
+    #[test]
+    fn test_markup_declarations() {
+        #[derive(Default)]
+        struct DeclHandler {
+            decls: Vec<String>,
+            tags: Vec<String>,
+        }
+
+        impl SaxHandler for DeclHandler {
+            type Error = IksError;
+
+            fn on_tag(&mut self, name: &str, _attributes: &[(String, String)], _tag_type: TagType) -> Result<()> {
+                self.tags.push(name.to_string());
+                Ok(())
+            }
+
+            fn on_cdata(&mut self, _data: &str) -> Result<()> {
+                Ok(())
+            }
+
+            fn on_markup_decl(&mut self, decl: &str) -> Result<()> {
+                self.decls.push(decl.to_string());
+                Ok(())
+            }
+        }
+
+        let decls = [
+            "ENTITY copy \"(c) <2024>\"",
+            "ATTLIST item id ID #REQUIRED kind (a|b) 'a>b'",
+            "NOTATION png SYSTEM \"image/png\"",
+            "ELEMENT list (item)*",
+        ];
+        for decl in decls {
+            let mut parser = Parser::new(DeclHandler::default());
+            parser.parse_complete(&format!("<!{}><root/>", decl)).unwrap();
+            assert_eq!(parser.handler().decls, [decl]);
+            assert_eq!(parser.handler().tags, ["root"]);
+        }
+
+        let mut parser = Parser::new(DeclHandler::default());
+        let subset: String = decls.iter().map(|decl| format!("<!{}>", decl)).collect();
+        parser.parse_complete(&format!("<!DOCTYPE root [{}]><root/>", subset)).unwrap();
+        assert!(parser.handler().decls.is_empty());
+        assert_eq!(parser.handler().tags, ["root"]);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut parser = Parser::new(TestHandler::new());
+        parser.parse("<root><item").unwrap();
+        let state = parser.snapshot();
+
+        assert!(parser.parse(" id='1' broken=value>").is_err());
+
+        parser.restore(state);
+        parser.parse(" id='2'>text</item></root>").unwrap();
+
+        let (tags, cdata) = (&parser.handler.tags, &parser.handler.cdata);
+        assert_eq!(tags.len(), 4);
+        assert_eq!(tags[1].0, "item");
+        assert_eq!(tags[1].1, vec![("id".to_string(), "2".to_string())]);
+        assert_eq!(cdata, &vec!["text".to_string()]);
+        assert_eq!(parser.offset(), "<root><item id='2'>text</item></root>".len());
+
+        let config = ParserConfig { apply_dtd_defaults: true, ..ParserConfig::default() };
+        let mut parser = Parser::with_config(TestHandler::new(), config);
+        let state = parser.snapshot();
+        parser.parse("<!DOCTYPE a [<!ATTLIST a x CDATA 'd'>]>").unwrap();
+        parser.restore(state);
+        parser.parse("<a/>").unwrap();
+        assert_eq!(parser.handler.tags[0].1, vec![]);
+    }
+}
@@ -0,0 +1,755 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::io;
+use std::rc::Rc;
+use crate::{Doctype, IksNode, IksType};
+use crate::constants::xml;
+use crate::utility::{escape_for, needs_escape, needs_escape_attr, split_qname, EscapeContext};
+
+/// Options controlling how a tree is written out.
+///
+/// The default writes a bare fragment, exactly like `Display`.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::{IksNode, SerializeOptions};
+///
+/// let node = IksNode::new_tag("root");
+/// let options = SerializeOptions::new().with_prolog();
+/// assert_eq!(
+///     node.to_string_with(&options),
+///     "<?xml version=\"1.0\" encoding=\"UTF-8\"?><root/>"
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SerializeOptions {
+    /// XML declaration written once before the root element
+    pub prolog: Option<String>,
+    /// Spaces per nesting level, or `None` to write everything on one line
+    pub indent: Option<usize>,
+    /// Line width above which pretty printing puts each attribute of a
+    /// start tag on its own line, or `None` to never wrap
+    pub max_line_width: Option<usize>,
+    /// Write the attributes of each start tag ordered by name instead of in
+    /// source order
+    pub sort_attributes: bool,
+    /// Leave out attributes whose value is the empty string
+    pub omit_empty_attributes: bool,
+    /// Write attributes marked as valueless as a bare name, like
+    /// `<input disabled>`, instead of `disabled=""`
+    pub valueless_attributes: bool,
+    /// Names of elements that are written as `<br/>` whenever they are
+    /// empty, even if they hold an empty text
+    pub void_elements: HashSet<String>,
+    /// Names of elements that are never self-closed, so an empty one is
+    /// written as `<div></div>`
+    pub expanded_elements: HashSet<String>,
+    /// Nesting depth from which pretty printing writes elements with
+    /// content as `<name>...</name>`, or `None` to print everything
+    pub collapse_depth: Option<usize>,
+    /// Prefixes to write for namespace URIs, replacing the prefixes the
+    /// tree uses for them; empty prefixes are ignored
+    pub namespace_prefixes: HashMap<String, String>,
+}
+
+impl SerializeOptions {
+    /// Creates the default options.
+    ///
+    /// # Returns
+    ///
+    /// A new `SerializeOptions` instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables the standard `<?xml version="1.0" encoding="UTF-8"?>` prolog.
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn with_prolog(self) -> Self {
+        self.prolog(xml::DEFAULT_PROLOG)
+    }
+
+    /// Sets a custom XML declaration to write before the root element.
+    ///
+    /// # Arguments
+    ///
+    /// * `declaration` - The complete declaration, e.g. `<?xml version="1.1"?>`
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn prolog<S: Into<String>>(mut self, declaration: S) -> Self {
+        self.prolog = Some(declaration.into());
+        self
+    }
+
+    /// Enables pretty printing with one element per line.
+    ///
+    /// # Arguments
+    ///
+    /// * `indent` - Number of spaces per nesting level
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = Some(indent);
+        self
+    }
+
+    /// Limits the width of pretty printed start tags.
+    ///
+    /// A start tag with several attributes that would not fit within the
+    /// width is broken up with one attribute per line. Tags with at most one
+    /// attribute always stay on one line. Has no effect without `indent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The maximum line width in characters
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn max_line_width(mut self, width: usize) -> Self {
+        self.max_line_width = Some(width);
+        self
+    }
+
+    /// Writes attributes in lexicographic order of their names.
+    ///
+    /// This gives deterministic output for trees built in different orders,
+    /// which helps when diffing or signing documents. Attributes sharing a
+    /// name keep their relative order.
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn sort_attributes(mut self) -> Self {
+        self.sort_attributes = true;
+        self
+    }
+
+    /// Skips attributes with an empty value, writing `<a/>` for `<a b=""/>`.
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn omit_empty_attributes(mut self) -> Self {
+        self.omit_empty_attributes = true;
+        self
+    }
+
+    /// Writes valueless attributes as a bare name.
+    ///
+    /// Attributes added with `IksNode::add_valueless_attribute`, or parsed
+    /// without a value in lenient mode, then come out as in
+    /// `<input disabled/>`. The result is HTML rather than XML; strict XML
+    /// parsers reject it. Other empty attributes keep their `=""`.
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn valueless_attributes(mut self) -> Self {
+        self.valueless_attributes = true;
+        self
+    }
+
+    /// Always self-closes empty elements of the given name.
+    ///
+    /// Without this an element is self-closed only if it has neither
+    /// children nor text, so one holding an empty text is written as
+    /// `<br></br>`. Meant for the void elements of XHTML.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The element name, e.g. `br`
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn void_element<S: Into<String>>(mut self, name: S) -> Self {
+        self.void_elements.insert(name.into());
+        self
+    }
+
+    /// Never self-closes elements of the given name.
+    ///
+    /// XHTML served as HTML needs e.g. `<div></div>`, since browsers read
+    /// `<div/>` as an unclosed start tag.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The element name, e.g. `div`
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn expand_element<S: Into<String>>(mut self, name: S) -> Self {
+        self.expanded_elements.insert(name.into());
+        self
+    }
+
+    /// Elides the content of the elements at nesting level `depth` and below.
+    ///
+    /// The printed node is at depth 0, so with a depth of 1 only its own
+    /// children are shown, each collapsed to its start tag, `...` and its
+    /// end tag. Elements inside mixed content count their levels the same
+    /// way. Empty elements and text are printed as usual. The output is meant
+    /// for logs and previews and does not reparse to the same tree. Has no
+    /// effect without `indent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `depth` - The first nesting level whose elements are collapsed
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn collapse_depth(mut self, depth: usize) -> Self {
+        self.collapse_depth = Some(depth);
+        self
+    }
+
+    /// Writes a namespace with the given prefix, however the tree binds it.
+    ///
+    /// Element and attribute names in the namespace get the prefix, and
+    /// the declarations of the namespace, including a default `xmlns`, are
+    /// written as `xmlns:prefix`. Documents using different prefixes for
+    /// the same namespace thus come out alike. Names are resolved against
+    /// the whole tree, so a namespace declared above the serialized node is
+    /// renamed too, though its declaration is not written. The prefix must
+    /// not be bound to another namespace anywhere in the tree. An empty
+    /// prefix would give names like `:item`, so it is ignored and the
+    /// namespace keeps the prefixes of the tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `uri` - The namespace URI, e.g. `jabber:iq:roster`
+    /// * `prefix` - The prefix to write, e.g. `roster`
+    ///
+    /// # Returns
+    ///
+    /// The updated options
+    pub fn namespace_prefix<U: Into<String>, P: Into<String>>(mut self, uri: U, prefix: P) -> Self {
+        self.namespace_prefixes.insert(uri.into(), prefix.into());
+        self
+    }
+
+    /// Gets the prefix to write for a namespace URI, skipping empty ones.
+    fn prefix_for(&self, uri: &str) -> Option<&str> {
+        self.namespace_prefixes.get(uri).map(String::as_str).filter(|prefix| !prefix.is_empty())
+    }
+
+    /// Decides whether an element without children is self-closed.
+    fn self_closes(&self, node: &IksNode) -> bool {
+        let name = node.name.as_deref().unwrap_or_default();
+        if self.void_elements.contains(name) {
+            node.content.as_deref().is_none_or(str::is_empty)
+        } else {
+            node.content.is_none() && !self.expanded_elements.contains(name)
+        }
+    }
+}
+
+/// Serializes a node as a document, honoring the given options.
+///
+/// # Arguments
+///
+/// * `node` - The root of the subtree to serialize
+/// * `doctype` - The document type declaration to write after the prolog
+/// * `out` - The sink to write the XML text to
+/// * `options` - How to write the document
+///
+/// # Returns
+///
+/// A `fmt::Result` indicating whether the sink accepted all output
+pub(crate) fn serialize_document<W: Write>(node: &IksNode, doctype: Option<&Doctype>, out: &mut W, options: &SerializeOptions) -> fmt::Result {
+    if let Some(prolog) = &options.prolog {
+        out.write_str(prolog)?;
+    }
+    if let Some(doctype) = doctype {
+        write!(out, "{}", doctype)?;
+    }
+    match options.indent {
+        Some(indent) => serialize_pretty(node, out, indent, options),
+        None => serialize_with(node, out, options),
+    }
+}
+
+/// A pending step of the serializer's work stack.
+enum Work {
+    /// Serialize a node, pushing its children and closing tag
+    Start(Rc<RefCell<IksNode>>),
+    /// Write the closing tag of an already opened element
+    End(Rc<RefCell<IksNode>>),
+}
+
+/// Serializes a node and its subtree into a formatter sink.
+///
+/// The traversal uses an explicit work stack instead of recursion, so the
+/// depth of the tree is bounded only by available heap memory.
+///
+/// # Arguments
+///
+/// * `node` - The root of the subtree to serialize
+/// * `out` - The sink to write the XML text to
+///
+/// # Returns
+///
+/// A `fmt::Result` indicating whether the sink accepted all output
+pub(crate) fn serialize<W: Write>(node: &IksNode, out: &mut W) -> fmt::Result {
+    serialize_with(node, out, &SerializeOptions::default())
+}
+
+/// Serializes a node and its subtree on one line, honoring the attribute
+/// order of the options.
+fn serialize_with<W: Write>(node: &IksNode, out: &mut W, options: &SerializeOptions) -> fmt::Result {
+    if write_start(node, out, options)? {
+        write_children(node, out, options)?;
+        write_end(node, out, options)?;
+    }
+    Ok(())
+}
+
+/// Serializes the content and children of an element without its own tags.
+///
+/// A container node writes its children. Other nodes have no inner
+/// markup and write nothing.
+///
+/// # Arguments
+///
+/// * `node` - The element whose inside to write
+/// * `out` - The sink receiving the markup
+///
+/// # Returns
+///
+/// A `fmt::Result` indicating whether the sink accepted all output
+pub(crate) fn serialize_inner<W: Write>(node: &IksNode, out: &mut W) -> fmt::Result {
+    if !matches!(node.node_type, IksType::Tag | IksType::None) {
+        return Ok(());
+    }
+    if let Some(content) = &node.content {
+        write_text(out, content)?;
+    }
+    write_children(node, out, &SerializeOptions::default())
+}
+
+/// Serializes the children of an opened node in document order.
+fn write_children<W: Write>(node: &IksNode, out: &mut W, options: &SerializeOptions) -> fmt::Result {
+    let mut stack = Vec::new();
+    push_children(node, &mut stack);
+    while let Some(work) = stack.pop() {
+        match work {
+            Work::Start(child) => {
+                if write_start(&child.borrow(), out, options)? {
+                    stack.push(Work::End(child.clone()));
+                    push_children(&child.borrow(), &mut stack);
+                }
+            }
+            Work::End(child) => write_end(&child.borrow(), out, options)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes the opening part of a node, including its own content.
+///
+/// # Returns
+///
+/// `true` if the node was opened and needs its children and a closing tag
+fn write_start<W: Write>(node: &IksNode, out: &mut W, options: &SerializeOptions) -> Result<bool, fmt::Error> {
+    match node.node_type {
+        IksType::Tag => {
+            write!(out, "<{}", tag_name(node, options))?;
+            for (written, (name, value)) in ordered_attributes(node, options) {
+                write_attribute(out, node, &written, name, value, options)?;
+            }
+
+            if node.children.is_empty() && options.self_closes(node) {
+                out.write_str("/>")?;
+                return Ok(false);
+            }
+
+            out.write_char('>')?;
+            if let Some(content) = &node.content {
+                write_text(out, content)?;
+            }
+            Ok(true)
+        }
+        IksType::CData => {
+            if let Some(content) = &node.content {
+                if node.cdata_section {
+                    write_cdata_section(out, content)?;
+                } else {
+                    write_text(out, content)?;
+                }
+            }
+            Ok(false)
+        }
+        IksType::Comment => {
+            write!(out, "<!--{}-->", node.content.as_deref().unwrap_or_default())?;
+            Ok(false)
+        }
+        IksType::Pi => {
+            write!(out, "<?{}", node.name.as_deref().unwrap_or_default())?;
+            match node.content.as_deref() {
+                Some(data) if !data.is_empty() => write!(out, " {}?>", data)?,
+                _ => out.write_str("?>")?,
+            }
+            Ok(false)
+        }
+        IksType::None => Ok(true),
+        IksType::Attribute => Ok(false),
+    }
+}
+
+/// Gets the attributes of a node that are written, in output order, each
+/// with the name it is written under.
+///
+/// Declarations that end up binding the same prefix after renaming are
+/// written once.
+fn ordered_attributes<'a>(node: &'a IksNode, options: &SerializeOptions) -> Vec<(Cow<'a, str>, &'a (String, String))> {
+    let mut attributes: Vec<(Cow<'a, str>, &'a (String, String))> = Vec::with_capacity(node.attributes.len());
+    for attribute in &node.attributes {
+        if options.omit_empty_attributes && attribute.1.is_empty() {
+            continue;
+        }
+        let written = attribute_name(node, &attribute.0, &attribute.1, options);
+        if matches!(written, Cow::Owned(_)) && attributes.iter().any(|(name, _)| *name == written) {
+            continue;
+        }
+        attributes.push((written, attribute));
+    }
+    if options.sort_attributes {
+        attributes.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+    attributes
+}
+
+/// Gets the name an element is written under, after namespace renaming.
+fn tag_name<'a>(node: &'a IksNode, options: &SerializeOptions) -> Cow<'a, str> {
+    let name = node.name.as_deref().unwrap_or_default();
+    if options.namespace_prefixes.is_empty() {
+        return Cow::Borrowed(name);
+    }
+    let (prefix, local) = split_qname(name);
+    match node.namespace_uri().and_then(|uri| options.prefix_for(&uri)) {
+        Some(renamed) if prefix != Some(renamed) => Cow::Owned(format!("{}:{}", renamed, local)),
+        _ => Cow::Borrowed(name),
+    }
+}
+
+/// Gets the name an attribute is written under, after namespace renaming.
+///
+/// A namespace declaration is renamed by the URI it binds, other prefixed
+/// attributes by the URI their prefix resolves to.
+fn attribute_name<'a>(node: &IksNode, name: &'a str, value: &str, options: &SerializeOptions) -> Cow<'a, str> {
+    if options.namespace_prefixes.is_empty() {
+        return Cow::Borrowed(name);
+    }
+    let renamed = match split_qname(name) {
+        (None, "xmlns") | (Some("xmlns"), _) => {
+            options.prefix_for(value).map(|prefix| format!("xmlns:{}", prefix))
+        }
+        (Some(prefix), local) => node.lookup_namespace(Some(prefix))
+            .and_then(|uri| options.prefix_for(&uri))
+            .map(|prefix| format!("{}:{}", prefix, local)),
+        (None, _) => None,
+    };
+    match renamed {
+        Some(renamed) if renamed != name => Cow::Owned(renamed),
+        _ => Cow::Borrowed(name),
+    }
+}
+
+/// Writes the closing tag of an opened element.
+fn write_end<W: Write>(node: &IksNode, out: &mut W, options: &SerializeOptions) -> fmt::Result {
+    if node.node_type == IksType::None {
+        return Ok(());
+    }
+    write!(out, "</{}>", tag_name(node, options))
+}
+
+/// Queues the children of a node so that they pop in document order.
+fn push_children(node: &IksNode, stack: &mut Vec<Work>) {
+    stack.extend(node.children.iter().rev().cloned().map(Work::Start));
+}
+
+/// A pending step of the pretty printer's work stack.
+enum PrettyWork {
+    /// Print a child node at the given depth
+    Start(Rc<RefCell<IksNode>>, usize),
+    /// Print the closing tag of an element at the given depth
+    End(Rc<RefCell<IksNode>>, usize),
+}
+
+/// Serializes a node and its subtree with one element per line.
+///
+/// Indentation is only inserted into element content. Elements holding
+/// text (mixed content) are written exactly like `serialize` does, and
+/// whitespace-only text between elements is replaced by the printer's own
+/// indentation. This keeps the printed document a faithful rendering of the
+/// tree: re-parsing it yields a tree that is `tree_eq` to the original even
+/// when whitespace is preserved, and pretty printing that tree again gives
+/// the same output.
+///
+/// # Arguments
+///
+/// * `node` - The root of the subtree to serialize
+/// * `out` - The sink to write the XML text to
+/// * `indent` - Number of spaces per nesting level
+/// * `options` - The remaining options, e.g. the line width above which
+///   start tags are wrapped
+///
+/// # Returns
+///
+/// A `fmt::Result` indicating whether the sink accepted all output
+pub(crate) fn serialize_pretty<W: Write>(node: &IksNode, out: &mut W, indent: usize, options: &SerializeOptions) -> fmt::Result {
+    if node.node_type == IksType::None {
+        let children = node.children.iter().filter(|child| !child.borrow().is_whitespace());
+        for (i, child) in children.enumerate() {
+            if i > 0 {
+                out.write_char('\n')?;
+            }
+            serialize_pretty(&child.borrow(), out, indent, options)?;
+        }
+        return Ok(());
+    }
+    let mut stack = Vec::new();
+    let layout = PrettyLayout { indent, options };
+    if let Some(children) = write_pretty_start(node, out, &layout, 0)? {
+        stack.extend(children.into_iter().rev().map(|child| PrettyWork::Start(child, 1)));
+        while let Some(work) = stack.pop() {
+            match work {
+                PrettyWork::Start(child, depth) => {
+                    write_indent(out, indent * depth)?;
+                    if let Some(children) = write_pretty_start(&child.borrow(), out, &layout, depth)? {
+                        stack.push(PrettyWork::End(child.clone(), depth));
+                        stack.extend(children.into_iter().rev().map(|c| PrettyWork::Start(c, depth + 1)));
+                    }
+                }
+                PrettyWork::End(child, depth) => {
+                    write_indent(out, indent * depth)?;
+                    write_end(&child.borrow(), out, options)?;
+                }
+            }
+        }
+        write_indent(out, 0)?;
+        write_end(node, out, options)?;
+    }
+    Ok(())
+}
+
+/// Line layout settings of the pretty printer.
+struct PrettyLayout<'a> {
+    /// Number of spaces per nesting level
+    indent: usize,
+    /// The options the printer was called with
+    options: &'a SerializeOptions,
+}
+
+/// Writes a node for the pretty printer.
+///
+/// Mixed content and leaf nodes are written completely. Elements with
+/// element content are only opened.
+///
+/// # Returns
+///
+/// The children to print on their own lines if the element was opened
+fn write_pretty_start<W: Write>(node: &IksNode, out: &mut W, layout: &PrettyLayout<'_>, depth: usize) -> Result<Option<Vec<Rc<RefCell<IksNode>>>>, fmt::Error> {
+    if collapses(node, depth, layout.options) {
+        write_collapsed(node, out, layout.options)?;
+        return Ok(None);
+    }
+    if node.node_type != IksType::Tag || node.has_mixed_content() {
+        write_inline(node, out, layout.options, depth)?;
+        return Ok(None);
+    }
+
+    let children: Vec<_> = node.children.iter()
+        .filter(|child| !child.borrow().is_whitespace())
+        .cloned()
+        .collect();
+
+    let name = tag_name(node, layout.options);
+    let mut tag = String::new();
+    write!(tag, "<{}", name)?;
+    let mut attributes = Vec::with_capacity(node.attributes.len());
+    for (written, (name, value)) in ordered_attributes(node, layout.options) {
+        let mut attribute = String::new();
+        write_attribute(&mut attribute, node, &written, name, value, layout.options)?;
+        attributes.push(attribute);
+    }
+    let empty_end;
+    let close = match children.is_empty() {
+        false => ">",
+        true if layout.options.self_closes(node) => "/>",
+        true => {
+            empty_end = format!("></{}>", name);
+            &empty_end
+        }
+    };
+
+    let width = layout.indent * depth
+        + tag.chars().count()
+        + attributes.iter().map(|a| a.chars().count()).sum::<usize>()
+        + close.len();
+    out.write_str(&tag)?;
+    if attributes.len() > 1 && layout.options.max_line_width.is_some_and(|max| width > max) {
+        for attribute in &attributes {
+            write_indent(out, layout.indent * (depth + 1))?;
+            out.write_str(attribute.trim_start())?;
+        }
+    } else {
+        for attribute in &attributes {
+            out.write_str(attribute)?;
+        }
+    }
+    out.write_str(close)?;
+    Ok(if children.is_empty() { None } else { Some(children) })
+}
+
+/// Checks whether the pretty printer elides the content of a node.
+///
+/// # Arguments
+///
+/// * `node` - The node to print
+/// * `depth` - The nesting level of the node in the printed tree
+///
+/// # Returns
+///
+/// `true` for an element with content at or below the collapse depth
+fn collapses(node: &IksNode, depth: usize, options: &SerializeOptions) -> bool {
+    let has_content = !node.children.is_empty() || node.content.as_deref().is_some_and(|c| !c.is_empty());
+    node.node_type == IksType::Tag && has_content && options.collapse_depth.is_some_and(|max| depth >= max)
+}
+
+/// Writes an element as its start tag, `...` and its end tag.
+fn write_collapsed<W: Write>(node: &IksNode, out: &mut W, options: &SerializeOptions) -> fmt::Result {
+    let name = tag_name(node, options);
+    write!(out, "<{}", name)?;
+    for (written, (name, value)) in ordered_attributes(node, options) {
+        write_attribute(out, node, &written, name, value, options)?;
+    }
+    write!(out, ">...</{}>", name)
+}
+
+/// Writes a node and its subtree on one line for the pretty printer.
+///
+/// This is `serialize_with`, except that elements at or below the collapse
+/// depth are elided.
+///
+/// # Arguments
+///
+/// * `node` - The node to write, e.g. an element with mixed content
+/// * `out` - The sink to write the XML text to
+/// * `options` - The options the printer was called with
+/// * `depth` - The nesting level of the node in the printed tree
+///
+/// # Returns
+///
+/// A `fmt::Result` indicating whether the sink accepted all output
+fn write_inline<W: Write>(node: &IksNode, out: &mut W, options: &SerializeOptions, depth: usize) -> fmt::Result {
+    if options.collapse_depth.is_none() {
+        return serialize_with(node, out, options);
+    }
+    if !write_start(node, out, options)? {
+        return Ok(());
+    }
+    let mut stack: Vec<_> = node.children.iter().rev().map(|child| PrettyWork::Start(child.clone(), depth + 1)).collect();
+    while let Some(work) = stack.pop() {
+        match work {
+            PrettyWork::Start(child, depth) => {
+                let child_ref = child.borrow();
+                if collapses(&child_ref, depth, options) {
+                    write_collapsed(&child_ref, out, options)?;
+                } else if write_start(&child_ref, out, options)? {
+                    stack.push(PrettyWork::End(child.clone(), depth));
+                    stack.extend(child_ref.children.iter().rev().map(|c| PrettyWork::Start(c.clone(), depth + 1)));
+                }
+            }
+            PrettyWork::End(child, _) => write_end(&child.borrow(), out, options)?,
+        }
+    }
+    write_end(node, out, options)
+}
+
+/// Starts a new line indented by the given number of spaces.
+fn write_indent<W: Write>(out: &mut W, width: usize) -> fmt::Result {
+    write!(out, "\n{:width$}", "", width = width)
+}
+
+/// Adapts an `io::Write` into a `fmt::Write`, keeping the underlying error.
+pub(crate) struct IoWriter<'a, W: io::Write> {
+    inner: &'a mut W,
+    /// The last I/O error reported by the inner writer
+    pub(crate) error: Option<io::Error>,
+}
+
+impl<'a, W: io::Write> IoWriter<'a, W> {
+    /// Wraps an `io::Write` sink.
+    pub(crate) fn new(inner: &'a mut W) -> Self {
+        IoWriter { inner, error: None }
+    }
+}
+
+impl<W: io::Write> Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+/// Writes an attribute of a node under the name `written`, escaping its
+/// value only when needed.
+///
+/// A value whose source form the node kept is written in that form.
+fn write_attribute<W: Write>(out: &mut W, node: &IksNode, written: &str, name: &str, value: &str, options: &SerializeOptions) -> fmt::Result {
+    if options.valueless_attributes && node.is_valueless_attribute(name) {
+        write!(out, " {}", written)
+    } else if let Some(raw) = node.attribute_source(name) {
+        write!(out, " {}=\"{}\"", written, raw)
+    } else if needs_escape_attr(value) {
+        write!(out, " {}=\"{}\"", written, escape_for(value, EscapeContext::Attribute))
+    } else {
+        write!(out, " {}=\"{}\"", written, value)
+    }
+}
+
+/// Writes text content, escaping it only when needed.
+fn write_text<W: Write>(out: &mut W, text: &str) -> fmt::Result {
+    if needs_escape(text) {
+        out.write_str(&escape_for(text, EscapeContext::Text))
+    } else {
+        out.write_str(text)
+    }
+}
+
+/// Writes text as a CDATA section.
+///
+/// A `]]>` inside the text would end the section early, so the section is
+/// closed between `]]` and `>` and a new one is opened for the rest.
+fn write_cdata_section<W: Write>(out: &mut W, text: &str) -> fmt::Result {
+    write!(out, "<![CDATA[{}]]>", escape_for(text, EscapeContext::CData))
+}
+
@@ -0,0 +1,322 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use crate::{IksError, Result, SaxHandler, TagType};
+
+/// Handler receiving the attributes of a start tag one at a time.
+///
+/// Where `SaxHandler::on_tag` gets all attributes of a tag at once, this
+/// trait is told about each attribute as soon as the parser has read it, so
+/// the parser never collects them. Wrap an implementation in `Streaming` to
+/// hand it to a `Parser`.
+///
+/// A start tag is reported as `on_start_tag`, then `on_attribute` for each
+/// attribute in source order, then `on_start_tag_end`.
+pub trait StreamingSaxHandler {
+    /// Called when the name of a start tag has been read.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the element
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure
+    fn on_start_tag(&mut self, name: &str) -> Result<()>;
+
+    /// Called for each attribute of the current start tag.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the attribute
+    /// * `value` - The value, with entity references decoded
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure
+    fn on_attribute(&mut self, name: &str, value: &str) -> Result<()>;
+
+    /// Called when the current start tag is complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `empty` - `true` for an empty element such as `<br/>`, which gets
+    ///   no `on_end_tag`
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure
+    fn on_start_tag_end(&mut self, empty: bool) -> Result<()>;
+
+    /// Called when an end tag is encountered.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the element
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure
+    fn on_end_tag(&mut self, name: &str) -> Result<()>;
+
+    /// Called when character data is encountered.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The character data encountered
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure
+    fn on_cdata(&mut self, data: &str) -> Result<()>;
+}
+
+/// Adapts a `StreamingSaxHandler` to the `SaxHandler` interface.
+///
+/// A `Parser` recognizes the adapter and reports attributes straight to the
+/// wrapped handler. Anything else that drives a `SaxHandler`, such as
+/// `Event::dispatch`, still works: the attributes passed to `on_tag` are
+/// then replayed one by one.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::{Parser, Result, Streaming, StreamingSaxHandler};
+///
+/// #[derive(Default)]
+/// struct Ids(Vec<String>);
+///
+/// impl StreamingSaxHandler for Ids {
+///     fn on_start_tag(&mut self, _name: &str) -> Result<()> { Ok(()) }
+///     fn on_attribute(&mut self, name: &str, value: &str) -> Result<()> {
+///         if name == "id" {
+///             self.0.push(value.to_string());
+///         }
+///         Ok(())
+///     }
+///     fn on_start_tag_end(&mut self, _empty: bool) -> Result<()> { Ok(()) }
+///     fn on_end_tag(&mut self, _name: &str) -> Result<()> { Ok(()) }
+///     fn on_cdata(&mut self, _data: &str) -> Result<()> { Ok(()) }
+/// }
+///
+/// let mut parser = Parser::new(Streaming::new(Ids::default()));
+/// parser.parse("<a id='1'><b id='2'/></a>").unwrap();
+/// assert_eq!(parser.handler().handler().0, ["1", "2"]);
+/// ```
+#[derive(Debug, Default)]
+pub struct Streaming<H> {
+    handler: H,
+    /// Whether `on_start_tag` was reported for the tag being read
+    started: bool,
+}
+
+impl<H: StreamingSaxHandler> Streaming<H> {
+    /// Wraps a streaming handler.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler to receive the events
+    ///
+    /// # Returns
+    ///
+    /// A new `Streaming` instance
+    pub fn new(handler: H) -> Self {
+        Self { handler, started: false }
+    }
+
+    /// Gets a reference to the wrapped handler.
+    ///
+    /// # Returns
+    ///
+    /// A reference to the handler
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Gets a mutable reference to the wrapped handler.
+    ///
+    /// # Returns
+    ///
+    /// A mutable reference to the handler
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Unwraps the handler.
+    ///
+    /// # Returns
+    ///
+    /// The wrapped handler
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+}
+
+impl<H: StreamingSaxHandler> StreamingSaxHandler for Streaming<H> {
+    fn on_start_tag(&mut self, name: &str) -> Result<()> {
+        self.started = true;
+        self.handler.on_start_tag(name)
+    }
+
+    fn on_attribute(&mut self, name: &str, value: &str) -> Result<()> {
+        self.handler.on_attribute(name, value)
+    }
+
+    fn on_start_tag_end(&mut self, empty: bool) -> Result<()> {
+        self.started = false;
+        self.handler.on_start_tag_end(empty)
+    }
+
+    fn on_end_tag(&mut self, name: &str) -> Result<()> {
+        self.handler.on_end_tag(name)
+    }
+
+    fn on_cdata(&mut self, data: &str) -> Result<()> {
+        self.handler.on_cdata(data)
+    }
+}
+
+impl<H: StreamingSaxHandler> SaxHandler for Streaming<H> {
+    type Error = IksError;
+
+    fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+        if tag_type == TagType::Close {
+            return self.handler.on_end_tag(name);
+        }
+        if !self.started {
+            self.handler.on_start_tag(name)?;
+            for (name, value) in attributes {
+                self.handler.on_attribute(name, value)?;
+            }
+        }
+        self.on_start_tag_end(tag_type == TagType::Single)
+    }
+
+    fn on_cdata(&mut self, data: &str) -> Result<()> {
+        self.handler.on_cdata(data)
+    }
+
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingSaxHandler> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, Parser, ParserConfig};
+
+    /// The tags of a document, as (name, attributes, tag type)
+    type Tags = Vec<(String, Vec<(String, String)>, TagType)>;
+
+    #[derive(Default)]
+    struct Batched {
+        tags: Tags,
+    }
+
+    impl SaxHandler for Batched {
+        type Error = IksError;
+
+        fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+            self.tags.push((name.to_string(), attributes.to_vec(), tag_type));
+            Ok(())
+        }
+
+        fn on_cdata(&mut self, _data: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Pushed {
+        tags: Tags,
+        current: Option<(String, Vec<(String, String)>)>,
+    }
+
+    impl StreamingSaxHandler for Pushed {
+        fn on_start_tag(&mut self, name: &str) -> Result<()> {
+            assert!(self.current.is_none());
+            self.current = Some((name.to_string(), Vec::new()));
+            Ok(())
+        }
+
+        fn on_attribute(&mut self, name: &str, value: &str) -> Result<()> {
+            let (_, attributes) = self.current.as_mut().expect("attribute outside a start tag");
+            attributes.push((name.to_string(), value.to_string()));
+            Ok(())
+        }
+
+        fn on_start_tag_end(&mut self, empty: bool) -> Result<()> {
+            let (name, attributes) = self.current.take().expect("no start tag");
+            let tag_type = if empty { TagType::Single } else { TagType::Open };
+            self.tags.push((name, attributes, tag_type));
+            Ok(())
+        }
+
+        fn on_end_tag(&mut self, name: &str) -> Result<()> {
+            self.tags.push((name.to_string(), Vec::new(), TagType::Close));
+            Ok(())
+        }
+
+        fn on_cdata(&mut self, _data: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_matches_batched_handler() {
+        let xml = "<doc a='1' b=\"x &amp; y\"><item\n id='7'/><empty/><item id='8' >t</item ></doc>";
+
+        let mut batched = Parser::new(Batched::default());
+        batched.parse(xml).unwrap();
+        let mut pushed = Parser::new(Streaming::new(Pushed::default()));
+        for chunk in xml.as_bytes().chunks(3) {
+            pushed.parse(std::str::from_utf8(chunk).unwrap()).unwrap();
+        }
+        assert_eq!(pushed.handler().handler().tags, batched.handler().tags);
+        assert_eq!(batched.handler().tags[0].1.len(), 2);
+
+        let mut replayed = Streaming::new(Pushed::default());
+        for event in [
+            Event::StartTag { name: "a".to_string(), attributes: vec![("k".to_string(), "v".to_string())] },
+            Event::EndTag("a".to_string()),
+        ] {
+            event.dispatch(&mut replayed).unwrap();
+        }
+        assert_eq!(replayed.into_inner().tags, [
+            ("a".to_string(), vec![("k".to_string(), "v".to_string())], TagType::Open),
+            ("a".to_string(), Vec::new(), TagType::Close),
+        ]);
+    }
+
+    #[test]
+    fn test_dtd_defaults() {
+        let xml = "<!DOCTYPE list [<!ATTLIST item type CDATA 'book' lang CDATA 'en'>]>\
+                   <list><item/><item lang='tr' id='1'>x</item><other/><item type='film' lang='de'/></list>";
+        let config = ParserConfig { apply_dtd_defaults: true, ..ParserConfig::default() };
+
+        let mut batched = Parser::with_config(Batched::default(), config.clone());
+        batched.parse(xml).unwrap();
+        let mut pushed = Parser::with_config(Streaming::new(Pushed::default()), config);
+        pushed.parse(xml).unwrap();
+
+        let tags = &pushed.handler().handler().tags;
+        assert_eq!(tags, &batched.handler().tags);
+        let attributes = |index: usize| -> Vec<(&str, &str)> {
+            tags[index].1.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect()
+        };
+        assert_eq!(attributes(1), [("type", "book"), ("lang", "en")]);
+        assert_eq!(attributes(2), [("lang", "tr"), ("id", "1"), ("type", "book")]);
+        assert_eq!(attributes(5), [("type", "film"), ("lang", "de")]);
+    }
+}
@@ -0,0 +1,197 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::collections::HashMap;
+
+/// An interned element name.
+///
+/// Symbols are small integers handed out in order by a `SymbolTable`, so
+/// handlers can dispatch on them with a `match` on constants or by indexing
+/// an array instead of comparing strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// Gets the numeric id of this symbol.
+    ///
+    /// # Returns
+    ///
+    /// The position of the name in its table, starting at 0
+    pub fn id(self) -> u32 {
+        self.0
+    }
+
+    /// Gets the id of this symbol as an index.
+    ///
+    /// # Returns
+    ///
+    /// The id, suitable for indexing a dispatch table
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Maps element names to symbols and back.
+///
+/// A handler pre-seeds the table with the names it knows about, remembers
+/// their symbols and passes the table to `Parser::set_symbols`. The parser
+/// then interns every tag name and reports its symbol through
+/// `SaxHandler::on_tag_symbol`. Names not seeded get the next free id the
+/// first time they are seen.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::SymbolTable;
+///
+/// let mut symbols = SymbolTable::new();
+/// let message = symbols.intern("message");
+/// assert_eq!(symbols.intern("message"), message);
+/// assert_eq!(symbols.name(message), Some("message"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    ids: HashMap<String, Symbol>,
+    names: Vec<String>,
+}
+
+impl SymbolTable {
+    /// Creates an empty table.
+    ///
+    /// # Returns
+    ///
+    /// A new `SymbolTable` instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the symbol of a name, adding the name if it is new.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name to intern
+    ///
+    /// # Returns
+    ///
+    /// The symbol of the name; the same name always yields the same symbol
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.ids.get(name) {
+            return symbol;
+        }
+        let id = u32::try_from(self.names.len()).expect("symbol table is full");
+        let symbol = Symbol(id);
+        self.ids.insert(name.to_string(), symbol);
+        self.names.push(name.to_string());
+        symbol
+    }
+
+    /// Looks up the symbol of a name without adding it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name to look up
+    ///
+    /// # Returns
+    ///
+    /// The symbol, or `None` if the name was never interned
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.ids.get(name).copied()
+    }
+
+    /// Gets the name of a symbol.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - A symbol handed out by this table
+    ///
+    /// # Returns
+    ///
+    /// The name, or `None` if the symbol belongs to another table
+    pub fn name(&self, symbol: Symbol) -> Option<&str> {
+        self.names.get(symbol.index()).map(String::as_str)
+    }
+
+    /// Gets the number of interned names.
+    ///
+    /// # Returns
+    ///
+    /// The number of names in the table
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Checks whether the table is empty.
+    ///
+    /// # Returns
+    ///
+    /// `true` if no name has been interned
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IksError, Parser, Result, SaxHandler, TagType};
+
+    struct Dispatcher {
+        counts: Vec<usize>,
+        names: Vec<String>,
+    }
+
+    impl SaxHandler for Dispatcher {
+        type Error = IksError;
+
+        fn on_tag(&mut self, _name: &str, _attributes: &[(String, String)], _tag_type: TagType) -> Result<()> {
+            unreachable!("the parser reports symbols")
+        }
+
+        fn on_tag_symbol(&mut self, symbol: Symbol, name: &str, _attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+            if tag_type != TagType::Close {
+                if self.counts.len() <= symbol.index() {
+                    self.counts.resize(symbol.index() + 1, 0);
+                }
+                self.counts[symbol.index()] += 1;
+                self.names.push(name.to_string());
+            }
+            Ok(())
+        }
+
+        fn on_cdata(&mut self, _data: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stable_symbols() {
+        let mut symbols = SymbolTable::new();
+        let message = symbols.intern("message");
+        let body = symbols.intern("body");
+        assert_eq!((message.id(), body.id()), (0, 1));
+
+        let mut parser = Parser::new(Dispatcher { counts: Vec::new(), names: Vec::new() });
+        parser.set_symbols(symbols);
+        parser.parse("<stream><message><body>a</body></message><presence/><message><body/></message></stream>").unwrap();
+
+        let symbols = parser.symbols().unwrap();
+        assert_eq!(symbols.get("message"), Some(message));
+        assert_eq!(symbols.get("body"), Some(body));
+        assert_eq!(symbols.get("stream").map(Symbol::id), Some(2));
+        assert_eq!(symbols.get("presence").map(Symbol::id), Some(3));
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols.name(body), Some("body"));
+        assert_eq!(parser.handler().counts, [2, 2, 1, 1]);
+        assert_eq!(parser.handler().names, ["stream", "message", "body", "presence", "message", "body"]);
+    }
+}
@@ -0,0 +1,359 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::ops::Range;
+use crate::{IksError, Result};
+
+/// The kind of a `Token`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// `<` starting a start tag, or `</` starting an end tag
+    TagOpen,
+    /// The name of an element
+    Name,
+    /// The name of an attribute
+    AttrName,
+    /// The value of an attribute, without its quotes
+    AttrValue,
+    /// `>` ending a tag, or `/>` ending an empty-element tag
+    TagClose,
+    /// Character data between markup, with references undecoded
+    Text,
+    /// A whole `<!--...-->` comment
+    Comment,
+    /// A whole `<![CDATA[...]]>` section
+    CData,
+    /// A whole `<?...?>` processing instruction
+    Pi,
+    /// A whole `<!...>` declaration, such as `<!DOCTYPE ...>`
+    Declaration,
+}
+
+/// A piece of markup found by the `Tokenizer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// What the bytes are
+    pub kind: TokenKind,
+    /// Byte range of the token in the tokenized buffer
+    pub span: Range<usize>,
+}
+
+impl Token {
+    /// Gets the bytes of this token.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The buffer given to the `Tokenizer`
+    ///
+    /// # Returns
+    ///
+    /// The slice of `data` covered by the token
+    pub fn bytes<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.span.clone()]
+    }
+}
+
+/// What the tokenizer expects next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// Text or the start of markup
+    Content,
+    /// The name right after `<` or `</`
+    Name,
+    /// An attribute name or the end of the tag
+    Tag,
+    /// `=` and the quoted value of the attribute just read
+    Value,
+}
+
+/// Splits a buffer into raw tokens without interpreting them.
+///
+/// The tokenizer works below `Parser`: it only finds where tags, names,
+/// attributes and text start and end, and yields their byte ranges. No
+/// entity is decoded, no `String` is built and well-formedness beyond the
+/// token boundaries, such as matching end tags, is not checked. This makes
+/// it suited for zero-copy processing of buffers the caller owns.
+///
+/// Iteration ends after the first error. A token cut off by the end of the
+/// buffer fails with `IksError::UnexpectedEof`; `position` then tells where
+/// it started, so the caller can retry once more data arrived. Text at the
+/// end of the buffer is yielded as it is.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::{TokenKind, Tokenizer};
+///
+/// let data = b"<a href='x'>hi</a>";
+/// let names: Vec<_> = Tokenizer::new(data)
+///     .map(|token| token.unwrap())
+///     .filter(|token| token.kind == TokenKind::Name)
+///     .map(|token| token.bytes(data))
+///     .collect();
+/// assert_eq!(names, [b"a", b"a"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Tokenizer<'a> {
+    data: &'a [u8],
+    pos: usize,
+    mode: Mode,
+    failed: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Creates a tokenizer over a buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The UTF-8 encoded XML data
+    ///
+    /// # Returns
+    ///
+    /// A new `Tokenizer` instance
+    pub fn new(data: &'a [u8]) -> Self {
+        Tokenizer { data, pos: 0, mode: Mode::Content, failed: false }
+    }
+
+    /// Gets the offset at which the next token starts.
+    ///
+    /// After an error this is the start of the token that failed.
+    ///
+    /// # Returns
+    ///
+    /// The byte offset into the buffer
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Reads the next token at the current position.
+    fn read(&mut self) -> Result<Token> {
+        match self.mode {
+            Mode::Content => self.read_content(),
+            Mode::Name => {
+                let token = self.read_name(TokenKind::Name)?;
+                self.mode = Mode::Tag;
+                Ok(token)
+            }
+            Mode::Tag => {
+                self.skip_whitespace();
+                match self.rest() {
+                    [] => Err(IksError::UnexpectedEof),
+                    [b'>', ..] => Ok(self.close(1)),
+                    [b'/', b'>', ..] => Ok(self.close(2)),
+                    [b'/'] => Err(IksError::UnexpectedEof),
+                    _ => {
+                        let token = self.read_name(TokenKind::AttrName)?;
+                        self.mode = Mode::Value;
+                        Ok(token)
+                    }
+                }
+            }
+            Mode::Value => self.read_value(),
+        }
+    }
+
+    /// Reads text or a piece of markup starting with `<`.
+    fn read_content(&mut self) -> Result<Token> {
+        let start = self.pos;
+        let rest = self.rest();
+        if rest[0] != b'<' {
+            let len = rest.iter().position(|&b| b == b'<').unwrap_or(rest.len());
+            return Ok(self.token(TokenKind::Text, start + len));
+        }
+        let whole = |open: &[u8], close: &[u8]| {
+            rest[open.len()..].windows(close.len())
+                .position(|window| window == close)
+                .map(|at| start + open.len() + at + close.len())
+                .ok_or(IksError::UnexpectedEof)
+        };
+        if rest.starts_with(b"<!--") {
+            let end = whole(b"<!--", b"-->")?;
+            Ok(self.token(TokenKind::Comment, end))
+        } else if rest.starts_with(b"<![CDATA[") {
+            let end = whole(b"<![CDATA[", b"]]>")?;
+            Ok(self.token(TokenKind::CData, end))
+        } else if rest.starts_with(b"<?") {
+            let end = whole(b"<?", b"?>")?;
+            Ok(self.token(TokenKind::Pi, end))
+        } else if rest.starts_with(b"<!") {
+            let end = self.declaration_end()?;
+            Ok(self.token(TokenKind::Declaration, end))
+        } else {
+            let len = if rest.starts_with(b"</") { 2 } else { 1 };
+            self.mode = Mode::Name;
+            Ok(self.token(TokenKind::TagOpen, start + len))
+        }
+    }
+
+    /// Finds the end of a `<!...>` declaration, skipping over an internal
+    /// subset in brackets and quoted literals.
+    fn declaration_end(&self) -> Result<usize> {
+        let mut depth = 0usize;
+        let mut quote = None;
+        for (i, &b) in self.rest().iter().enumerate().skip(2) {
+            match (quote, b) {
+                (Some(q), _) if b == q => quote = None,
+                (Some(_), _) => {}
+                (None, b'"' | b'\'') => quote = Some(b),
+                (None, b'[') => depth += 1,
+                (None, b']') => depth = depth.saturating_sub(1),
+                (None, b'>') if depth == 0 => return Ok(self.pos + i + 1),
+                _ => {}
+            }
+        }
+        Err(IksError::UnexpectedEof)
+    }
+
+    /// Reads an element or attribute name.
+    fn read_name(&mut self, kind: TokenKind) -> Result<Token> {
+        let rest = self.rest();
+        let len = rest.iter()
+            .position(|&b| b.is_ascii_whitespace() || matches!(b, b'/' | b'>' | b'=' | b'<' | b'"' | b'\''))
+            .unwrap_or(rest.len());
+        if len == rest.len() {
+            return Err(IksError::UnexpectedEof);
+        }
+        if len == 0 {
+            return Err(IksError::BadXml);
+        }
+        Ok(self.token(kind, self.pos + len))
+    }
+
+    /// Reads `=` and a quoted attribute value.
+    fn read_value(&mut self) -> Result<Token> {
+        let start = self.pos;
+        self.skip_whitespace();
+        match self.rest().first() {
+            Some(b'=') => self.pos += 1,
+            Some(_) => return self.fail_at(start, IksError::BadXml),
+            None => return self.fail_at(start, IksError::UnexpectedEof),
+        }
+        self.skip_whitespace();
+        let quote = match self.rest().first() {
+            Some(&q @ (b'"' | b'\'')) => q,
+            Some(_) => return self.fail_at(start, IksError::BadXml),
+            None => return self.fail_at(start, IksError::UnexpectedEof),
+        };
+        let value_start = self.pos + 1;
+        let len = match self.data[value_start..].iter().position(|&b| b == quote) {
+            Some(len) => len,
+            None => return self.fail_at(start, IksError::UnexpectedEof),
+        };
+        self.pos = value_start + len + 1;
+        self.mode = Mode::Tag;
+        Ok(Token { kind: TokenKind::AttrValue, span: value_start..value_start + len })
+    }
+
+    /// Yields a token ending a tag.
+    fn close(&mut self, len: usize) -> Token {
+        self.mode = Mode::Content;
+        self.token(TokenKind::TagClose, self.pos + len)
+    }
+
+    /// Yields a token from the current position to `end` and moves past it.
+    fn token(&mut self, kind: TokenKind, end: usize) -> Token {
+        let span = self.pos..end;
+        self.pos = end;
+        Token { kind, span }
+    }
+
+    /// Moves back to the start of a token that failed.
+    fn fail_at(&mut self, start: usize, error: IksError) -> Result<Token> {
+        self.pos = start;
+        Err(error)
+    }
+
+    /// Gets the bytes not tokenized yet.
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    /// Moves past whitespace between the parts of a tag.
+    fn skip_whitespace(&mut self) {
+        while self.rest().first().is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || (self.pos >= self.data.len() && self.mode == Mode::Content) {
+            return None;
+        }
+        if self.pos >= self.data.len() {
+            self.failed = true;
+            return Some(Err(IksError::UnexpectedEof));
+        }
+        let token = self.read();
+        self.failed = token.is_err();
+        Some(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(data: &[u8]) -> Vec<(TokenKind, &str)> {
+        Tokenizer::new(data)
+            .map(|token| {
+                let token = token.unwrap();
+                (token.kind, std::str::from_utf8(token.bytes(data)).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_spans() {
+        use TokenKind::*;
+        let data = "<?xml version='1.0'?><!DOCTYPE a [<!ENTITY e '>'>]><a x = \"1 &amp; 2\" y='ş'>\
+                    tëxt<!-- c --><b/><![CDATA[<raw>]]></a>";
+        assert_eq!(tokens(data.as_bytes()), [
+            (Pi, "<?xml version='1.0'?>"),
+            (Declaration, "<!DOCTYPE a [<!ENTITY e '>'>]>"),
+            (TagOpen, "<"), (Name, "a"),
+            (AttrName, "x"), (AttrValue, "1 &amp; 2"),
+            (AttrName, "y"), (AttrValue, "ş"),
+            (TagClose, ">"),
+            (Text, "tëxt"),
+            (Comment, "<!-- c -->"),
+            (TagOpen, "<"), (Name, "b"), (TagClose, "/>"),
+            (CData, "<![CDATA[<raw>]]>"),
+            (TagOpen, "</"), (Name, "a"), (TagClose, ">"),
+        ]);
+
+        let spans: Vec<_> = Tokenizer::new(b"<a k='v'>t").map(|token| token.unwrap().span).collect();
+        assert_eq!(spans, [0..1, 1..2, 3..4, 6..7, 8..9, 9..10]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut tokenizer = Tokenizer::new(b"<a k='v");
+        assert_eq!(tokenizer.by_ref().take(4).filter(Result::is_ok).count(), 3);
+        assert_eq!(tokenizer.position(), 4);
+        assert!(tokenizer.next().is_none());
+
+        for truncated in [&b"<a"[..], b"<a k", b"<a/", b"<!-- c -", b"<![CDATA[x]]", b"<?pi"] {
+            let last = Tokenizer::new(truncated).last().unwrap();
+            assert!(matches!(last, Err(IksError::UnexpectedEof)), "{:?}", truncated);
+        }
+        for malformed in [&b"<>"[..], b"<a k>", b"<a k=v>"] {
+            let last = Tokenizer::new(malformed).last().unwrap();
+            assert!(matches!(last, Err(IksError::BadXml)), "{:?}", malformed);
+        }
+        assert!(Tokenizer::new(b"").next().is_none());
+    }
+}
@@ -11,8 +11,8 @@
  Affero General Public License for more details.
 */
 
+use std::borrow::Cow;
 use std::sync::Once;
-use std::alloc::{GlobalAlloc, System, Layout};
 
 /// Custom memory allocator wrapper.
 /// 
@@ -126,6 +126,67 @@ pub fn str_len(src: Option<&str>) -> usize {
     src.map_or(0, str::len)
 }
 
+/// Splits a qualified name into its prefix and local part.
+/// 
+/// This does not resolve namespaces; it only separates `prefix:local`
+/// at the first colon.
+/// 
+/// # Arguments
+/// 
+/// * `name` - The qualified name to split
+/// 
+/// # Returns
+/// 
+/// A tuple containing the optional prefix and the local name
+pub fn split_qname(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(':') {
+        Some((prefix, local)) => (Some(prefix), local),
+        None => (None, name),
+    }
+}
+
+/// Checks whether text content contains characters that must be escaped.
+/// 
+/// Serializers use this to write strings without special characters
+/// directly, skipping the allocation made by escaping.
+/// 
+/// # Arguments
+/// 
+/// * `s` - The text to check
+/// 
+/// # Returns
+/// 
+/// `true` if `s` contains `&`, `<` or `>`
+pub fn needs_escape(s: &str) -> bool {
+    s.bytes().any(|b| matches!(b, b'&' | b'<' | b'>'))
+}
+
+/// Checks whether an attribute value contains characters that must be escaped.
+/// 
+/// # Arguments
+/// 
+/// * `s` - The attribute value to check
+/// 
+/// # Returns
+/// 
+/// `true` if `s` contains `&`, `<`, `>`, `"` or `'`
+pub fn needs_escape_attr(s: &str) -> bool {
+    s.bytes().any(|b| matches!(b, b'&' | b'<' | b'>' | b'"' | b'\''))
+}
+
+/// Checks whether text is insignificant whitespace.
+/// 
+/// # Arguments
+/// 
+/// * `s` - The text to check
+/// 
+/// # Returns
+/// 
+/// `true` if `s` is empty or made only of whitespace
+pub(crate) fn is_blank(s: &str) -> bool {
+    s.trim().is_empty()
+}
+
 /// Escapes special XML characters in a string.
 /// 
 /// This function replaces special XML characters with their corresponding
@@ -139,14 +200,54 @@ pub fn str_len(src: Option<&str>) -> usize {
 /// 
 /// The escaped string
 pub fn escape(s: &str) -> String {
+    escape_for(s, EscapeContext::Attribute)
+}
+
+/// The place in a document an escaped string is written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeContext {
+    /// Character data between tags; `&`, `<` and `>` are escaped
+    Text,
+    /// An attribute value in either kind of quotes; `&`, `<`, `>`, `"`
+    /// and `'` are escaped
+    Attribute,
+    /// An attribute value in double quotes; `&`, `<`, `>` and `"` are escaped
+    DoubleQuotedAttribute,
+    /// An attribute value in single quotes; `&`, `<`, `>` and `'` are escaped
+    SingleQuotedAttribute,
+    /// The inside of a `<![CDATA[ ... ]]>` section
+    ///
+    /// Nothing is escaped, but every `]]>` is split across two sections so
+    /// that it cannot end the enclosing one.
+    CData,
+}
+
+/// Escapes a string for the given place in a document.
+/// 
+/// # Arguments
+/// 
+/// * `s` - The string to escape
+/// * `ctx` - Where the result is going to be written
+/// 
+/// # Returns
+/// 
+/// The escaped string, safe to write in `ctx` unchanged
+pub fn escape_for(s: &str, ctx: EscapeContext) -> String {
+    if ctx == EscapeContext::CData {
+        return s.replace("]]>", "]]]]><![CDATA[>");
+    }
     let mut result = String::with_capacity(s.len());
     for c in s.chars() {
         match c {
             '&' => result.push_str("&amp;"),
-            '\'' => result.push_str("&apos;"),
-            '"' => result.push_str("&quot;"),
             '<' => result.push_str("&lt;"),
             '>' => result.push_str("&gt;"),
+            '"' if matches!(ctx, EscapeContext::Attribute | EscapeContext::DoubleQuotedAttribute) => {
+                result.push_str("&quot;")
+            }
+            '\'' if matches!(ctx, EscapeContext::Attribute | EscapeContext::SingleQuotedAttribute) => {
+                result.push_str("&apos;")
+            }
             _ => result.push(c),
         }
     }
@@ -198,9 +299,99 @@ pub fn unescape(s: &str) -> String {
     result
 }
 
+/// Unescapes XML entities in a string, allocating only when needed.
+/// 
+/// Text without any `&` is returned as is; otherwise the result is the
+/// same as that of `unescape`.
+/// 
+/// # Arguments
+/// 
+/// * `s` - The string to unescape
+/// 
+/// # Returns
+/// 
+/// The unescaped string, borrowing `s` if it contains no entity
+pub fn unescape_cow(s: &str) -> Cow<'_, str> {
+    if s.contains('&') {
+        Cow::Owned(unescape(s))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+/// Lazily decodes XML entities in a string.
+/// 
+/// This yields the same characters as `unescape` without building the
+/// whole result, so large text can be processed as it is decoded.
+/// 
+/// # Arguments
+/// 
+/// * `s` - The string to unescape
+/// 
+/// # Returns
+/// 
+/// An iterator over the unescaped characters
+pub fn unescape_iter(s: &str) -> impl Iterator<Item = char> + '_ {
+    Unescape {
+        rest: s,
+        pending: "",
+        pending_semicolon: false,
+    }
+}
+
+/// Iterator state for `unescape_iter`.
+struct Unescape<'a> {
+    /// Input not yet looked at
+    rest: &'a str,
+    /// Name of an unknown entity still being copied to the output
+    pending: &'a str,
+    /// Whether the `;` of an unknown entity is still to be emitted
+    pending_semicolon: bool,
+}
+
+impl Iterator for Unescape<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if let Some(c) = self.pending.chars().next() {
+            self.pending = &self.pending[c.len_utf8()..];
+            return Some(c);
+        }
+        if self.pending_semicolon {
+            self.pending_semicolon = false;
+            return Some(';');
+        }
+
+        let c = self.rest.chars().next()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        if c != '&' {
+            return Some(c);
+        }
+
+        let (entity, rest) = match self.rest.find(';') {
+            Some(end) => (&self.rest[..end], &self.rest[end + 1..]),
+            None => (self.rest, ""),
+        };
+        self.rest = rest;
+        match entity {
+            "amp" => Some('&'),
+            "apos" => Some('\''),
+            "quot" => Some('"'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            _ => {
+                self.pending = entity;
+                self.pending_semicolon = true;
+                Some('&')
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::alloc::{GlobalAlloc, System, Layout};
 
     #[test]
     fn test_string_utils() {
@@ -223,6 +414,22 @@ mod tests {
         assert_eq!(str_len(None), 0);
     }
 
+    #[test]
+    fn test_split_qname() {
+        assert_eq!(split_qname("stream:features"), (Some("stream"), "features"));
+        assert_eq!(split_qname("query"), (None, "query"));
+    }
+
+    #[test]
+    fn test_needs_escape() {
+        assert!(!needs_escape("plain text with 'quotes' and \"more\""));
+        assert!(needs_escape("a & b"));
+        assert!(needs_escape("<tag>"));
+        assert!(!needs_escape_attr("plain value"));
+        assert!(needs_escape_attr("it's"));
+        assert!(needs_escape_attr("say \"hi\""));
+    }
+
     #[test]
     fn test_xml_escaping() {
         let input = "a < b & c > d \"quote\" 'apos'";
@@ -234,6 +441,56 @@ mod tests {
         assert_eq!(unescape(&escaped), input);
     }
 
+    #[test]
+    fn test_escape_for() {
+        let input = "Tom & Jerry's \"<show>\"";
+        assert_eq!(escape_for(input, EscapeContext::Text), "Tom &amp; Jerry's \"&lt;show&gt;\"");
+        assert_eq!(
+            escape_for(input, EscapeContext::Attribute),
+            "Tom &amp; Jerry&apos;s &quot;&lt;show&gt;&quot;"
+        );
+        assert_eq!(
+            escape_for(input, EscapeContext::DoubleQuotedAttribute),
+            "Tom &amp; Jerry's &quot;&lt;show&gt;&quot;"
+        );
+        assert_eq!(
+            escape_for(input, EscapeContext::SingleQuotedAttribute),
+            "Tom &amp; Jerry&apos;s \"&lt;show&gt;\""
+        );
+        assert_eq!(escape_for(input, EscapeContext::CData), input);
+        assert_eq!(escape_for("a]]>b", EscapeContext::CData), "a]]]]><![CDATA[>b");
+        assert_eq!(escape(input), escape_for(input, EscapeContext::Attribute));
+    }
+
+    #[test]
+    fn test_unescape_cow() {
+        assert!(matches!(unescape_cow("no entities at all"), Cow::Borrowed("no entities at all")));
+        assert!(matches!(unescape_cow(""), Cow::Borrowed("")));
+        match unescape_cow("a &lt; b") {
+            Cow::Owned(s) => assert_eq!(s, "a < b"),
+            Cow::Borrowed(s) => panic!("unexpected borrow: {:?}", s),
+        }
+        assert_eq!(unescape_cow("unknown &nbsp; entity"), unescape("unknown &nbsp; entity"));
+    }
+
+    #[test]
+    fn test_unescape_iter() {
+        for input in [
+            "a &lt; b &amp; c &gt; d &quot;quote&quot; &apos;apos&apos;",
+            "no entities at all",
+            "unknown &nbsp; entity and &\u{e7}; char",
+            "dangling &amp",
+            "",
+        ] {
+            assert_eq!(unescape_iter(input).collect::<String>(), unescape(input), "input {:?}", input);
+        }
+
+        let mut chars = unescape_iter("&lt;x");
+        assert_eq!(chars.next(), Some('<'));
+        assert_eq!(chars.next(), Some('x'));
+        assert_eq!(chars.next(), None);
+    }
+
     #[test]
     fn test_custom_allocator() {
         static mut ALLOC_CALLED: bool = false;
@@ -0,0 +1,343 @@
+/* 
+            iksemel - XML parser for Rust
+          Copyright (C) 2024 Süleyman Poyraz
+ This code is free software; you can redistribute it and/or
+ modify it under the terms of the Affero General Public License
+ as published by the Free Software Foundation; either version 3
+ of the License, or (at your option) any later version.
+ This program is distributed in the hope that it will be useful,
+ but WITHOUT ANY WARRANTY; without even the implied warranty of
+ MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ Affero General Public License for more details.
+*/
+
+use std::fmt;
+use crate::{DomParser, IksError, IksNode, IksType, Result};
+
+/// Namespace of the roster query element.
+pub const ROSTER_NS: &str = "jabber:iq:roster";
+
+/// Valid values of the `subscription` attribute of a roster item.
+pub const SUBSCRIPTIONS: [&str; 5] = ["none", "to", "from", "both", "remove"];
+
+/// A single contact entry of a roster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RosterItem {
+    /// Address of the contact
+    pub jid: String,
+    /// Display name of the contact, if one was set
+    pub name: Option<String>,
+    /// Subscription state, one of `SUBSCRIPTIONS`
+    pub subscription: String,
+    /// Groups the contact is filed under
+    pub groups: Vec<String>,
+}
+
+impl RosterItem {
+    /// Creates an item without a name or groups and with no subscription.
+    ///
+    /// # Arguments
+    ///
+    /// * `jid` - Address of the contact
+    ///
+    /// # Returns
+    ///
+    /// A new `RosterItem` instance
+    pub fn new<S: Into<String>>(jid: S) -> Self {
+        RosterItem {
+            jid: jid.into(),
+            name: None,
+            subscription: "none".to_string(),
+            groups: Vec::new(),
+        }
+    }
+
+    /// Reads an item from an `<item>` element.
+    fn from_node(node: &IksNode) -> Result<Self> {
+        let jid = node.find_attrib("jid").ok_or(IksError::BadXml)?;
+        let subscription = node.find_attrib("subscription").unwrap_or("none");
+        if !SUBSCRIPTIONS.contains(&subscription) {
+            return Err(IksError::BadXml);
+        }
+
+        let groups = node.children()
+            .filter(|group| group.borrow().name() == Some("group"))
+            .filter_map(|group| {
+                group.borrow().children()
+                    .find(|text| text.borrow().node_type() == IksType::CData)
+                    .and_then(|text| text.borrow().content().map(String::from))
+            })
+            .collect();
+
+        Ok(RosterItem {
+            jid: jid.to_string(),
+            name: node.find_attrib("name").map(String::from),
+            subscription: subscription.to_string(),
+            groups,
+        })
+    }
+
+    /// Builds the `<item>` element of this item.
+    fn to_node(&self) -> IksNode {
+        let mut node = IksNode::new_tag("item");
+        node.add_attribute("jid", &self.jid);
+        if let Some(name) = &self.name {
+            node.add_attribute("name", name);
+        }
+        node.add_attribute("subscription", &self.subscription);
+        for group in &self.groups {
+            let mut group_node = IksNode::new_tag("group");
+            group_node.insert_cdata(group.as_str());
+            node.add_child(group_node);
+        }
+        node
+    }
+}
+
+/// The contact list of an XMPP account.
+///
+/// A roster is read from and written as the `<query xmlns='jabber:iq:roster'>`
+/// element that servers send and accept. Items are kept in the order they
+/// were added, and no two share a `jid`.
+///
+/// # Examples
+///
+/// ```
+/// use iksemel::xmpp::{Roster, RosterItem};
+///
+/// let mut roster = Roster::from_xml("<query xmlns='jabber:iq:roster'><item jid='romeo@example.net'/></query>").unwrap();
+/// roster.add_item(RosterItem::new("juliet@example.com")).unwrap();
+/// roster.remove_item("romeo@example.net");
+/// assert_eq!(
+///     roster.to_string(),
+///     "<query xmlns=\"jabber:iq:roster\"><item jid=\"juliet@example.com\" subscription=\"none\"/></query>"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Roster {
+    items: Vec<RosterItem>,
+}
+
+impl Roster {
+    /// Creates an empty roster.
+    ///
+    /// # Returns
+    ///
+    /// A new `Roster` instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a roster from a `<query xmlns='jabber:iq:roster'>` element.
+    ///
+    /// Children other than `<item>` elements are skipped. When several
+    /// items share a `jid`, the last one wins.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The roster query element
+    ///
+    /// # Returns
+    ///
+    /// The roster, or `IksError::BadXml` if the node is not a roster query
+    /// or an item lacks a `jid` or has an unknown `subscription`
+    pub fn from_node(query: &IksNode) -> Result<Self> {
+        if query.name() != Some("query") || query.find_attrib("xmlns") != Some(ROSTER_NS) {
+            return Err(IksError::BadXml);
+        }
+
+        let mut roster = Roster::new();
+        for child in query.children() {
+            let child = child.borrow();
+            if child.node_type() == IksType::Tag && child.name() == Some("item") {
+                roster.add_item(RosterItem::from_node(&child)?)?;
+            }
+        }
+        Ok(roster)
+    }
+
+    /// Parses a roster from XML text.
+    ///
+    /// # Arguments
+    ///
+    /// * `xml` - The text of a roster query element
+    ///
+    /// # Returns
+    ///
+    /// The roster, or the parse error or the error of `from_node`
+    pub fn from_xml(xml: &str) -> Result<Self> {
+        let query = DomParser::parse_str(xml)?;
+        let roster = Self::from_node(&query.borrow())?;
+        Ok(roster)
+    }
+
+    /// Builds the roster query element holding all items.
+    ///
+    /// # Returns
+    ///
+    /// A new `<query xmlns='jabber:iq:roster'>` node
+    pub fn to_node(&self) -> IksNode {
+        let mut query = IksNode::new_tag("query");
+        query.add_attribute("xmlns", ROSTER_NS);
+        for item in &self.items {
+            query.add_child(item.to_node());
+        }
+        query
+    }
+
+    /// Adds an item, replacing the one with the same `jid` in place.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - The item to add
+    ///
+    /// # Returns
+    ///
+    /// A `Result` that fails with `IksError::BadXml` if the subscription
+    /// is not one of `SUBSCRIPTIONS`
+    pub fn add_item(&mut self, item: RosterItem) -> Result<()> {
+        if !SUBSCRIPTIONS.contains(&item.subscription.as_str()) {
+            return Err(IksError::BadXml);
+        }
+        match self.items.iter_mut().find(|existing| existing.jid == item.jid) {
+            Some(existing) => *existing = item,
+            None => self.items.push(item),
+        }
+        Ok(())
+    }
+
+    /// Removes the item with the given `jid`.
+    ///
+    /// # Arguments
+    ///
+    /// * `jid` - Address of the contact to remove
+    ///
+    /// # Returns
+    ///
+    /// The removed item, or `None` if the roster had no such contact
+    pub fn remove_item(&mut self, jid: &str) -> Option<RosterItem> {
+        let index = self.items.iter().position(|item| item.jid == jid)?;
+        Some(self.items.remove(index))
+    }
+
+    /// Gets the item with the given `jid`.
+    ///
+    /// # Arguments
+    ///
+    /// * `jid` - Address of the contact
+    ///
+    /// # Returns
+    ///
+    /// The item, or `None` if the roster has no such contact
+    pub fn get(&self, jid: &str) -> Option<&RosterItem> {
+        self.items.iter().find(|item| item.jid == jid)
+    }
+
+    /// Gets all items in order.
+    ///
+    /// # Returns
+    ///
+    /// A slice of the items
+    pub fn items(&self) -> &[RosterItem] {
+        &self.items
+    }
+
+    /// Gets the number of items.
+    ///
+    /// # Returns
+    ///
+    /// The number of contacts in the roster
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Checks whether the roster has no items.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the roster is empty
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl fmt::Display for Roster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_node())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROSTER: &str = "<query xmlns='jabber:iq:roster'>\
+        <item jid='romeo@example.net' name='Romeo' subscription='both'><group>Friends</group></item>\
+        <item jid='mercutio@example.org' subscription='from'/>\
+        </query>";
+
+    #[test]
+    fn test_roster_round_trip() {
+        let roster = Roster::from_xml(ROSTER).unwrap();
+        let items = roster.items();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].jid, "romeo@example.net");
+        assert_eq!(items[0].name.as_deref(), Some("Romeo"));
+        assert_eq!(items[0].subscription, "both");
+        assert_eq!(items[0].groups, vec!["Friends".to_string()]);
+        assert_eq!(items[1].jid, "mercutio@example.org");
+        assert_eq!(items[1].name, None);
+        assert_eq!(items[1].subscription, "from");
+        assert!(items[1].groups.is_empty());
+
+        let xml = roster.to_string();
+        assert_eq!(
+            xml,
+            "<query xmlns=\"jabber:iq:roster\">\
+             <item jid=\"romeo@example.net\" name=\"Romeo\" subscription=\"both\"><group>Friends</group></item>\
+             <item jid=\"mercutio@example.org\" subscription=\"from\"/></query>"
+        );
+        assert_eq!(Roster::from_xml(&xml).unwrap(), roster);
+    }
+
+    #[test]
+    fn test_roster_mutation() {
+        let mut roster = Roster::from_xml(ROSTER).unwrap();
+        let mut juliet = RosterItem::new("juliet@example.com");
+        juliet.groups.push("Family".to_string());
+        roster.add_item(juliet.clone()).unwrap();
+        assert_eq!(roster.len(), 3);
+        assert_eq!(roster.get("juliet@example.com"), Some(&juliet));
+
+        let mut romeo = RosterItem::new("romeo@example.net");
+        romeo.subscription = "to".to_string();
+        roster.add_item(romeo).unwrap();
+        assert_eq!(roster.len(), 3);
+        assert_eq!(roster.items()[0].subscription, "to");
+        assert_eq!(roster.items()[0].name, None);
+
+        let removed = roster.remove_item("mercutio@example.org").unwrap();
+        assert_eq!(removed.subscription, "from");
+        assert_eq!(roster.remove_item("mercutio@example.org"), None);
+        let jids: Vec<_> = roster.items().iter().map(|item| item.jid.as_str()).collect();
+        assert_eq!(jids, ["romeo@example.net", "juliet@example.com"]);
+
+        let mut bogus = RosterItem::new("tybalt@example.com");
+        bogus.subscription = "bogus".to_string();
+        assert!(matches!(roster.add_item(bogus), Err(IksError::BadXml)));
+        assert_eq!(roster.len(), 2);
+
+        roster.remove_item("romeo@example.net");
+        roster.remove_item("juliet@example.com");
+        assert!(roster.is_empty());
+        assert_eq!(roster.to_string(), "<query xmlns=\"jabber:iq:roster\"/>");
+    }
+
+    #[test]
+    fn test_roster_validation() {
+        assert!(Roster::from_xml("<query xmlns='jabber:iq:other'/>").is_err());
+        assert!(Roster::from_xml("<list xmlns='jabber:iq:roster'/>").is_err());
+        assert!(Roster::from_xml("<query xmlns='jabber:iq:roster'><item name='x'/></query>").is_err());
+        assert!(Roster::from_xml("<query xmlns='jabber:iq:roster'><item jid='a@b' subscription='bogus'/></query>").is_err());
+    }
+}
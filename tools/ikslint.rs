@@ -1,8 +1,6 @@
-use std::env;
 use std::fs::File;
 use std::io::{self, Read, BufReader};
-use std::path::Path;
-use clap::{Parser, ValueEnum};
+use clap::Parser;
 use iksemel::{Parser as IksParser, SaxHandler, IksError, Result};
 
 #[derive(Parser)]
@@ -37,6 +35,8 @@ struct TagHandler {
 }
 
 impl SaxHandler for TagHandler {
+    type Error = IksError;
+
     fn on_tag(&mut self, name: &str, _attrs: &[(String, String)], tag_type: iksemel::TagType) -> Result<()> {
         match tag_type {
             iksemel::TagType::Open => {
@@ -71,7 +71,7 @@ impl SaxHandler for TagHandler {
 }
 
 fn check_file(file_path: Option<&str>, args: &Args) -> Result<()> {
-    let mut handler = TagHandler {
+    let handler = TagHandler {
         stats: Stats::default(),
         tag_stack: Vec::new(),
         tag_counts: std::collections::HashMap::new(),
@@ -92,7 +92,7 @@ fn check_file(file_path: Option<&str>, args: &Args) -> Result<()> {
         let chunk = String::from_utf8_lossy(&buffer[..n]);
         parser.parse(&chunk)?;
     }
-    parser.parse("")?;
+    parser.finish()?;
 
     if let Some(path) = file_path {
         println!("File '{}':", path);
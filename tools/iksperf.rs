@@ -1,10 +1,10 @@
+use std::cell::RefCell;
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, BufReader, Read};
+use std::rc::Rc;
 use std::time::Instant;
 use clap::{Parser, ValueEnum};
-use iksemel::{Parser as IksParser, SaxHandler, Result, DomParser, IksNode};
-use std::rc::Rc;
-use std::cell::RefCell;
+use iksemel::{Parser as IksParser, ParserBuilder, SaxHandler, IksError, Result, DomParser, FlatDocument, FlatParser, IksNode, Symbol, SymbolTable, TagType};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -16,6 +16,10 @@ struct Args {
     /// Block size for chunked parsing
     #[arg(short, long, default_value = "4096")]
     block_size: usize,
+
+    /// Read the input in blocks instead of loading it into memory first
+    #[arg(long)]
+    stream: bool,
 }
 
 #[derive(ValueEnum, Clone, Debug)]
@@ -42,6 +46,8 @@ impl TestHandler {
 }
 
 impl SaxHandler for TestHandler {
+    type Error = IksError;
+
     fn on_tag(&mut self, name: &str, _attributes: &[(String, String)], _tag_type: iksemel::TagType) -> Result<()> {
         self.tags.push(name.to_string());
         Ok(())
@@ -53,48 +59,223 @@ impl SaxHandler for TestHandler {
     }
 }
 
+/// Names the dispatch benchmarks look for, as in an XMPP stream
+const DISPATCH_NAMES: [&str; 3] = ["message", "presence", "iq"];
+
+/// Counts known elements by comparing their names.
+#[derive(Default)]
+struct StringDispatch {
+    counts: [usize; 4],
+}
+
+impl SaxHandler for StringDispatch {
+    type Error = IksError;
+
+    fn on_tag(&mut self, name: &str, _attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+        if tag_type != TagType::Close {
+            let slot = match name {
+                "message" => 0,
+                "presence" => 1,
+                "iq" => 2,
+                _ => 3,
+            };
+            self.counts[slot] += 1;
+        }
+        Ok(())
+    }
+
+    fn on_cdata(&mut self, _data: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Counts known elements by indexing with their interned symbols.
+struct SymbolDispatch {
+    known: Vec<Symbol>,
+    counts: [usize; 4],
+}
+
+impl SaxHandler for SymbolDispatch {
+    type Error = IksError;
+
+    fn on_tag(&mut self, _name: &str, _attributes: &[(String, String)], _tag_type: TagType) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_tag_symbol(&mut self, symbol: Symbol, _name: &str, _attributes: &[(String, String)], tag_type: TagType) -> Result<()> {
+        if tag_type != TagType::Close {
+            let slot = if symbol.index() < self.known.len() { symbol.index() } else { 3 };
+            self.counts[slot] += 1;
+        }
+        Ok(())
+    }
+
+    fn on_cdata(&mut self, _data: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Parses the input in chunks of raw bytes, so that characters split
+/// across chunks are joined by the parser.
+fn feed<H: SaxHandler>(parser: &mut IksParser<H>, data: &[u8], chunk_size: usize) -> Result<(), H::Error> {
+    for chunk in data.chunks(chunk_size.max(1)) {
+        parser.parse_bytes(chunk)?;
+    }
+    parser.finish()
+}
+
+fn dispatch_test(data: &[u8], chunk_size: usize) -> Result<()> {
+    let start = Instant::now();
+    let mut parser = IksParser::new(StringDispatch::default());
+    feed(&mut parser, data, chunk_size)?;
+    println!("Dispatch by name: {:?} ({:?})", start.elapsed(), parser.handler().counts);
+
+    let start = Instant::now();
+    let mut symbols = SymbolTable::new();
+    let known = DISPATCH_NAMES.iter().map(|name| symbols.intern(name)).collect();
+    let mut parser = IksParser::new(SymbolDispatch { known, counts: [0; 4] });
+    parser.set_symbols(symbols);
+    feed(&mut parser, data, chunk_size)?;
+    println!("Dispatch by symbol: {:?} ({:?})", start.elapsed(), parser.handler().counts);
+    Ok(())
+}
+
+fn positions_test(data: &[u8], chunk_size: usize) -> Result<()> {
+    let start = Instant::now();
+    let mut parser = IksParser::new(StringDispatch::default());
+    feed(&mut parser, data, chunk_size)?;
+    println!("SAX with positions: {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let mut parser = ParserBuilder::new().track_positions(false).build(StringDispatch::default());
+    feed(&mut parser, data, chunk_size)?;
+    println!("SAX without positions: {:?}", start.elapsed());
+    Ok(())
+}
+
 fn sax_test(data: &[u8], chunk_size: usize) -> Result<()> {
     let handler = TestHandler::new();
     let mut parser = IksParser::new(handler);
-    
-    let mut pos = 0;
-    while pos < data.len() {
-        let chunk_size = chunk_size.min(data.len() - pos);
-        let chunk = String::from_utf8_lossy(&data[pos..pos + chunk_size]);
-        parser.parse(&chunk)?;
-        pos += chunk_size;
-    }
-    parser.parse("")?;
-    Ok(())
+    feed(&mut parser, data, chunk_size)
 }
 
-fn dom_test(data: &[u8], chunk_size: usize) -> Result<()> {
+/// Builds a DOM from chunks of the input.
+///
+/// Returns the tree, so that freeing it is not part of the measured time,
+/// and the approximate memory it takes.
+fn dom_test(data: &[u8], chunk_size: usize) -> Result<(Rc<RefCell<IksNode>>, usize)> {
     let parser = DomParser::new()?;
     let mut sax_parser = IksParser::new(parser);
-    
-    let mut pos = 0;
-    while pos < data.len() {
-        let chunk_size = chunk_size.min(data.len() - pos);
-        let chunk = String::from_utf8_lossy(&data[pos..pos + chunk_size]);
-        sax_parser.parse(&chunk)?;
-        pos += chunk_size;
-    }
-    sax_parser.parse("")?;
+    feed(&mut sax_parser, data, chunk_size)?;
+    let root = sax_parser.handler().document().ok_or(IksError::BadXml)?;
+    Ok((root, sax_parser.handler().used_bytes()))
+}
+
+/// Counts the nodes of a tree, including its root.
+fn count_nodes(root: &Rc<RefCell<IksNode>>) -> usize {
+    let mut count = 0;
+    let mut stack = vec![root.clone()];
+    while let Some(node) = stack.pop() {
+        count += 1;
+        stack.extend(node.borrow().children());
+    }
+    count
+}
+
+/// Number of element names the find benchmark looks up
+const FIND_NAMES: usize = 8;
+
+/// Number of times the find benchmark repeats its queries
+const FIND_ROUNDS: usize = 10;
+
+/// Gets the first distinct element names of a document, in document order.
+fn element_names(doc: &FlatDocument) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for name in (0..doc.len()).filter_map(|idx| doc.name(idx)) {
+        if names.len() == FIND_NAMES {
+            break;
+        }
+        if !names.iter().any(|known| known == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Counts the elements of each name below the root of the flat arena.
+fn flat_find(doc: &FlatDocument, names: &[String]) -> usize {
+    let Some(root) = doc.root() else { return 0 };
+    names.iter()
+        .map(|name| doc.descendants(root).filter(|&idx| doc.name(idx) == Some(name.as_str())).count())
+        .sum()
+}
+
+/// Counts the elements of each name below the root of the `Rc` tree.
+fn dom_find(root: &Rc<RefCell<IksNode>>, names: &[String]) -> usize {
+    names.iter().map(|name| root.borrow().count_tags(name)).sum()
+}
+
+/// Compares find-heavy queries over the `Rc` tree and the flat arena.
+fn find_test(data: &[u8], chunk_size: usize) -> Result<()> {
+    let (root, _) = dom_test(data, chunk_size)?;
+    let start = Instant::now();
+    let mut parser = IksParser::new(FlatParser::new());
+    feed(&mut parser, data, chunk_size)?;
+    println!("Flat arena parsing: {:?} ({} nodes)", start.elapsed(), parser.handler().document().len());
+
+    let doc = parser.handler().document();
+    let names = element_names(doc);
+    let start = Instant::now();
+    let hits: usize = (0..FIND_ROUNDS).map(|_| dom_find(&root, &names)).sum();
+    println!("Find in Rc tree: {:?} ({} hits)", start.elapsed(), hits);
+
+    let start = Instant::now();
+    let hits: usize = (0..FIND_ROUNDS).map(|_| flat_find(doc, &names)).sum();
+    println!("Find in flat arena: {:?} ({} hits)", start.elapsed(), hits);
     Ok(())
 }
 
 fn serialize_test(data: &[u8]) -> Result<()> {
     let parser = DomParser::new()?;
     let mut sax_parser = IksParser::new(parser);
-    
-    let mut pos = 0;
-    while pos < data.len() {
-        let chunk_size = (data.len() - pos).min(4096);
-        let chunk = String::from_utf8_lossy(&data[pos..pos + chunk_size]);
-        sax_parser.parse(&chunk)?;
-        pos += chunk_size;
-    }
-    sax_parser.parse("")?;
+    feed(&mut sax_parser, data, 4096)?;
+    let root = sax_parser.handler().document().ok_or(IksError::BadXml)?;
+
+    let start = Instant::now();
+    let mut out = Vec::with_capacity(data.len());
+    root.borrow().write_to(&mut out)?;
+    let duration = start.elapsed();
+
+    println!("Serialization: {:?} ({} bytes)", duration, out.len());
+    Ok(())
+}
+
+/// Builds a DOM from a file read block by block.
+fn stream_dom(path: &str, block_size: usize) -> Result<Option<Rc<RefCell<IksNode>>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut parser = IksParser::new(DomParser::new()?);
+    let mut buffer = vec![0; block_size.max(1)];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        parser.parse_bytes(&buffer[..n])?;
+    }
+    parser.finish()?;
+    Ok(parser.handler().document())
+}
+
+fn stream_test(path: &str, block_size: usize) -> Result<()> {
+    let start = Instant::now();
+    let root = stream_dom(path, block_size)?;
+    println!("Streaming DOM parsing: {:?}", start.elapsed());
+
+    if let Some(root) = root {
+        let start = Instant::now();
+        root.borrow().write_to(&mut io::sink())?;
+        println!("Serialization: {:?}", start.elapsed());
+    }
     Ok(())
 }
 
@@ -115,6 +296,11 @@ fn sha1_test(data: &[u8]) -> Result<()> {
 
 fn main() -> Result<()> {
     let args = Args::parse();
+
+    if args.stream {
+        println!("Running streaming performance tests on {}...", args.input);
+        return stream_test(&args.input, args.block_size);
+    }
     
     let mut file = std::fs::File::open(&args.input)?;
     let mut data = Vec::new();
@@ -130,15 +316,80 @@ fn main() -> Result<()> {
     
     // DOM parsing test
     let start = Instant::now();
-    dom_test(&data, args.block_size)?;
+    let (root, used_bytes) = dom_test(&data, args.block_size)?;
     let duration = start.elapsed();
-    println!("DOM parsing: {:?}", duration);
+    println!("DOM parsing: {:?} ({} nodes, ~{} bytes)", duration, count_nodes(&root), used_bytes);
+    drop(root);
     
+    // Element dispatch test
+    dispatch_test(&data, args.block_size)?;
+
+    // Position tracking overhead test
+    positions_test(&data, args.block_size)?;
+
+    // Rc tree versus flat arena queries
+    find_test(&data, args.block_size)?;
+
     // Serialization test
-    let start = Instant::now();
     serialize_test(&data)?;
-    let duration = start.elapsed();
-    println!("Serialization: {:?}", duration);
+
+    // SHA1 hashing test
+    sha1_test(&data)?;
     
     Ok(())
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dom_test_keeps_tree() {
+        let xml = "<log><entry id=\"1\">a</entry><entry id=\"2\"/></log>";
+        let (root, used_bytes) = dom_test(xml.as_bytes(), 7).unwrap();
+        assert_eq!(count_nodes(&root), 4);
+        assert!(used_bytes > 0);
+        assert_eq!(root.borrow().to_string(), xml);
+    }
+
+    #[test]
+    fn test_chunks_split_characters() {
+        let xml = "<log><entry ad=\"çalışma\">günlüğü 🦀</entry></log>";
+        for chunk_size in 1..8 {
+            let (root, _) = dom_test(xml.as_bytes(), chunk_size).unwrap();
+            assert_eq!(root.borrow().to_string(), xml);
+        }
+        assert!(sax_test(xml.as_bytes(), 3).is_ok());
+        assert!(sax_test(&xml.as_bytes()[..xml.len() - 3], 3).is_err());
+    }
+
+    #[test]
+    fn test_find_workloads_agree() {
+        let xml = "<log><entry id=\"1\"><tag>a</tag></entry><entry id=\"2\"><tag/><tag/></entry><end/></log>";
+        let (root, _) = dom_test(xml.as_bytes(), 5).unwrap();
+        let doc = FlatParser::parse_str(xml).unwrap();
+        let names = element_names(&doc);
+        assert_eq!(names, ["log", "entry", "tag", "end"]);
+        assert_eq!(flat_find(&doc, &names), 6);
+        assert_eq!(dom_find(&root, &names), 6);
+    }
+
+    #[test]
+    fn test_stream_dom_large_file() {
+        let mut xml = String::from("<log>");
+        for i in 0..40_000 {
+            xml.push_str(&format!("<entry id=\"{}\">çalışma günlüğü №{} 🦀</entry>", i, i));
+        }
+        xml.push_str("</log>");
+        assert!(xml.len() > 2 * 1024 * 1024);
+
+        let path = std::env::temp_dir().join(format!("iksperf-stream-{}.xml", std::process::id()));
+        std::fs::write(&path, &xml).unwrap();
+        let root = stream_dom(path.to_str().unwrap(), 4093);
+        std::fs::remove_file(&path).unwrap();
+
+        let root = root.unwrap().unwrap();
+        assert_eq!(root.borrow().child_count(), 40_000);
+        assert_eq!(root.borrow().to_string(), xml);
+    }
+}
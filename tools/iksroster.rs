@@ -1,8 +1,8 @@
 use std::fs::File;
-use std::io::{self, Read, Write};
-use std::time::Duration;
-use clap::{Parser, ValueEnum};
-use iksemel::{Parser as IksParser, SaxHandler, IksError, Result, DomParser, IksNode};
+use std::io::Write;
+use clap::Parser;
+use iksemel::{Parser as IksParser, SaxHandler, IksError, Result, IksNode};
+use iksemel::xmpp::Roster;
 use rpassword::prompt_password;
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -51,6 +51,7 @@ struct Args {
     output: Option<String>,
 }
 
+#[allow(dead_code)] // Most fields are only used once `connect` is implemented
 struct Session {
     parser: IksParser<RosterHandler>,
     jid: String,
@@ -95,6 +96,8 @@ impl RosterHandler {
 }
 
 impl SaxHandler for RosterHandler {
+    type Error = IksError;
+
     fn on_tag(&mut self, name: &str, attributes: &[(String, String)], tag_type: iksemel::TagType) -> Result<()> {
         match tag_type {
             iksemel::TagType::Open | iksemel::TagType::Single => {
@@ -102,33 +105,23 @@ impl SaxHandler for RosterHandler {
                 for (attr, value) in attributes {
                     node.add_attribute(attr, value);
                 }
-                let node_rc = Rc::new(RefCell::new(node));
+                let node_rc = node.into_rc();
 
                 if let Some(parent_rc) = self.node_stack.last() {
-                    let mut child = IksNode::new_tag(name);
-                    for (attr, value) in attributes {
-                        child.add_attribute(attr, value);
-                    }
-                    parent_rc.borrow_mut().add_child(child);
-                    if tag_type == iksemel::TagType::Open {
-                        self.node_stack.push(node_rc);
-                    }
+                    IksNode::append_child(parent_rc, node_rc.clone());
                 } else {
                     self.root = Some(node_rc.clone());
-                    if tag_type == iksemel::TagType::Open {
-                        self.node_stack.push(node_rc);
-                    }
+                }
+                if tag_type == iksemel::TagType::Open {
+                    self.node_stack.push(node_rc);
                 }
             },
             iksemel::TagType::Close => {
                 if let Some(current) = self.node_stack.last() {
-                    let current_ref = current.borrow();
-                    let current_name = current_ref.find_attrib("name");
-                    if current_name.map_or(false, |n| n == name) {
-                        drop(current_ref);
+                    if current.borrow().name() == Some(name) {
                         self.node_stack.pop();
                     } else {
-                        return Err(iksemel::IksError::BadXml);
+                        return Err(IksError::BadXml);
                     }
                 }
             },
@@ -139,9 +132,7 @@ impl SaxHandler for RosterHandler {
     fn on_cdata(&mut self, data: &str) -> Result<()> {
         if let Some(parent) = self.node_stack.last() {
             if !data.trim().is_empty() {
-                let mut cdata = IksNode::new(iksemel::IksType::CData);
-                cdata.set_content(data);
-                parent.borrow_mut().add_child(cdata);
+                parent.borrow_mut().add_child(IksNode::text(data));
             }
         }
         Ok(())
@@ -149,6 +140,7 @@ impl SaxHandler for RosterHandler {
 }
 
 fn save_roster(file: &str, roster: &IksNode) -> Result<()> {
+    let roster = Roster::from_node(roster)?;
     let mut file = File::create(file)?;
     file.write_all(roster.to_string().as_bytes())?;
     Ok(())
@@ -159,8 +151,8 @@ fn load_roster(path: &str) -> Result<IksNode> {
     let handler = RosterHandler::new();
     let mut parser = IksParser::new(handler);
     parser.parse(&contents)?;
-    let handler = parser.handler();
-    let root = handler.root.as_ref().unwrap().borrow().clone();
+    let root = parser.handler().root.as_ref().ok_or(IksError::BadXml)?;
+    let root = root.borrow().deep_clone();
     Ok(root)
 }
 
@@ -196,9 +188,9 @@ fn main() -> Result<()> {
         }
     } else if let Some(restore_jid) = args.restore {
         if let Some(file) = args.file {
-            let roster = load_roster(&file)?;
+            let roster = Roster::from_xml(&std::fs::read_to_string(&file)?)?;
             let mut session = Session::new(&restore_jid, &password, true)?;
-            session.roster = Some(roster);
+            session.roster = Some(roster.to_node());
             connect(&mut session)?;
         }
     }
@@ -208,8 +200,31 @@ fn main() -> Result<()> {
     if let Some(output) = args.output {
         std::fs::write(output, node.to_string())?;
     } else {
-        println!("{}", node.to_string());
+        println!("{}", node);
     }
 
     Ok(())
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROSTER: &str = "<query xmlns='jabber:iq:roster'>\
+        <item jid='romeo@example.net' name='Romeo' subscription='both'><group>Friends</group></item>\
+        <item jid='mercutio@example.org' subscription='from'/>\
+        </query>";
+
+    #[test]
+    fn test_load_roster_keeps_children() {
+        let path = std::env::temp_dir().join(format!("iksroster-load-{}.xml", std::process::id()));
+        std::fs::write(&path, ROSTER).unwrap();
+        let root = load_roster(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(root.name(), Some("query"));
+        assert_eq!(root.children().count(), 2);
+        assert_eq!(root.find_deep("group").unwrap().borrow().text_content(), "Friends");
+        assert_eq!(Roster::from_node(&root).unwrap(), Roster::from_xml(ROSTER).unwrap());
+    }
+}